@@ -0,0 +1,72 @@
+/*
+Coverage file - How each byte of the address space was accessed while the program was running in the emulator
+
+File layout:
+    8 bytes         Magic "I8080COV"
+    1 byte          Format version
+    65536 bytes     Access flags of every address, see the flag constants below
+*/
+
+use std::fs::{read, write};
+use std::path::Path;
+
+use crate::errors::DisassemblerError;
+
+const MAGIC: &[u8; 8] = b"I8080COV";
+const VERSION: u8 = 1;
+
+pub const OPCODE: u8 = 0x01;        // Fetched as the first byte of an instruction
+pub const OPERAND: u8 = 0x02;       // Fetched as an operand byte of an instruction
+pub const DATA_READ: u8 = 0x04;     // Read as data
+pub const DATA_WRITE: u8 = 0x08;    // Written as data
+
+pub const ADDR_SPACE: usize = 0x10000;
+
+
+// Length of the instruction in bytes, including the operands
+pub fn instruction_length(opcode: u8) -> usize {
+    match opcode {
+        // LXI, SHLD, LHLD, STA, LDA
+        0x01 | 0x11 | 0x21 | 0x31 | 0x22 | 0x2a | 0x32 | 0x3a => 3,
+
+        // MVI
+        0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e => 2,
+
+        // Jumps and calls, including the alternates
+        0xc2 | 0xc3 | 0xc4 | 0xca | 0xcb | 0xcc | 0xcd |
+        0xd2 | 0xd4 | 0xda | 0xdc | 0xdd |
+        0xe2 | 0xe4 | 0xea | 0xec | 0xed |
+        0xf2 | 0xf4 | 0xfa | 0xfc | 0xfd => 3,
+
+        // Immediate arithmetic and logic, OUT and IN
+        0xc6 | 0xce | 0xd6 | 0xde | 0xe6 | 0xee | 0xf6 | 0xfe | 0xd3 | 0xdb => 2,
+
+        _ => 1,
+    }
+}
+
+pub fn write_coverage(path: &Path, flags: &[u8]) -> Result<(), DisassemblerError> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(MAGIC.len() + 1 + ADDR_SPACE);
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(flags);
+    bytes.resize(MAGIC.len() + 1 + ADDR_SPACE, 0);
+
+    write(path, bytes)?;
+    Ok(())
+}
+
+// Return the access flags of every address in the address space
+pub fn read_coverage(path: &Path) -> Result<Vec<u8>, DisassemblerError> {
+    let bytes = read(path)?;
+
+    if bytes.len() != MAGIC.len() + 1 + ADDR_SPACE || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(DisassemblerError::CoverageInvalid(path.display().to_string()));
+    }
+
+    if bytes[MAGIC.len()] != VERSION {
+        return Err(DisassemblerError::CoverageInvalid(path.display().to_string()));
+    }
+
+    Ok(bytes[MAGIC.len() + 1..].to_vec())
+}
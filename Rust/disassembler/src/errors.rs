@@ -5,6 +5,9 @@ pub enum DisassemblerError {
     FilePathNotGiven,
     FilePathNotFound(String),
     FileCantOpen(String),
+    ArgumentUnknown(String),
+    ArgumentValueMissing(String),
+    CoverageInvalid(String),
 }
 
 fn get_err_msg(err: &DisassemblerError) -> String {
@@ -12,6 +15,9 @@ fn get_err_msg(err: &DisassemblerError) -> String {
         DisassemblerError::FilePathNotGiven => format!("File path was not given!"),
         DisassemblerError::FilePathNotFound(s) => format!("File path '{s}' was not valid!"),
         DisassemblerError::FileCantOpen(s) => format!("Couldn't open file '{s}'!"),
+        DisassemblerError::ArgumentUnknown(s) => format!("Unknown argument '{s}'!"),
+        DisassemblerError::ArgumentValueMissing(s) => format!("Argument '{s}' requires a value!"),
+        DisassemblerError::CoverageInvalid(s) => format!("File '{s}' is not a valid coverage file!"),
     }
 }
 
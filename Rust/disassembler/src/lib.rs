@@ -0,0 +1,9 @@
+/*
+Intel 8080 disassembler written in rust
+*/
+
+pub mod checksum;
+pub mod coverage;
+pub mod disassembler;
+pub mod errors;
+pub mod romdb;
@@ -2,13 +2,13 @@
 Intel 8080 disassembler written in rust
 */
 
-mod errors;
-mod disassembler;
-
 use std::env;
 use std::path::PathBuf;
 
-use errors::DisassemblerError;
+use disassembler::coverage::read_coverage;
+use disassembler::disassembler::disassemble_bytes;
+use disassembler::errors::DisassemblerError;
+use disassembler::romdb;
 
 
 fn get_input_file() -> Result<PathBuf, DisassemblerError> {
@@ -33,13 +33,44 @@ fn get_input_file() -> Result<PathBuf, DisassemblerError> {
     Ok(file_path)
 }
 
+// Optional coverage file recorded by the emulator, given with "--coverage <file>" after the input file
+fn get_coverage_file() -> Result<Option<PathBuf>, DisassemblerError> {
+    let mut args = env::args().skip(2);
+    let mut coverage = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--coverage" => match args.next() {
+                Some(path) => coverage = Some(PathBuf::from(path)),
+                None => return Err(DisassemblerError::ArgumentValueMissing(arg)),
+            },
+            _ => return Err(DisassemblerError::ArgumentUnknown(arg)),
+        }
+    }
+
+    Ok(coverage)
+}
+
 
 fn main() -> Result<(), DisassemblerError>{
 
     println!("\n### Initializing disassembler! ###\n");
 
     let path = get_input_file()?;
-    disassembler::disassemble(path)?;
+    let rom = romdb::load_rom(&path)?;
+
+    println!("{}", rom.describe());
+    for warning in rom.warnings.iter() {
+        println!("Warning: {warning}");
+    }
+    println!();
+
+    let coverage = match get_coverage_file()? {
+        Some(coverage_path) => Some(read_coverage(&coverage_path)?),
+        None => None,
+    };
+
+    disassemble_bytes(&rom.image, coverage.as_deref());
 
     println!("### Disassembler exiting! ###");
 
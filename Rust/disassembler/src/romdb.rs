@@ -0,0 +1,238 @@
+/*
+ROM database - Identify known 8080 ROM sets by their checksums
+
+A ROM can be given either as a single image with all parts combined in load order, or as any one of the part
+files, in which case the other parts are searched for in the same directory by their checksums. The parts are
+placed at their load addresses, so that images with mis-ordered parts and part files with wrong names still work,
+and warnings are given for bad dumps, missing and mis-ordered parts.
+
+Parts are identified by their CRC32, the SHA-1 is checked as well to detect colliding CRC32s.
+*/
+
+use std::fs::{read, read_dir};
+use std::path::Path;
+
+use crate::checksum::{crc32, sha1, to_hex};
+use crate::errors::DisassemblerError;
+
+
+// One ROM chip of a set
+pub struct RomPart {
+    pub name: &'static str,
+    pub size: usize,
+    pub crc32: u32,
+    pub sha1: &'static str,
+
+    // Address of the first byte and the distance between consecutive bytes, e.g. 2 for chips holding only the even
+    // or the odd bytes of a 16-bit bus
+    pub load_addr: usize,
+    pub interleave: usize,
+}
+
+pub struct RomSet {
+    pub name: &'static str,
+    pub description: &'static str,
+
+    // Hardware the set runs on, e.g. "invaders" for the Midway 8080 board
+    pub machine: &'static str,
+    pub parts: &'static [RomPart],
+}
+
+pub const ROM_SETS: &[RomSet] = &[
+    RomSet {
+        name: "invaders",
+        description: "Space Invaders (Midway, 1978)",
+        machine: "invaders",
+        parts: &[
+            RomPart {
+                name: "invaders.h", size: 0x800, crc32: 0x734f5ad8, sha1: "ff6200af4c9110d8181249cbcef1a8a40fa40b7f",
+                load_addr: 0x0000, interleave: 1,
+            },
+            RomPart {
+                name: "invaders.g", size: 0x800, crc32: 0x6bfaca4a, sha1: "16f48649b531bdef8c2d1446c429b5f414524350",
+                load_addr: 0x0800, interleave: 1,
+            },
+            RomPart {
+                name: "invaders.f", size: 0x800, crc32: 0x0ccead96, sha1: "537aef03468f63c5b9e11dd61e253f7ae17d9743",
+                load_addr: 0x1000, interleave: 1,
+            },
+            RomPart {
+                name: "invaders.e", size: 0x800, crc32: 0x14e538b0, sha1: "1d6ca0c99f9df71e2990b610deb9d7da0125e2d8",
+                load_addr: 0x1800, interleave: 1,
+            },
+        ],
+    },
+];
+
+
+impl RomPart {
+    fn matches(&self, data: &[u8]) -> bool {
+        data.len() == self.size && crc32(data) == self.crc32
+    }
+
+    // Address range of the part in the image
+    fn range(&self) -> String {
+        let end = self.load_addr + (self.size - 1) * self.interleave;
+        format!("{:#06X}-{:#06X}", self.load_addr, end)
+    }
+
+    // Bytes of the part from a combined image
+    fn extract(&self, image: &[u8]) -> Vec<u8> {
+        (0..self.size).map(|i| image[self.load_addr + i * self.interleave]).collect()
+    }
+
+    fn place(&self, image: &mut [u8], data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            image[self.load_addr + i * self.interleave] = *byte;
+        }
+    }
+}
+
+impl RomSet {
+    // Size of the combined image
+    pub fn image_size(&self) -> usize {
+        self.parts.iter().map(|part| part.load_addr + (part.size - 1) * part.interleave + 1).max().unwrap_or(0)
+    }
+
+    fn find_part(&self, data: &[u8]) -> Option<&'static RomPart> {
+        self.parts.iter().find(|part| part.matches(data))
+    }
+}
+
+// Return the part of any known set that has the contents
+fn find_known_part(data: &[u8]) -> Option<(&'static RomSet, &'static RomPart)> {
+    ROM_SETS.iter().find_map(|set| set.find_part(data).map(|part| (set, part)))
+}
+
+
+// ROM image ready for loading at address 0, with the set it was identified as
+pub struct RomImage {
+    pub image: Vec<u8>,
+    pub set: Option<&'static RomSet>,
+    pub warnings: Vec<String>,
+}
+
+impl RomImage {
+    // Human readable identification, e.g. for printing when the program starts
+    pub fn describe(&self) -> String {
+        match self.set {
+            Some(set) => format!("ROM identified as {} [{}], machine '{}'", set.description, set.name, set.machine),
+            None => format!("Unknown ROM, CRC32 {:08x}", crc32(&self.image)),
+        }
+    }
+
+    // Check the SHA-1 of the parts whose CRC32 matched
+    fn check_sha1(&mut self) {
+        let Some(set) = self.set else {
+            return;
+        };
+
+        for part in set.parts {
+            let data = part.extract(&self.image);
+
+            if crc32(&data) == part.crc32 && to_hex(&sha1(&data)) != part.sha1 {
+                self.warnings.push(format!("{} has the right CRC32 but the wrong SHA-1, bad dump?", part.name));
+            }
+        }
+    }
+}
+
+// Load a ROM file, identify it and assemble the whole set if the file is a part of a known set
+pub fn load_rom(path: &Path) -> Result<RomImage, DisassemblerError> {
+    let data = read(path)?;
+    let file_name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+
+    let mut rom = match identify_combined(&data) {
+        Some(rom) => rom,
+        None => {
+            let by_contents = find_known_part(&data).map(|(set, _)| set);
+            let by_name = ROM_SETS.iter().find(|set| set.parts.iter().any(|part| part.name == file_name));
+
+            match by_contents.or(by_name) {
+                Some(set) => assemble_set(set, path)?,
+                None => RomImage { image: data, set: None, warnings: Vec::new() },
+            }
+        },
+    };
+
+    rom.check_sha1();
+    Ok(rom)
+}
+
+// Identify an image that has all the parts of a set combined, reorder the parts if needed
+fn identify_combined(data: &[u8]) -> Option<RomImage> {
+    let set = ROM_SETS.iter().find(|set| {
+        set.image_size() == data.len() && set.parts.iter().any(|part| find_known_part(&part.extract(data)).is_some())
+    })?;
+
+    let mut image = data.to_vec();
+    let mut warnings = Vec::new();
+
+    for slot in set.parts {
+        let contents = slot.extract(data);
+
+        match set.find_part(&contents) {
+            Some(part) if part.name == slot.name => {},
+            Some(part) => {
+                warnings.push(format!(
+                    "Parts are mis-ordered, {} holds {} instead of {}", slot.range(), part.name, slot.name
+                ));
+                part.place(&mut image, &contents);
+            },
+            None => warnings.push(format!(
+                "{} does not match {} (CRC32 {:08x}, expected {:08x}), bad dump?",
+                slot.range(), slot.name, crc32(&contents), slot.crc32
+            )),
+        }
+    }
+
+    Some(RomImage { image, set: Some(set), warnings })
+}
+
+// Build the image of a set from the part files in the directory of the given part
+fn assemble_set(set: &'static RomSet, path: &Path) -> Result<RomImage, DisassemblerError> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // Only files of the size of a part need to be looked at
+        let is_candidate = entry.metadata()
+            .map(|metadata| metadata.is_file() && set.parts.iter().any(|part| part.size as u64 == metadata.len()))
+            .unwrap_or(false);
+
+        if is_candidate {
+            files.push((name, read(entry.path())?));
+        }
+    }
+
+    let mut image = vec![0x00; set.image_size()];
+    let mut warnings = Vec::new();
+
+    for part in set.parts {
+        let by_contents = files.iter().find(|(_, data)| part.matches(data));
+        let by_name = files.iter().find(|(name, _)| name == part.name);
+
+        match (by_contents, by_name) {
+            (Some((name, data)), _) => {
+                if name != part.name {
+                    warnings.push(format!("{} was found as '{name}', the part files are mis-named", part.name));
+                }
+
+                part.place(&mut image, data);
+            },
+            (None, Some((name, data))) => {
+                warnings.push(format!(
+                    "'{name}' does not match {} (CRC32 {:08x}, expected {:08x}), bad dump?",
+                    part.name, crc32(data), part.crc32
+                ));
+                part.place(&mut image, data);
+            },
+            (None, None) => warnings.push(format!("{} is missing, {} is left empty", part.name, part.range())),
+        }
+    }
+
+    Ok(RomImage { image, set: Some(set), warnings })
+}
@@ -0,0 +1,145 @@
+/*
+Altair 8800 (MITS, 1975)
+
+    CPU         Intel 8080 at 2 MHz
+    RAM         1KB to 64KB from 0x0000, the rest of the address space is empty
+    IN/OUT 0-1  88-SIO serial board, or
+    IN/OUT 10-13
+                88-2SIO serial board, see serial.rs
+    IN 0xFF     Front panel sense switches, i.e. the upper 8 address switches
+
+Programs such as Altair BASIC are loaded from binary paper tape images into RAM and started at their load address.
+Images loaded above the RAM, e.g. monitor ROMs, are mapped as ROM.
+*/
+
+use crate::devices::InputLatch;
+use crate::emulator::memory_map::MemoryKind;
+use crate::emulator::Intel8080;
+use crate::errors::EmulatorError;
+use crate::machine::{Console, Machine};
+use crate::serial::{SerialCard, SerialKind};
+
+pub const CLOCK_HZ: u64 = 2_000_000;
+
+// Cycles run per frame, the Altair has no video but the front ends work in 60 Hz frames
+pub const FRAME_CYCLES: u64 = CLOCK_HZ / 60;
+
+pub const SENSE_SWITCH_PORT: u8 = 0xFF;
+pub const MAX_RAM_KB: usize = 64;
+
+
+pub struct AltairConfig {
+    pub ram_kb: usize,
+    pub serial: SerialKind,
+    pub sense_switches: u8,
+
+    // Baud rate of the terminal line, None transfers the bytes as fast as the program polls
+    pub baud: Option<u32>,
+}
+
+impl Default for AltairConfig {
+    fn default() -> Self {
+        AltairConfig {
+            ram_kb: MAX_RAM_KB,
+            serial: SerialKind::TwoSio,
+            sense_switches: 0x00,
+            baud: None,
+        }
+    }
+}
+
+
+pub struct Altair {
+    cpu: Intel8080,
+    ram_size: usize,
+    waiting: bool,
+}
+
+impl Altair {
+    // Fit a CPU with an empty memory with the RAM and the boards of the configuration
+    pub fn new(mut cpu: Intel8080, config: &AltairConfig) -> Result<Self, EmulatorError> {
+        if config.ram_kb == 0 || config.ram_kb > MAX_RAM_KB {
+            return Err(EmulatorError::RamSizeInvalid(config.ram_kb, MAX_RAM_KB));
+        }
+
+        let ram_size = config.ram_kb * 1024;
+
+        cpu.load_rom(&[]);
+        cpu.map_memory(ram_size as u16, 0x10000 - ram_size, MemoryKind::Empty);
+
+        let mut serial = SerialCard::new(config.serial);
+        serial.set_baud(config.baud, CLOCK_HZ);
+
+        cpu.attach_device(Box::new(serial));
+        cpu.attach_device(Box::new(InputLatch::new("sense_switches", SENSE_SWITCH_PORT, config.sense_switches)));
+
+        Ok(Altair { cpu, ram_size, waiting: false })
+    }
+
+    // Load a binary image to the address, the part above the RAM becomes ROM
+    pub fn load(&mut self, image: &[u8], addr: u16) -> Result<(), EmulatorError> {
+        if addr as usize + image.len() > 0x10000 {
+            return Err(EmulatorError::LoadAddressInvalid(image.len(), addr));
+        }
+
+        for (i, byte) in image.iter().enumerate() {
+            self.cpu.write_byte(addr + i as u16, *byte);
+        }
+
+        let rom_start = (addr as usize).max(self.ram_size);
+        let rom_end = addr as usize + image.len();
+
+        if rom_start < rom_end {
+            self.cpu.map_memory(rom_start as u16, rom_end - rom_start, MemoryKind::Rom);
+        }
+
+        Ok(())
+    }
+
+    // Start the program at the address, like examining it on the front panel and pressing RUN
+    pub fn start(&mut self, addr: u16) {
+        self.cpu.set_pc(addr);
+    }
+
+    pub fn serial(&mut self) -> &mut SerialCard {
+        self.cpu.device_mut::<SerialCard>().expect("Altair serial board missing")
+    }
+
+    pub fn set_sense_switches(&mut self, val: u8) {
+        self.cpu.named_device_mut::<InputLatch>("sense_switches").expect("Altair sense switches missing").set(val);
+    }
+}
+
+impl Machine for Altair {
+    fn cpu(&self) -> &Intel8080 {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut Intel8080 {
+        &mut self.cpu
+    }
+
+    fn run_frame(&mut self) {
+        self.cpu.apply_freezes();
+        self.serial().take_idle();
+
+        let target = self.cpu.get_cycles() + FRAME_CYCLES;
+        self.cpu.run_until(target);
+
+        self.waiting = self.serial().take_idle();
+    }
+}
+
+impl Console for Altair {
+    fn push_input(&mut self, bytes: &[u8]) {
+        self.serial().receive(bytes);
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        self.serial().take_sent()
+    }
+
+    fn is_waiting_for_input(&self) -> bool {
+        self.waiting
+    }
+}
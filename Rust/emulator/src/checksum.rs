@@ -0,0 +1,5 @@
+/*
+Checksums used by the file formats, shared with the disassembler for identifying ROMs
+*/
+
+pub use disassembler::checksum::{adler32, crc32, crc32_update, sha1, to_hex};
@@ -0,0 +1,411 @@
+/*
+Command line options of the emulator
+
+Usage: emulator <rom file> [options]
+    --invaders              Run the ROM on the Space Invaders hardware, known Space Invaders ROMs select it anyway
+    --machine <name>        Run the ROM on a board of the Space Invaders family: invaders, invadpt2, lrescue,
+                            ballbomb, gunfight or seawolf
+    --config                Assemble the machine from the file as a TOML machine configuration, see config.rs. Files
+                            ending with .toml select it anyway
+    --prom <file>           Colour PROM of the invadpt2, lrescue and ballbomb boards, both colour maps in one file
+    --cpu <model>           CPU model, 8080, 8085 or z80 (default: 8080, or the cpu of the configuration)
+    --undocumented <policy> What the 8080 does with its undocumented opcodes 0x08, 0x10, 0x18, 0x20, 0x28, 0x30,
+                            0x38, 0xCB, 0xD9, 0xDD, 0xED and 0xFD: execute them, execute them and warn, or stop with
+                            an error. execute, warn or stop (default: execute)
+    --cpm                   Run the file as a CP/M 2.2 .COM program, files ending with .com select it anyway
+    --cpm-dir <dir>         Host directory that the CP/M drives are mapped onto (default: directory of the program)
+    --cpm-args <tail>       Command tail passed to the CP/M program, e.g. "FOO.TXT BAR.TXT"
+    --cpm-disk              Boot CP/M 2.2 from the file as the 8" disk image of drive A:, files ending with .dsk select
+                            it anyway
+    --disk <file>           Add a disk image as the next drive B:, C: or D:
+    --altair                Run the file on an Altair 8800 with a serial terminal on the console
+    --ram <KB>              Altair RAM size from 1 to 64KB (default: 64)
+    --serial <board>        Altair serial board for the terminal, sio or 2sio (default: 2sio)
+    --sense <n>             Altair front panel sense switches, read from IN 0xFF
+    --baud <rate>           Pace the Altair serial board at the baud rate, e.g. 9600 (default: as fast as possible)
+    --serial-tcp <port>     Serve the console of CP/M or the Altair on the TCP port of 127.0.0.1 instead of stdio
+    --serial-pty            Serve the console of CP/M or the Altair on a new pseudo-terminal instead of stdio
+    --load-addr <addr>      Load the Altair image to the address, images above the RAM become ROM (default: 0)
+    --start <addr>          Start the Altair program at the address (default: the load address)
+    --patch <file>          Apply an IPS or BPS patch to the ROM before starting, can be given multiple times. The
+                            patch options don't apply to disk images and configurations
+    --poke <addr>:<bytes>   Patch the hex bytes into the ROM at the hex address, e.g. 1A2B:C90000
+    --patch-crc <crc32>     Check the CRC32 of the unpatched ROM, e.g. 0xb64ca815
+    --frames <n>            Stop after running n frames (1/60 s of emulated time each)
+    --clock <hz|part>       Run the CPU in real time at the frequency in Hz or of the part: 8080 (2 MHz), 8080a-2
+                            (2.5 MHz) or 8080a-1 (3.125 MHz). Default: the clock of the machine, 2 MHz unless the
+                            configuration sets another
+    --throttle              Run in real time without a front end too, the terminal and the interactive console always do
+    --turbo                 Start in turbo mode, running as fast as possible. Tab in the terminal and ^] on the console
+                            toggle it
+    --show-speed            Show the measured speed of the CPU once per second
+    --terminal              Play Space Invaders in the terminal, drawn with braille characters. Also the other
+                            boards except gunfight and seawolf, whose controls are not mapped
+    --half-blocks           Draw the terminal screen with half blocks instead, needs a 224x128 terminal
+    --nvram <file>          Keep the high scores or battery backed RAM in the file, restored at the start and saved
+                            when exiting
+    --load-state <file>     Restore the machine state from a save state before starting
+    --save-state <file>     Write the machine state into a save state when exiting
+    --debug                 Start the interactive debugger instead of running the program
+    --provenance-report <file>
+                            Track the last writer of every memory address and write a report when exiting
+    --profile               Profile the execution and print a report of the hot spots when exiting
+    --profile-folded <file> Profile the execution and write the call stacks in flamegraph folded format when exiting
+    --coverage <file>       Record how each memory byte was accessed and write a coverage file for the disassembler
+    --screenshot <file>     Render the Space Invaders video RAM into a .png or .ppm image when exiting
+    --overlay               Colour the screenshot with the cellophane overlay of the cabinet
+    --sound-log <file>      Write the Space Invaders sound events with their cycle timestamps when exiting
+    --sound-wav <file>      Render the Space Invaders sound events into a WAV file when exiting
+    --samples <dir>         Use the sound samples 0.wav - 9.wav from the directory instead of synthesized tones
+    --dips <n>              Set the Space Invaders DIP switches (IN 2 bits 0, 1, 3 and 7)
+    --record-movie <file>   Record the inputs of every frame into a movie file, starting from power on. Can't be
+                            combined with --load-state or --freeze
+    --play-movie <file>     Replay the inputs from a movie file, stops at the end of the movie
+    --verify                Check that the memory after the replay matches the recording
+    --freeze <addr>=<value>[:type]
+                            Force the address to the value every frame, the type is u8 (default), u16, bcd or bcd16.
+                            Can be given multiple times
+*/
+
+use std::env;
+use std::path::PathBuf;
+
+use emulator::altair;
+use emulator::emulator::cheats::ValueType;
+use emulator::emulator::{CpuModel, UndocumentedPolicy};
+use emulator::errors::EmulatorError;
+use emulator::midway::{self, Board};
+use emulator::patch::Patch;
+use emulator::serial::SerialKind;
+use emulator::throttle;
+
+use crate::link::LinkKind;
+use crate::terminal::RenderMode;
+
+
+pub struct Options {
+    pub rom: PathBuf,
+    pub invaders: bool,
+    pub board: &'static Board,
+    pub prom: Option<PathBuf>,
+    pub config: bool,
+    pub cpu: Option<CpuModel>,
+    pub undocumented: UndocumentedPolicy,
+    pub cpm: bool,
+    pub cpm_dir: Option<PathBuf>,
+    pub cpm_args: String,
+    pub cpm_disk: bool,
+    pub disks: Vec<PathBuf>,
+    pub altair: bool,
+    pub ram_kb: usize,
+    pub serial: SerialKind,
+    pub sense: u8,
+    pub baud: Option<u32>,
+    pub link: LinkKind,
+    pub load_addr: u16,
+    pub start_addr: Option<u16>,
+    pub patches: Vec<Patch>,
+    pub patch_crc: Option<u32>,
+    pub frames: Option<u64>,
+    pub clock: Option<u64>,
+    pub throttle: bool,
+    pub turbo: bool,
+    pub show_speed: bool,
+    pub terminal: Option<RenderMode>,
+    pub nvram: Option<PathBuf>,
+    pub load_state: Option<PathBuf>,
+    pub save_state: Option<PathBuf>,
+    pub debug: bool,
+    pub provenance_report: Option<PathBuf>,
+    pub profile: bool,
+    pub profile_folded: Option<PathBuf>,
+    pub coverage: Option<PathBuf>,
+    pub screenshot: Option<PathBuf>,
+    pub overlay: bool,
+    pub sound_log: Option<PathBuf>,
+    pub sound_wav: Option<PathBuf>,
+    pub samples: Option<PathBuf>,
+    pub dips: Option<u8>,
+    pub record_movie: Option<PathBuf>,
+    pub play_movie: Option<PathBuf>,
+    pub verify: bool,
+    pub freezes: Vec<(u16, ValueType, u32)>,
+}
+
+// Return the value following an option, e.g. the file name in "--save-state file.sav"
+fn get_value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, EmulatorError> {
+    match args.next() {
+        Some(val) => Ok(val),
+        None => Err(EmulatorError::ArgumentValueMissing(option.to_string())),
+    }
+}
+
+// Parse a decimal or 0x prefixed hexadecimal number
+fn parse_number(val: &str) -> Option<u64> {
+    match val.strip_prefix("0x").or_else(|| val.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => val.parse::<u64>().ok(),
+    }
+}
+
+// Return the value following an option as a decimal or 0x prefixed hexadecimal number
+fn get_number(args: &mut impl Iterator<Item = String>, option: &str) -> Result<u64, EmulatorError> {
+    let val = get_value(args, option)?;
+    parse_number(&val).ok_or(EmulatorError::ArgumentValueInvalid(option.to_string(), val))
+}
+
+// Return the value following an option as a number that is at most the max, e.g. u8::MAX for a byte
+fn get_number_max(args: &mut impl Iterator<Item = String>, option: &str, max: u64) -> Result<u64, EmulatorError> {
+    let val = get_value(args, option)?;
+
+    match parse_number(&val) {
+        Some(number) if number <= max => Ok(number),
+        _ => Err(EmulatorError::ArgumentValueInvalid(option.to_string(), val)),
+    }
+}
+
+// Return the value following an option as a 16-bit address
+fn get_address(args: &mut impl Iterator<Item = String>, option: &str) -> Result<u16, EmulatorError> {
+    let val = get_value(args, option)?;
+
+    match parse_number(&val) {
+        Some(addr) if addr <= 0xFFFF => Ok(addr as u16),
+        _ => Err(EmulatorError::ArgumentValueInvalid(option.to_string(), val)),
+    }
+}
+
+// Return the value following an option as a freeze in the form <addr>=<value>[:type]
+fn get_freeze(args: &mut impl Iterator<Item = String>, option: &str) -> Result<(u16, ValueType, u32), EmulatorError> {
+    let val = get_value(args, option)?;
+
+    let (addr, rest) = val.split_once('=').unwrap_or((&val, ""));
+    let (number, value_type) = match rest.split_once(':') {
+        Some((number, value_type)) => (number, ValueType::parse(value_type)),
+        None => (rest, Some(ValueType::U8)),
+    };
+
+    match (parse_number(addr), parse_number(number), value_type) {
+        (Some(addr), Some(number), Some(value_type)) if addr <= 0xFFFF => Ok((addr as u16, value_type, number as u32)),
+        _ => Err(EmulatorError::ArgumentValueInvalid(option.to_string(), val)),
+    }
+}
+
+// Return the value following an option as a path to an existing file
+fn get_existing_file(args: &mut impl Iterator<Item = String>, option: &str) -> Result<PathBuf, EmulatorError> {
+    let val = get_value(args, option)?;
+    let file_path = PathBuf::from(&val);
+
+    if !file_path.exists() {
+        return Err(EmulatorError::FilePathNotFound(val));
+    }
+
+    Ok(file_path)
+}
+
+pub fn get_options() -> Result<Options, EmulatorError> {
+    // Skip first arg that has the executable path
+    let mut args = env::args().skip(1);
+
+    let rom = match args.next() {
+        Some(i) => {
+            i
+        },
+
+        None => {
+            return Err(EmulatorError::FilePathNotGiven);
+        },
+    };
+
+    let rom_path = PathBuf::from(&rom);
+
+    if !rom_path.exists() {
+        return Err(EmulatorError::FilePathNotFound(rom));
+    }
+
+    let mut options = Options {
+        rom: rom_path,
+        invaders: false,
+        board: &midway::INVADERS,
+        prom: None,
+        config: false,
+        cpu: None,
+        undocumented: UndocumentedPolicy::Execute,
+        cpm: false,
+        cpm_dir: None,
+        cpm_args: String::new(),
+        cpm_disk: false,
+        disks: Vec::new(),
+        altair: false,
+        ram_kb: altair::MAX_RAM_KB,
+        serial: SerialKind::TwoSio,
+        sense: 0x00,
+        baud: None,
+        link: LinkKind::Stdio,
+        load_addr: 0x0000,
+        start_addr: None,
+        patches: Vec::new(),
+        patch_crc: None,
+        frames: None,
+        clock: None,
+        throttle: false,
+        turbo: false,
+        show_speed: false,
+        terminal: None,
+        nvram: None,
+        load_state: None,
+        save_state: None,
+        debug: false,
+        provenance_report: None,
+        profile: false,
+        profile_folded: None,
+        coverage: None,
+        screenshot: None,
+        overlay: false,
+        sound_log: None,
+        sound_wav: None,
+        samples: None,
+        dips: None,
+        record_movie: None,
+        play_movie: None,
+        verify: false,
+        freezes: Vec::new(),
+    };
+
+    // First of the patch options, which only apply to images that are loaded as a whole
+    let mut patch_arg: Option<String> = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--invaders" => options.invaders = true,
+            "--machine" => {
+                let val = get_value(&mut args, &arg)?;
+                options.board = midway::find(&val).ok_or(EmulatorError::ArgumentValueInvalid(arg, val))?;
+                options.invaders = true;
+            },
+            "--config" => options.config = true,
+            "--prom" => options.prom = Some(get_existing_file(&mut args, &arg)?),
+            "--cpu" => {
+                let val = get_value(&mut args, &arg)?;
+                options.cpu = Some(CpuModel::parse(&val).ok_or(EmulatorError::ArgumentValueInvalid(arg, val))?);
+            },
+            "--undocumented" => {
+                let val = get_value(&mut args, &arg)?;
+                let policy = UndocumentedPolicy::parse(&val);
+                options.undocumented = policy.ok_or(EmulatorError::ArgumentValueInvalid(arg, val))?;
+            },
+            "--cpm" => options.cpm = true,
+            "--cpm-dir" => options.cpm_dir = Some(get_existing_file(&mut args, &arg)?),
+            "--cpm-args" => options.cpm_args = get_value(&mut args, &arg)?,
+            "--cpm-disk" => options.cpm_disk = true,
+            "--disk" => options.disks.push(get_existing_file(&mut args, &arg)?),
+            "--altair" => options.altair = true,
+            "--ram" => options.ram_kb = get_number(&mut args, &arg)? as usize,
+            "--serial" => {
+                let val = get_value(&mut args, &arg)?;
+                options.serial = SerialKind::parse(&val).ok_or(EmulatorError::ArgumentValueInvalid(arg, val))?;
+            },
+            "--sense" => options.sense = get_number_max(&mut args, &arg, u8::MAX as u64)? as u8,
+            "--baud" => {
+                let val = get_value(&mut args, &arg)?;
+                let baud = val.parse::<u32>().ok().filter(|baud| *baud > 0);
+                options.baud = Some(baud.ok_or(EmulatorError::ArgumentValueInvalid(arg, val))?);
+            },
+            "--serial-tcp" => options.link = LinkKind::Tcp(get_number_max(&mut args, &arg, u16::MAX as u64)? as u16),
+            "--serial-pty" => options.link = LinkKind::Pty,
+            "--load-addr" => options.load_addr = get_address(&mut args, &arg)?,
+            "--start" => options.start_addr = Some(get_address(&mut args, &arg)?),
+            "--patch" => {
+                options.patches.push(Patch::load(&get_existing_file(&mut args, &arg)?)?);
+                patch_arg.get_or_insert(arg);
+            },
+            "--poke" => {
+                let val = get_value(&mut args, &arg)?;
+                let patch = Patch::parse_inline(&val).ok_or(EmulatorError::ArgumentValueInvalid(arg.clone(), val))?;
+                options.patches.push(patch);
+                patch_arg.get_or_insert(arg);
+            },
+            "--patch-crc" => {
+                options.patch_crc = Some(get_number_max(&mut args, &arg, u32::MAX as u64)? as u32);
+                patch_arg.get_or_insert(arg);
+            },
+            "--frames" => options.frames = Some(get_number(&mut args, &arg)?),
+            "--clock" => {
+                let val = get_value(&mut args, &arg)?;
+                options.clock = Some(throttle::parse_clock(&val).ok_or(EmulatorError::ArgumentValueInvalid(arg, val))?);
+                options.throttle = true;
+            },
+            "--throttle" => options.throttle = true,
+            "--turbo" => options.turbo = true,
+            "--show-speed" => options.show_speed = true,
+            "--terminal" => options.terminal = options.terminal.or(Some(RenderMode::Braille)),
+            "--half-blocks" => options.terminal = Some(RenderMode::HalfBlock),
+            "--nvram" => options.nvram = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+            "--load-state" => options.load_state = Some(get_existing_file(&mut args, &arg)?),
+            "--save-state" => options.save_state = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+            "--debug" => options.debug = true,
+            "--provenance-report" => options.provenance_report = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+            "--profile" => options.profile = true,
+            "--profile-folded" => options.profile_folded = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+            "--coverage" => options.coverage = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+            "--screenshot" => options.screenshot = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+            "--overlay" => options.overlay = true,
+            "--sound-log" => options.sound_log = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+            "--sound-wav" => options.sound_wav = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+            "--samples" => options.samples = Some(get_existing_file(&mut args, &arg)?),
+            "--dips" => options.dips = Some(get_number_max(&mut args, &arg, u8::MAX as u64)? as u8),
+            "--record-movie" => options.record_movie = Some(PathBuf::from(get_value(&mut args, &arg)?)),
+            "--play-movie" => options.play_movie = Some(get_existing_file(&mut args, &arg)?),
+            "--verify" => options.verify = true,
+            "--freeze" => options.freezes.push(get_freeze(&mut args, &arg)?),
+            _ => return Err(EmulatorError::ArgumentUnknown(arg)),
+        }
+    }
+
+    match options.rom.extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("com") => options.cpm = true,
+        Some(ext) if ext.eq_ignore_ascii_case("dsk") => options.cpm_disk = true,
+        Some(ext) if ext.eq_ignore_ascii_case("toml") => options.config = true,
+        _ => {},
+    }
+
+    // The terminal front end is only for the boards of the Space Invaders family whose controls are known
+    if options.terminal.is_some() {
+        options.invaders = true;
+
+        if options.board.controls.is_empty() {
+            let board = format!("--machine {}", options.board.name);
+            return Err(EmulatorError::ArgumentsConflict(String::from("--terminal"), board));
+        }
+    }
+
+    // The disk images are opened by the disk controller and the ROMs of a configuration by the machine, which don't
+    // patch them
+    if let Some(patch_arg) = patch_arg {
+        if options.cpm_disk {
+            return Err(EmulatorError::ArgumentsConflict(patch_arg, String::from("--cpm-disk")));
+        }
+
+        if options.config {
+            return Err(EmulatorError::ArgumentsConflict(patch_arg, String::from("--config")));
+        }
+    }
+
+    // Movies always start from power on and replay only the inputs, so that they replay the same way
+    let movie_arg = match (&options.record_movie, &options.play_movie) {
+        (Some(_), _) => Some("--record-movie"),
+        (_, Some(_)) => Some("--play-movie"),
+        _ => None,
+    };
+
+    if let Some(movie_arg) = movie_arg {
+        if options.load_state.is_some() {
+            return Err(EmulatorError::ArgumentsConflict(String::from("--load-state"), String::from(movie_arg)));
+        }
+
+        if !options.freezes.is_empty() {
+            return Err(EmulatorError::ArgumentsConflict(String::from("--freeze"), String::from(movie_arg)));
+        }
+    }
+
+    Ok(options)
+}
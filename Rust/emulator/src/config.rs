@@ -0,0 +1,592 @@
+/*
+Machines assembled from a TOML configuration file instead of being written in Rust
+
+    name = "Space Invaders"
+    cpu = "8080"                # CPU model, 8080, 8085 or z80 (default: 8080)
+    clock_hz = 2_000_000        # CPU clock (default: 2 MHz)
+    frame_hz = 60               # Frames per second that the front ends run (default: 60)
+
+    [[rom]]                     # ROM image loaded to the address, the path is relative to the configuration file
+    file = "invaders.h"
+    address = 0x0000
+    writable = false            # Load the image into RAM instead (default: false)
+
+    [[ram]]                     # RAM area
+    start = 0x2000
+    size = 0x2000
+
+    [[mirror]]                  # Addresses that show the memory of the target area, e.g. an undecoded address line
+    start = 0x4000
+    size = 0x2000
+    target = 0x2000
+
+    [[read_only]]               # Area that the program can't write, e.g. RAM that is only written by the loader
+    start = 0x1800
+    size = 0x0800
+
+    [[device]]                  # Input latch, e.g. buttons or DIP switches
+    type = "latch"
+    name = "in1"
+    port = 1
+    value = 0x08
+
+    [[device]]                  # Shift register, see devices.rs
+    type = "shift_register"
+    offset_port = 2
+    data_port = 4
+    result_port = 3
+    reversed_port = 0           # Optional port of the result with the bits reversed
+
+    [[device]]                  # Serial board connected to the console, see serial.rs
+    type = "uart"
+    board = "2sio"              # sio or 2sio
+    port = 0x10                 # Base port (default: the one of the board)
+    baud = 9600                 # Optional baud rate pacing
+
+    [[interrupt]]               # RST instruction executed every period cycles, the first one after offset cycles
+    rst = 1
+    period = 33333
+    offset = 16666              # (default: the period)
+
+Without any ROM or RAM areas the whole address space is RAM, otherwise the addresses outside of the areas are empty.
+*/
+
+use std::fs::{read, read_to_string};
+use std::path::{Path, PathBuf};
+
+use crate::devices::{InputLatch, ShiftRegister};
+use crate::emulator::memory_map::{MemoryKind, PAGE_SIZE};
+use crate::emulator::{CpuModel, Intel8080};
+use crate::errors::EmulatorError;
+use crate::machine::{Console, Machine};
+use crate::serial::{SerialCard, SerialKind};
+use crate::toml::{self, Table, Value};
+
+pub const DEFAULT_CLOCK_HZ: u64 = 2_000_000;
+pub const DEFAULT_FRAME_HZ: u64 = 60;
+
+
+pub struct RomConfig {
+    pub path: PathBuf,
+    pub address: u16,
+    pub writable: bool,
+}
+
+// Area of the address space
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Area {
+    pub start: u16,
+    pub size: usize,
+}
+
+pub struct MirrorConfig {
+    pub area: Area,
+    pub target: u16,
+}
+
+pub enum DeviceConfig {
+    Latch { name: String, port: u8, value: u8 },
+    ShiftRegister { offset_port: u8, data_port: u8, result_port: u8, reversed_port: Option<u8> },
+    Uart { kind: SerialKind, port: Option<u8>, baud: Option<u32> },
+}
+
+pub struct InterruptConfig {
+    pub rst: u8,
+    pub period: u64,
+    pub offset: u64,
+}
+
+pub struct MachineConfig {
+    pub name: String,
+    pub cpu: CpuModel,
+    pub clock_hz: u64,
+    pub frame_hz: u64,
+    pub roms: Vec<RomConfig>,
+    pub ram: Vec<Area>,
+    pub mirrors: Vec<MirrorConfig>,
+    pub read_only: Vec<Area>,
+    pub devices: Vec<DeviceConfig>,
+    pub interrupts: Vec<InterruptConfig>,
+}
+
+
+// Table of the configuration with the name used in error messages, e.g. "rom 2"
+struct Section<'t> {
+    table: &'t Table,
+    name: String,
+}
+
+impl<'t> Section<'t> {
+    fn error(&self, msg: &str) -> EmulatorError {
+        EmulatorError::ConfigInvalid(format!("{} {msg}", self.name))
+    }
+
+    // Reject misspelled keys instead of silently using the defaults
+    fn check_keys(&self, keys: &[&str]) -> Result<(), EmulatorError> {
+        match self.table.keys().find(|key| !keys.contains(&key.as_str())) {
+            Some(key) => Err(self.error(&format!("has the unknown key '{key}'"))),
+            None => Ok(()),
+        }
+    }
+
+    fn integer(&self, key: &str, max: u64) -> Result<Option<u64>, EmulatorError> {
+        let Some(value) = self.table.get(key) else {
+            return Ok(None);
+        };
+
+        match value.as_integer() {
+            Some(val) if (0..=max as i64).contains(&val) => Ok(Some(val as u64)),
+            Some(val) => Err(self.error(&format!("'{key}' is {val}, not between 0 and {max}"))),
+            None => Err(self.error(&format!("'{key}' is a {}, not an integer", value.type_name()))),
+        }
+    }
+
+    fn required_integer(&self, key: &str, max: u64) -> Result<u64, EmulatorError> {
+        self.integer(key, max)?.ok_or_else(|| self.error(&format!("is missing '{key}'")))
+    }
+
+    fn port(&self, key: &str) -> Result<Option<u8>, EmulatorError> {
+        Ok(self.integer(key, 0xFF)?.map(|port| port as u8))
+    }
+
+    fn required_port(&self, key: &str) -> Result<u8, EmulatorError> {
+        Ok(self.required_integer(key, 0xFF)? as u8)
+    }
+
+    fn string(&self, key: &str) -> Result<Option<&'t str>, EmulatorError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(value) => value.as_str()
+                .map(Some)
+                .ok_or_else(|| self.error(&format!("'{key}' is a {}, not a string", value.type_name()))),
+        }
+    }
+
+    fn required_string(&self, key: &str) -> Result<&'t str, EmulatorError> {
+        self.string(key)?.ok_or_else(|| self.error(&format!("is missing '{key}'")))
+    }
+
+    fn bool(&self, key: &str) -> Result<Option<bool>, EmulatorError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(value) => value.as_bool()
+                .map(Some)
+                .ok_or_else(|| self.error(&format!("'{key}' is a {}, not a boolean", value.type_name()))),
+        }
+    }
+
+    // Area of the start and size keys, which must fit into the address space
+    fn area(&self) -> Result<Area, EmulatorError> {
+        let start = self.required_integer("start", 0xFFFF)?;
+        let size = self.required_integer("size", 0x10000 - start)? as usize;
+
+        Ok(Area { start: start as u16, size })
+    }
+}
+
+// Sections of an array of tables, e.g. all [[rom]] tables
+fn sections<'t>(root: &'t Table, key: &str) -> Result<Vec<Section<'t>>, EmulatorError> {
+    let Some(value) = root.get(key) else {
+        return Ok(Vec::new());
+    };
+
+    let Value::Array(values) = value else {
+        return Err(EmulatorError::ConfigInvalid(format!("'{key}' must be an array of tables, i.e. [[{key}]]")));
+    };
+
+    values.iter().enumerate().map(|(i, value)| match value.as_table() {
+        Some(table) => Ok(Section { table, name: format!("{key} {}", i + 1) }),
+        None => Err(EmulatorError::ConfigInvalid(format!("{key} {} is not a table", i + 1))),
+    }).collect()
+}
+
+
+impl MachineConfig {
+    pub fn load(path: &Path) -> Result<Self, EmulatorError> {
+        let text = read_to_string(path)?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+
+        Self::parse(&text, dir)
+    }
+
+    // Parse the configuration, the ROM paths are relative to the directory
+    pub fn parse(text: &str, dir: &Path) -> Result<Self, EmulatorError> {
+        let root = toml::parse(text)?;
+        let top = Section { table: &root, name: String::from("machine") };
+        top.check_keys(&[
+            "name", "cpu", "clock_hz", "frame_hz", "rom", "ram", "mirror", "read_only", "device", "interrupt",
+        ])?;
+
+        let cpu = match top.string("cpu")? {
+            Some(name) => CpuModel::parse(name).ok_or_else(|| top.error(&format!("has an unknown cpu '{name}'")))?,
+            None => CpuModel::I8080,
+        };
+
+        let clock_hz = top.integer("clock_hz", u32::MAX as u64)?.unwrap_or(DEFAULT_CLOCK_HZ);
+        let frame_hz = top.integer("frame_hz", 1000)?.unwrap_or(DEFAULT_FRAME_HZ);
+
+        if clock_hz == 0 || frame_hz == 0 {
+            return Err(top.error("needs a clock_hz and a frame_hz above 0"));
+        }
+
+        let mut config = MachineConfig {
+            name: top.string("name")?.unwrap_or("Custom machine").to_string(),
+            cpu,
+            clock_hz,
+            frame_hz,
+            roms: Vec::new(),
+            ram: Vec::new(),
+            mirrors: Vec::new(),
+            read_only: Vec::new(),
+            devices: Vec::new(),
+            interrupts: Vec::new(),
+        };
+
+        for rom in sections(&root, "rom")? {
+            rom.check_keys(&["file", "address", "writable"])?;
+
+            config.roms.push(RomConfig {
+                path: dir.join(rom.required_string("file")?),
+                address: rom.required_integer("address", 0xFFFF)? as u16,
+                writable: rom.bool("writable")?.unwrap_or(false),
+            });
+        }
+
+        for ram in sections(&root, "ram")? {
+            ram.check_keys(&["start", "size"])?;
+            config.ram.push(ram.area()?);
+        }
+
+        for mirror in sections(&root, "mirror")? {
+            mirror.check_keys(&["start", "size", "target"])?;
+
+            let area = mirror.area()?;
+            let target = mirror.required_integer("target", (0x10000 - area.size) as u64)? as u16;
+            config.mirrors.push(MirrorConfig { area, target });
+        }
+
+        for read_only in sections(&root, "read_only")? {
+            read_only.check_keys(&["start", "size"])?;
+            config.read_only.push(read_only.area()?);
+        }
+
+        for device in sections(&root, "device")? {
+            config.devices.push(parse_device(&device)?);
+        }
+
+        for interrupt in sections(&root, "interrupt")? {
+            interrupt.check_keys(&["rst", "period", "offset"])?;
+
+            let period = interrupt.required_integer("period", u32::MAX as u64)?;
+            if period == 0 {
+                return Err(interrupt.error("needs a period above 0 cycles"));
+            }
+
+            config.interrupts.push(InterruptConfig {
+                rst: interrupt.required_integer("rst", 7)? as u8,
+                period,
+                offset: interrupt.integer("offset", u32::MAX as u64)?.unwrap_or(period),
+            });
+        }
+
+        Ok(config)
+    }
+
+    // Fit an empty CPU with the memory and the devices of the configuration
+    pub fn build(&self, mut cpu: Intel8080) -> Result<ConfigMachine, EmulatorError> {
+        cpu.set_model(self.cpu);
+        cpu.load_rom(&[]);
+
+        let mut rom_areas = Vec::new();
+
+        for rom in self.roms.iter() {
+            let image = read(&rom.path)
+                .map_err(|e| EmulatorError::FileCantOpen(format!("{}: {e}", rom.path.display())))?;
+
+            if rom.address as usize + image.len() > 0x10000 {
+                return Err(EmulatorError::LoadAddressInvalid(image.len(), rom.address));
+            }
+
+            for (i, byte) in image.iter().enumerate() {
+                cpu.write_byte(rom.address + i as u16, *byte);
+            }
+
+            rom_areas.push(Area { start: rom.address, size: image.len() });
+        }
+
+        let mut areas: Vec<Area> = self.ram.iter().chain(rom_areas.iter()).copied().collect();
+        areas.extend(self.mirrors.iter().map(|mirror| mirror.area));
+
+        // Without any memory areas the default of RAM everywhere stays
+        if !areas.is_empty() {
+            let mut used = [false; 0x10000 / PAGE_SIZE];
+
+            for area in areas.iter().filter(|area| area.size > 0) {
+                let first = area.start as usize / PAGE_SIZE;
+                let last = (area.start as usize + area.size - 1) / PAGE_SIZE;
+                used[first..=last].fill(true);
+            }
+
+            for (page, _) in used.iter().enumerate().filter(|(_, used)| !**used) {
+                cpu.map_memory((page * PAGE_SIZE) as u16, PAGE_SIZE, MemoryKind::Empty);
+            }
+        }
+
+        for (_, area) in self.roms.iter().zip(rom_areas.iter()).filter(|(rom, _)| !rom.writable) {
+            cpu.map_memory(area.start, area.size, MemoryKind::Rom);
+        }
+
+        for area in self.read_only.iter() {
+            cpu.map_memory(area.start, area.size, MemoryKind::Rom);
+        }
+
+        for mirror in self.mirrors.iter() {
+            cpu.mirror_memory(mirror.area.start, mirror.area.size, mirror.target);
+        }
+
+        let mut console = false;
+
+        for device in self.devices.iter() {
+            match device {
+                DeviceConfig::Latch { name, port, value } => {
+                    cpu.attach_device(Box::new(InputLatch::new(name, *port, *value)));
+                },
+                DeviceConfig::ShiftRegister { offset_port, data_port, result_port, reversed_port } => {
+                    let mut shift_register = ShiftRegister::new(*offset_port, *data_port, *result_port);
+                    if let Some(port) = reversed_port {
+                        shift_register = shift_register.with_reversed_result(*port);
+                    }
+
+                    cpu.attach_device(Box::new(shift_register));
+                },
+                DeviceConfig::Uart { kind, port, baud } => {
+                    let mut serial = SerialCard::new(*kind).with_base_port(port.unwrap_or(kind.base_port()));
+                    serial.set_baud(*baud, self.clock_hz);
+
+                    cpu.attach_device(Box::new(serial));
+                    console = true;
+                },
+            }
+        }
+
+        let interrupts = self.interrupts.iter()
+            .map(|interrupt| Interrupt { rst: interrupt.rst, period: interrupt.period, next_at: interrupt.offset })
+            .collect();
+
+        Ok(ConfigMachine {
+            cpu,
+            name: self.name.clone(),
+            clock_hz: self.clock_hz,
+            frame_cycles: (self.clock_hz / self.frame_hz).max(1),
+            interrupts,
+            console,
+            waiting: false,
+        })
+    }
+}
+
+fn parse_device(device: &Section) -> Result<DeviceConfig, EmulatorError> {
+    match device.required_string("type")? {
+        "latch" => {
+            device.check_keys(&["type", "name", "port", "value"])?;
+            let port = device.required_port("port")?;
+
+            Ok(DeviceConfig::Latch {
+                name: device.string("name")?.map_or_else(|| format!("in{port}"), |name| name.to_string()),
+                port,
+                value: device.integer("value", 0xFF)?.unwrap_or(0x00) as u8,
+            })
+        },
+        "shift_register" => {
+            device.check_keys(&["type", "offset_port", "data_port", "result_port", "reversed_port"])?;
+
+            Ok(DeviceConfig::ShiftRegister {
+                offset_port: device.required_port("offset_port")?,
+                data_port: device.required_port("data_port")?,
+                result_port: device.required_port("result_port")?,
+                reversed_port: device.port("reversed_port")?,
+            })
+        },
+        "uart" => {
+            device.check_keys(&["type", "board", "port", "baud"])?;
+
+            let board = device.string("board")?.unwrap_or("2sio");
+            let kind = SerialKind::parse(board)
+                .ok_or_else(|| device.error(&format!("has the unknown board '{board}'")))?;
+
+            Ok(DeviceConfig::Uart {
+                kind,
+                port: device.port("port")?,
+                baud: device.integer("baud", u32::MAX as u64)?.filter(|baud| *baud > 0).map(|baud| baud as u32),
+            })
+        },
+        other => Err(device.error(&format!("has the unknown type '{other}'"))),
+    }
+}
+
+
+// Interrupt source with the cycle count of its next interrupt
+struct Interrupt {
+    rst: u8,
+    period: u64,
+    next_at: u64,
+}
+
+pub struct ConfigMachine {
+    cpu: Intel8080,
+    name: String,
+    clock_hz: u64,
+    frame_cycles: u64,
+    interrupts: Vec<Interrupt>,
+    console: bool,
+    waiting: bool,
+}
+
+impl ConfigMachine {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Whether a serial board connects the machine to the console
+    pub fn has_console(&self) -> bool {
+        self.console
+    }
+
+    fn serial(&mut self) -> Option<&mut SerialCard> {
+        self.cpu.device_mut::<SerialCard>()
+    }
+}
+
+impl Machine for ConfigMachine {
+    fn cpu(&self) -> &Intel8080 {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut Intel8080 {
+        &mut self.cpu
+    }
+
+    fn clock_hz(&self) -> u64 {
+        self.clock_hz
+    }
+
+    // Run the CPU for one frame, delivering the interrupts at their cycles
+    fn run_frame(&mut self) {
+        self.cpu.apply_freezes();
+
+        if let Some(serial) = self.serial() {
+            serial.take_idle();
+        }
+
+        let start = self.cpu.get_cycles();
+        let end = start + self.frame_cycles;
+
+        // After restoring a save state the cycle count jumps, keep the phase of the sources but skip the missed ones
+        for interrupt in self.interrupts.iter_mut().filter(|interrupt| interrupt.next_at < start) {
+            interrupt.next_at += (start - interrupt.next_at).div_ceil(interrupt.period) * interrupt.period;
+        }
+
+        while let Some(interrupt) = self.interrupts.iter_mut()
+            .filter(|interrupt| interrupt.next_at <= end)
+            .min_by_key(|interrupt| interrupt.next_at)
+        {
+            let (rst, at) = (interrupt.rst, interrupt.next_at);
+            interrupt.next_at += interrupt.period;
+
+            self.cpu.run_until(at);
+            self.cpu.interrupt(rst);
+        }
+
+        self.cpu.run_until(end);
+
+        self.waiting = self.serial().is_some_and(|serial| serial.take_idle());
+    }
+}
+
+impl Console for ConfigMachine {
+    fn push_input(&mut self, bytes: &[u8]) {
+        if let Some(serial) = self.serial() {
+            serial.receive(bytes);
+        }
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        self.serial().map_or_else(Vec::new, |serial| serial.take_sent())
+    }
+
+    fn is_waiting_for_input(&self) -> bool {
+        self.waiting
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        name = "Test"
+        cpu = "8085"
+        clock_hz = 3_000_000
+
+        [[ram]]
+        start = 0x2000
+        size = 0x2000
+
+        [[mirror]]
+        start = 0x4000
+        size = 0x2000
+        target = 0x2000
+
+        [[read_only]]
+        start = 0x3000
+        size = 0x100
+
+        [[device]]
+        type = "shift_register"
+        offset_port = 2
+        data_port = 4
+        result_port = 3
+
+        [[interrupt]]
+        rst = 1
+        period = 1000
+    "#;
+
+    #[test]
+    fn machine_from_the_configuration() {
+        let config = MachineConfig::parse(CONFIG, Path::new(".")).unwrap();
+        assert_eq!(config.name, "Test");
+        assert_eq!(config.cpu, CpuModel::I8085);
+        assert_eq!(config.frame_hz, DEFAULT_FRAME_HZ);
+        assert_eq!(config.interrupts[0].offset, 1000);
+
+        let mut machine = config.build(Intel8080::new()).unwrap();
+        let cpu = &mut machine.cpu;
+        assert_eq!(cpu.memory_kind(0x0000), MemoryKind::Empty);
+        assert_eq!(cpu.memory_kind(0x2000), MemoryKind::Ram);
+        assert_eq!(cpu.memory_kind(0x3000), MemoryKind::Rom);
+        assert_eq!(cpu.memory_kind(0x5000), MemoryKind::Rom);
+        assert!(cpu.device_mut::<ShiftRegister>().is_some());
+
+        cpu.write_byte(0x2345, 0x12);
+        assert_eq!(cpu.read_byte(0x4345), 0x12);
+        assert_eq!(machine.frame_cycles, 50_000);
+    }
+
+    #[test]
+    fn invalid_configurations_fail() {
+        for text in [
+            "cpu = \"6502\"",
+            "clock = 1",
+            "frame_hz = 0",
+            "[[ram]]\nstart = 0\nsize = 0x100\nend = 0xff",
+            "[[mirror]]\nstart = 0\nsize = 0x2000\ntarget = 0xF000",
+            "[[interrupt]]\nrst = 8\nperiod = 100",
+            "[[device]]\ntype = \"printer\"",
+        ] {
+            let result = MachineConfig::parse(text, Path::new("."));
+            assert!(matches!(result, Err(EmulatorError::ConfigInvalid(_))), "'{text}' was accepted");
+        }
+    }
+}
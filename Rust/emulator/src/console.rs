@@ -0,0 +1,102 @@
+/*
+Console front end for machines with a character terminal, i.e. CP/M and the Altair 8800 serial boards
+
+The console is connected to the terminal in raw mode, so that the programs get every key as typed, including ^C,
+and do their own echoing. Press ^\ to quit the emulator and ^] to toggle the turbo mode.
+
+The interactive console runs in real time at the clock of the machine, the measured speed can be shown in the title
+of the terminal. Piped input runs as fast as possible unless throttling is asked for.
+
+When stdin is not a terminal, e.g. for piped input, line feeds are turned into the carriage returns that the
+programs expect, and the emulator stops once the input is used up and the program waits for more.
+
+The console can also be served on a TCP port or a pseudo-terminal instead, see link.rs. The emulator then runs until
+the CPU halts, the frame limit is reached or it is interrupted.
+*/
+
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::time::Duration;
+
+use emulator::errors::EmulatorError;
+use emulator::machine::{Console, Machine};
+use emulator::throttle::Throttle;
+
+use crate::link::{Link, LinkKind};
+use crate::terminal::RawMode;
+
+const QUIT: u8 = 0x1C;
+const TURBO: u8 = 0x1D;
+const CR: u8 = 0x0D;
+const LF: u8 = 0x0A;
+
+// How long to sleep while the program waits for a key
+const INPUT_POLL: Duration = Duration::from_millis(10);
+
+
+// Run the program until the CPU halts, the frame limit is reached or the user quits. The console is throttled when it's
+// interactive or always_throttle is set.
+pub fn run(
+    machine: &mut (impl Machine + Console), frames: Option<u64>, link: LinkKind, throttle: &mut Throttle,
+    always_throttle: bool, show_speed: bool,
+) -> Result<(), EmulatorError> {
+    let link = Link::open(link)?;
+    let stdio = link.kind() == LinkKind::Stdio;
+    let raw_mode = if stdio { RawMode::enable().ok() } else { None };
+    let input = link.input();
+    let throttled = always_throttle || raw_mode.is_some() || !stdio;
+
+    let mut frame: u64 = 0;
+    let mut input_closed = false;
+
+    while !machine.cpu().is_halted() && frames.is_none_or(|max| frame < max) {
+        loop {
+            match input.try_recv() {
+                Ok(QUIT) if raw_mode.is_some() => return Ok(()),
+                Ok(TURBO) if raw_mode.is_some() => {
+                    throttle.toggle_turbo();
+                },
+                Ok(LF) if stdio && raw_mode.is_none() => machine.push_input(&[CR]),
+                Ok(byte) => machine.push_input(&[byte]),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    input_closed = true;
+                    break;
+                },
+            }
+        }
+
+        machine.run_frame();
+        frame += 1;
+
+        if link.is_connected() {
+            link.write(&machine.take_output())?;
+        }
+
+        if machine.is_waiting_for_input() {
+            if input_closed {
+                break;
+            }
+
+            // In real time the throttle already sleeps for the rest of the frame
+            if !throttled || throttle.is_turbo() {
+                thread::sleep(INPUT_POLL);
+            }
+        }
+
+        if throttled && throttle.sync(machine.cpu().get_cycles()) && show_speed {
+            show_readout(&throttle.readout(), raw_mode.is_some());
+        }
+    }
+
+    Ok(())
+}
+
+// The terminal belongs to the program, so the speed goes into the title of its window instead, or to stderr
+fn show_readout(readout: &str, terminal: bool) {
+    if terminal {
+        eprint!("\x1b]2;{readout}\x07");
+    } else {
+        eprintln!("Speed: {readout}");
+    }
+}
@@ -0,0 +1,566 @@
+/*
+CP/M 2.2 - Run .COM programs in a CP/M 2.2 environment whose BDOS and BIOS are implemented in Rust
+
+Memory map:
+    0x0000      JMP to the BIOS warm boot, ends the program
+    0x0003      IOBYTE
+    0x0004      Current drive and user number
+    0x0005      JMP to the BDOS entry, its address also gives the top of the TPA
+    0x005C      Default FCB 1, filled from the first argument of the command tail
+    0x006C      Default FCB 2, filled from the second argument
+    0x0080      Command tail, also the default DMA buffer
+    0x0100      TPA, the program is loaded and started here
+    0xFC06      BDOS entry, followed by the disk parameter block and the allocation vector
+    0xFE00      BIOS jump table of 17 entries, jumping to one stub each at 0xFE80-0xFE90
+
+The BDOS entry and the BIOS stubs hold RET instructions, or HLT for the boot entries so that the CPU halts when the
+program exits. The machine handles the call in Rust when the PC reaches one of them and then lets the CPU execute
+the RET. All drives are mapped onto one host directory, see bdos.rs.
+
+Instead of running a program on the host directory, the machine can also boot an unmodified CP/M 2.2 from disk
+images, see disk.rs. The CCP and BDOS are loaded from the system tracks of drive A: to the address they were
+generated for, the BIOS stays in Rust and is placed right after the BDOS:
+    CCP         Command processor, 0x800 bytes
+    CCP+0x0800  BDOS, 0xE00 bytes
+    CCP+0x1600  BIOS jump table, stubs, skew table, disk parameter block, directory buffer, disk parameter headers,
+                check and allocation vectors
+
+The console is buffered: the front end pushes the typed bytes into the input queue and prints the output after every
+frame. A program waiting for a key ends the frame early and retries the call in the next frame.
+*/
+
+pub mod bdos;
+pub mod disk;
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use crate::cpm::disk::{DiskController, DiskImage, MAX_DRIVES, SECTOR_SIZE, SKEW};
+use crate::emulator::Intel8080;
+use crate::errors::EmulatorError;
+use crate::machine::{Console, Machine};
+
+pub const CLOCK_HZ: u64 = 2_000_000;
+
+// Cycles run per frame, matches a 60 Hz frame like the other machines
+pub const FRAME_CYCLES: u64 = CLOCK_HZ / 60;
+
+pub const BDOS_CALL: u16 = 0x0005;
+pub const FCB1: u16 = 0x005C;
+pub const FCB2: u16 = 0x006C;
+pub const DEFAULT_DMA: u16 = 0x0080;
+pub const TPA: u16 = 0x0100;
+
+pub const BDOS_ENTRY: u16 = 0xFC06;
+pub const DPB: u16 = 0xFC10;
+pub const ALLOCATION_VECTOR: u16 = 0xFC20;
+pub const BIOS: u16 = 0xFE00;
+pub const BIOS_ENTRIES: u16 = 17;
+
+// Offsets from the start of the BIOS
+const BIOS_STUBS: u16 = 0x80;
+const BIOS_SKEW: u16 = 0xA0;
+const BIOS_DPB: u16 = 0xC0;
+const BIOS_DIRECTORY_BUFFER: u16 = 0x100;
+const BIOS_DPH: u16 = 0x180;
+const BIOS_CHECK_VECTORS: u16 = 0x1C0;
+const BIOS_ALLOCATION_VECTORS: u16 = 0x200;
+const BIOS_SIZE: u16 = 0x280;
+
+// Sizes of the disk parameter header and of the check and allocation vectors of each drive
+const DPH_SIZE: u16 = 16;
+const CHECK_VECTOR_SIZE: u16 = 16;
+const ALLOCATION_VECTOR_SIZE: u16 = 32;
+
+// Offsets of the BDOS and the BIOS from the CCP
+const CCP_BDOS_ENTRY: u16 = 0x806;
+const CCP_BIOS: u16 = 0x1600;
+
+const IOBYTE: u16 = 0x0003;
+const DRIVE_USER: u16 = 0x0004;
+
+// The program gets a return address of 0x0000 on its stack, just below the BDOS
+const STACK: u16 = 0xFBFE;
+
+// Disk parameter block of the standard 8" single density disk: 26 sectors per track, 1K blocks, 243 blocks,
+// 64 directory entries and 2 system tracks
+const DPB_8_INCH: [u8; 15] = [26, 0, 3, 7, 0, 242, 0, 63, 0, 0xC0, 0x00, 16, 0, 2, 0];
+
+const JMP: u8 = 0xC3;
+const RET: u8 = 0xC9;
+const HLT: u8 = 0x76;
+
+const CR: u8 = 0x0D;
+const LF: u8 = 0x0A;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7F;
+const CTRL_C: u8 = 0x03;
+const CTRL_U: u8 = 0x15;
+const CTRL_X: u8 = 0x18;
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BiosFunction {
+    Boot,
+    WarmBoot,
+    ConsoleStatus,
+    ConsoleInput,
+    ConsoleOutput,
+    List,
+    Punch,
+    Reader,
+    Home,
+    SelectDisk,
+    SetTrack,
+    SetSector,
+    SetDma,
+    Read,
+    Write,
+    ListStatus,
+    SectorTranslate,
+}
+
+const BIOS_FUNCTIONS: [BiosFunction; BIOS_ENTRIES as usize] = [
+    BiosFunction::Boot,
+    BiosFunction::WarmBoot,
+    BiosFunction::ConsoleStatus,
+    BiosFunction::ConsoleInput,
+    BiosFunction::ConsoleOutput,
+    BiosFunction::List,
+    BiosFunction::Punch,
+    BiosFunction::Reader,
+    BiosFunction::Home,
+    BiosFunction::SelectDisk,
+    BiosFunction::SetTrack,
+    BiosFunction::SetSector,
+    BiosFunction::SetDma,
+    BiosFunction::Read,
+    BiosFunction::Write,
+    BiosFunction::ListStatus,
+    BiosFunction::SectorTranslate,
+];
+
+
+pub struct CpmMachine {
+    cpu: Intel8080,
+    bios: u16,
+
+    // Address of the CCP when booted from disk, None when the Rust BDOS runs a program on the host directory
+    ccp: Option<u16>,
+
+    // Host directory that all drives are mapped onto
+    dir: PathBuf,
+
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+
+    // Line being typed into a BDOS read buffer call
+    line: Option<Vec<u8>>,
+    waiting: bool,
+
+    dma: u16,
+    drive: u8,
+    user: u8,
+
+    // Directory entries left for the BDOS search next function
+    search: Vec<bdos::HostFile>,
+}
+
+impl CpmMachine {
+    // Set up the memory of a CPU with an empty memory, load the program into the TPA and parse the command tail into
+    // the default FCBs
+    pub fn new(mut cpu: Intel8080, program: &[u8], dir: &Path, tail: &str) -> Result<Self, EmulatorError> {
+        // Leave 256 bytes for the stack
+        let max_size = (STACK - 0x100 - TPA) as usize;
+
+        if program.len() > max_size {
+            return Err(EmulatorError::CpmProgramTooLarge(program.len(), max_size));
+        }
+
+        if !dir.is_dir() {
+            return Err(EmulatorError::FilePathNotFound(dir.display().to_string()));
+        }
+
+        let mut mem = vec![0x00; 0x10000];
+
+        mem[0x0000..0x0003].copy_from_slice(&[JMP, 0x03, (BIOS >> 8) as u8]);
+        let bdos_call = BDOS_CALL as usize;
+        mem[bdos_call..bdos_call + 3].copy_from_slice(&[JMP, BDOS_ENTRY as u8, (BDOS_ENTRY >> 8) as u8]);
+        mem[BDOS_ENTRY as usize] = RET;
+        mem[DPB as usize..DPB as usize + DPB_8_INCH.len()].copy_from_slice(&DPB_8_INCH);
+
+        write_jump_table(&mut mem, BIOS, HLT);
+
+        // Command tail as the CCP leaves it: upper case with a leading space and a terminating zero
+        let tail = tail.trim().to_ascii_uppercase();
+        let tail: Vec<u8> = if tail.is_empty() { Vec::new() } else { format!(" {tail}").into_bytes() };
+        let tail = &tail[..tail.len().min(126)];
+
+        mem[DEFAULT_DMA as usize] = tail.len() as u8;
+        mem[DEFAULT_DMA as usize + 1..DEFAULT_DMA as usize + 1 + tail.len()].copy_from_slice(tail);
+
+        let tail = String::from_utf8_lossy(tail).to_string();
+        let mut args = tail.split_whitespace();
+
+        for fcb in [FCB1, FCB2] {
+            let parsed = bdos::parse_fcb_name(args.next().unwrap_or(""));
+            mem[fcb as usize..fcb as usize + parsed.len()].copy_from_slice(&parsed);
+        }
+
+        mem[TPA as usize..TPA as usize + program.len()].copy_from_slice(program);
+
+        cpu.load_rom(&mem);
+        cpu.set_pc(TPA);
+        cpu.set_sp(STACK);
+
+        Ok(CpmMachine {
+            cpu,
+            bios: BIOS,
+            ccp: None,
+
+            dir: dir.to_path_buf(),
+
+            input: VecDeque::new(),
+            output: Vec::new(),
+
+            line: None,
+            waiting: false,
+
+            dma: DEFAULT_DMA,
+            drive: 0,
+            user: 0,
+
+            search: Vec::new(),
+        })
+    }
+
+    // Boot CP/M from the disk images in drives A:, B:, ... and attach the disk controller
+    pub fn boot(mut cpu: Intel8080, disks: Vec<DiskImage>) -> Result<Self, EmulatorError> {
+        let boot_disk = disks.first().map(|disk| disk.path().display().to_string()).unwrap_or_default();
+
+        if disks.is_empty() || disks.len() > MAX_DRIVES {
+            return Err(EmulatorError::DiskCountInvalid(disks.len(), MAX_DRIVES));
+        }
+
+        // The CCP starts with JMP CCP+0x035C, which gives the address it was generated for
+        let system = disks[0].system();
+        let ccp = match system[..3] {
+            [JMP, 0x5C, page] if page >= 3 => (page as u16 - 3) << 8,
+            _ => return Err(EmulatorError::DiskNotBootable(boot_disk)),
+        };
+
+        let bios = ccp.wrapping_add(CCP_BIOS);
+        if ccp < TPA || bios as usize + BIOS_SIZE as usize > 0x10000 {
+            return Err(EmulatorError::DiskNotBootable(boot_disk));
+        }
+
+        let mut mem = vec![0x00; 0x10000];
+        write_jump_table(&mut mem, bios, RET);
+
+        let skew = (bios + BIOS_SKEW) as usize;
+        mem[skew..skew + SKEW.len()].copy_from_slice(&SKEW);
+
+        let dpb = (bios + BIOS_DPB) as usize;
+        mem[dpb..dpb + DPB_8_INCH.len()].copy_from_slice(&DPB_8_INCH);
+
+        // Disk parameter headers: skew table, 3 words of BDOS scratch, directory buffer, DPB, check and allocation
+        // vector
+        for drive in 0..disks.len() as u16 {
+            let dph = (bios + BIOS_DPH + drive * DPH_SIZE) as usize;
+            let words = [
+                bios + BIOS_SKEW, 0x0000, 0x0000, 0x0000, bios + BIOS_DIRECTORY_BUFFER, bios + BIOS_DPB,
+                bios + BIOS_CHECK_VECTORS + drive * CHECK_VECTOR_SIZE,
+                bios + BIOS_ALLOCATION_VECTORS + drive * ALLOCATION_VECTOR_SIZE,
+            ];
+
+            for (i, word) in words.iter().enumerate() {
+                mem[dph + i * 2..dph + i * 2 + 2].copy_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        cpu.load_rom(&mem);
+        cpu.attach_device(Box::new(DiskController::new(disks)));
+
+        let mut machine = CpmMachine {
+            cpu,
+            bios,
+            ccp: Some(ccp),
+
+            dir: PathBuf::new(),
+
+            input: VecDeque::new(),
+            output: Vec::new(),
+
+            line: None,
+            waiting: false,
+
+            dma: DEFAULT_DMA,
+            drive: 0,
+            user: 0,
+
+            search: Vec::new(),
+        };
+
+        machine.load_system(true);
+        Ok(machine)
+    }
+
+    // Load the CCP and BDOS from the system tracks, set up the page zero jumps and start the CCP on the current
+    // drive. A cold boot also resets the IOBYTE and the current drive.
+    fn load_system(&mut self, cold: bool) {
+        let Some(ccp) = self.ccp else {
+            return;
+        };
+
+        let system = self.disk_controller().system().unwrap_or_default();
+
+        for (i, byte) in system.iter().take(CCP_BIOS as usize).enumerate() {
+            self.cpu.write_byte(ccp + i as u16, *byte);
+        }
+
+        let wboot = self.bios + 3;
+        let bdos = ccp + CCP_BDOS_ENTRY;
+
+        for (addr, byte) in [(0x0000, JMP), (0x0001, wboot as u8), (0x0002, (wboot >> 8) as u8)] {
+            self.cpu.write_byte(addr, byte);
+        }
+
+        for (addr, byte) in [(BDOS_CALL, JMP), (BDOS_CALL + 1, bdos as u8), (BDOS_CALL + 2, (bdos >> 8) as u8)] {
+            self.cpu.write_byte(addr, byte);
+        }
+
+        if cold {
+            self.cpu.write_byte(IOBYTE, 0x00);
+            self.cpu.write_byte(DRIVE_USER, 0x00);
+        }
+
+        self.dma = DEFAULT_DMA;
+        self.cpu.set_reg("C", self.cpu.read_byte(DRIVE_USER));
+        self.cpu.set_sp(DEFAULT_DMA);
+        self.cpu.set_pc(ccp);
+    }
+
+    fn disk_controller(&mut self) -> &mut DiskController {
+        self.cpu.device_mut::<DiskController>().expect("CP/M disk controller missing")
+    }
+
+    // End the program by jumping to the warm boot stub, which halts the CPU
+    fn exit(&mut self) {
+        self.cpu.set_pc(self.bios + BIOS_STUBS + 1);
+    }
+
+    // Return a 16-bit result of a BDOS or BIOS call in HL, and also in BA for compatibility with CP/M 1.4 programs
+    fn set_result(&mut self, val: u16) {
+        self.cpu.set_reg_pair("HL", val);
+        self.cpu.set_reg("A", val as u8);
+        self.cpu.set_reg("B", (val >> 8) as u8);
+    }
+
+    fn console_status(&self) -> u8 {
+        if self.input.is_empty() { 0x00 } else { 0xFF }
+    }
+
+    // Echo a typed character like CP/M does, control characters other than the line ending ones are not printed
+    fn echo(&mut self, byte: u8) {
+        if byte >= 0x20 || matches!(byte, CR | LF | BACKSPACE | b'\t') {
+            self.output.push(byte);
+        }
+    }
+
+    // BDOS function 10 - Read a line into the buffer at DE with simple line editing. Return false if the line is not
+    // finished yet.
+    fn read_buffer(&mut self) -> bool {
+        let buffer = self.cpu.get_reg_pair("DE");
+        let max = self.cpu.read_byte(buffer) as usize;
+        let mut line = self.line.take().unwrap_or_default();
+
+        while let Some(byte) = self.input.pop_front() {
+            match byte {
+                CR | LF => {
+                    self.output.push(CR);
+                    self.cpu.write_byte(buffer.wrapping_add(1), line.len() as u8);
+
+                    for (i, byte) in line.iter().enumerate() {
+                        self.cpu.write_byte(buffer.wrapping_add(2 + i as u16), *byte);
+                    }
+
+                    return true;
+                },
+                BACKSPACE | DELETE if !line.is_empty() => {
+                    line.pop();
+                    self.output.extend_from_slice(b"\x08 \x08");
+                },
+                BACKSPACE | DELETE => {},
+                CTRL_U | CTRL_X => {
+                    for _ in line.drain(..) {
+                        self.output.extend_from_slice(b"\x08 \x08");
+                    }
+                },
+                CTRL_C if line.is_empty() => {
+                    self.exit();
+                    return true;
+                },
+                _ if line.len() < max => {
+                    line.push(byte);
+                    self.echo(byte);
+                },
+                _ => {},
+            }
+        }
+
+        self.line = Some(line);
+        false
+    }
+
+    // Handle a call to a BIOS entry. Return false if it has to wait for console input.
+    fn bios_call(&mut self, function: BiosFunction) -> bool {
+        match function {
+            // The stubs halt the CPU when running a program on the host directory
+            BiosFunction::Boot => self.load_system(true),
+            BiosFunction::WarmBoot => self.load_system(false),
+            BiosFunction::ConsoleStatus => self.cpu.set_reg("A", self.console_status()),
+            BiosFunction::ConsoleInput => match self.input.pop_front() {
+                Some(byte) => self.cpu.set_reg("A", byte & 0x7F),
+                None => return false,
+            },
+            BiosFunction::ConsoleOutput => self.output.push(self.cpu.get_reg("C") & 0x7F),
+            BiosFunction::List | BiosFunction::Punch => {},
+            BiosFunction::Reader => self.cpu.set_reg("A", 0x1A),
+            BiosFunction::ListStatus => self.cpu.set_reg("A", 0xFF),
+            BiosFunction::SetDma => self.dma = self.cpu.get_reg_pair("BC"),
+
+            // Physical sector of the logical sector in BC from the skew table at DE, sectors count from 1 without one
+            BiosFunction::SectorTranslate => {
+                let sector = self.cpu.get_reg_pair("BC");
+                let table = self.cpu.get_reg_pair("DE");
+
+                let physical = match table {
+                    0x0000 => sector + 1,
+                    _ => self.cpu.read_byte(table.wrapping_add(sector)) as u16,
+                };
+
+                self.cpu.set_reg_pair("HL", physical);
+            },
+
+            // Without disks the drives are host directories handled by the BDOS
+            _ if self.ccp.is_none() => match function {
+                BiosFunction::SelectDisk => self.cpu.set_reg_pair("HL", 0x0000),
+                BiosFunction::Read | BiosFunction::Write => self.cpu.set_reg("A", 0x01),
+                _ => {},
+            },
+
+            // Return the address of the disk parameter header, 0 if there is no such drive
+            BiosFunction::SelectDisk => {
+                let drive = self.cpu.get_reg("C");
+
+                if (drive as usize) < self.disk_controller().drive_count() {
+                    self.disk_controller().select(drive);
+                    self.cpu.set_reg_pair("HL", self.bios + BIOS_DPH + drive as u16 * DPH_SIZE);
+                } else {
+                    self.cpu.set_reg_pair("HL", 0x0000);
+                }
+            },
+            BiosFunction::Home => self.disk_controller().set_track(0),
+            BiosFunction::SetTrack => {
+                let track = self.cpu.get_reg("C");
+                self.disk_controller().set_track(track);
+            },
+            BiosFunction::SetSector => {
+                let sector = self.cpu.get_reg("C");
+                self.disk_controller().set_sector(sector);
+            },
+
+            // Transfer the sector between the DMA buffer and the controller, return 0 for success
+            BiosFunction::Read => {
+                let status = self.disk_controller().command(disk::COMMAND_READ);
+                let buffer = *self.disk_controller().buffer();
+
+                if status == disk::STATUS_OK {
+                    for (i, byte) in buffer.iter().enumerate() {
+                        self.cpu.write_byte(self.dma.wrapping_add(i as u16), *byte);
+                    }
+                }
+
+                self.cpu.set_reg("A", status);
+            },
+            BiosFunction::Write => {
+                let mut buffer = [0x00; SECTOR_SIZE];
+                for (i, byte) in buffer.iter_mut().enumerate() {
+                    *byte = self.cpu.read_byte(self.dma.wrapping_add(i as u16));
+                }
+
+                *self.disk_controller().buffer_mut() = buffer;
+                let status = self.disk_controller().command(disk::COMMAND_WRITE);
+                self.cpu.set_reg("A", status);
+            },
+        }
+
+        true
+    }
+}
+
+impl Machine for CpmMachine {
+    fn cpu(&self) -> &Intel8080 {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut Intel8080 {
+        &mut self.cpu
+    }
+
+    // Run the program for one frame, handling the BDOS and BIOS calls. The frame ends early when the program exits
+    // or waits for console input.
+    fn run_frame(&mut self) {
+        self.cpu.apply_freezes();
+        self.waiting = false;
+
+        let target = self.cpu.get_cycles() + FRAME_CYCLES;
+
+        while self.cpu.get_cycles() < target && !self.cpu.is_halted() {
+            let pc = self.cpu.get_pc();
+
+            let stubs = self.bios + BIOS_STUBS;
+
+            let done = if pc == BDOS_ENTRY && self.ccp.is_none() {
+                self.bdos_call()
+            } else if (stubs..stubs + BIOS_ENTRIES).contains(&pc) {
+                self.bios_call(BIOS_FUNCTIONS[(pc - stubs) as usize])
+            } else {
+                true
+            };
+
+            if !done {
+                self.waiting = true;
+                return;
+            }
+
+            self.cpu.step();
+        }
+    }
+}
+
+impl Console for CpmMachine {
+    fn push_input(&mut self, bytes: &[u8]) {
+        self.input.extend(bytes);
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output)
+    }
+
+    // The last frame ended early because the program waits for console input
+    fn is_waiting_for_input(&self) -> bool {
+        self.waiting
+    }
+}
+
+// Write the BIOS jump table with one stub per entry, the stubs of the boot entries hold the given instruction
+fn write_jump_table(mem: &mut [u8], bios: u16, boot_stub: u8) {
+    for i in 0..BIOS_ENTRIES {
+        let entry = (bios + i * 3) as usize;
+        let stub = bios + BIOS_STUBS + i;
+
+        mem[entry..entry + 3].copy_from_slice(&[JMP, stub as u8, (stub >> 8) as u8]);
+        mem[stub as usize] = if i < 2 { boot_stub } else { RET };
+    }
+}
@@ -0,0 +1,586 @@
+/*
+CP/M 2.2 BDOS - Console, file and DMA functions called with the function number in C and the parameter in DE
+
+Files are FCB based, the FCB holds the name and the position and there are no handles:
+    0           Drive, 0 for the current drive and 1-16 for A-P
+    1-11        Name and type, padded with spaces. The highest bits of the type are the file attributes.
+    12          Extent, the current 16K block of the file
+    13-14       S1 and S2, S2 counts the 512K modules
+    15          Record count of the current extent
+    16-31       Allocation map, holds the new name when renaming
+    32          Current record of the extent
+    33-35       Random record number
+
+All drives and user numbers are mapped onto one host directory. Host files whose names don't fit 8.3 are not visible
+and new files are created with upper case names. The files are not kept open, every record is read or written at its
+position in the host file, so closing a file only checks that it still exists.
+*/
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use super::{CpmMachine, ALLOCATION_VECTOR, DEFAULT_DMA, DPB, DRIVE_USER, IOBYTE};
+
+const RECORD_SIZE: u64 = 128;
+const RECORDS_PER_EXTENT: u32 = 128;
+
+// Filler of the unused bytes of the last record of a file
+const EOF: u8 = 0x1A;
+
+// Byte of unused directory entries
+const EMPTY_ENTRY: u8 = 0xE5;
+
+// Characters that can't be a part of a file name
+const DELIMITERS: &[u8] = b" <>.,;:=?*[]_%|()/\\";
+
+const FCB_EXTENT: u16 = 12;
+const FCB_S2: u16 = 14;
+const FCB_RECORD_COUNT: u16 = 15;
+const FCB_NEW_NAME: u16 = 17;
+const FCB_CURRENT_RECORD: u16 = 32;
+const FCB_RANDOM_RECORD: u16 = 33;
+
+// Return codes of the file functions
+const OK: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const DISK_FULL: u8 = 0x02;
+const SEEK_PAST_END: u8 = 0x06;
+const NOT_FOUND: u8 = 0xFF;
+
+
+// A file in the host directory with its CP/M name
+pub struct HostFile {
+    pub host_name: String,
+    pub name: [u8; 11],
+    pub size: u64,
+}
+
+impl HostFile {
+    fn records(&self) -> u32 {
+        self.size.div_ceil(RECORD_SIZE) as u32
+    }
+}
+
+// Convert a host file name to the CP/M name and type padded with spaces, None if it doesn't fit 8.3
+fn to_cpm_name(host_name: &str) -> Option<[u8; 11]> {
+    let (name, ext) = host_name.rsplit_once('.').unwrap_or((host_name, ""));
+    let valid = |part: &str| part.bytes().all(|byte| byte.is_ascii_graphic() && !DELIMITERS.contains(&byte));
+
+    if name.is_empty() || name.len() > 8 || ext.len() > 3 || !valid(name) || !valid(ext) {
+        return None;
+    }
+
+    let mut cpm_name = [b' '; 11];
+    cpm_name[..name.len()].copy_from_slice(name.to_ascii_uppercase().as_bytes());
+    cpm_name[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+
+    Some(cpm_name)
+}
+
+// Host file name for a new file, e.g. "FOO.TXT" or "FOO" without a type
+fn to_host_name(name: &[u8; 11]) -> String {
+    let base = String::from_utf8_lossy(&name[..8]).trim_end().to_string();
+    let ext = String::from_utf8_lossy(&name[8..]).trim_end().to_string();
+
+    if ext.is_empty() { base } else { format!("{base}.{ext}") }
+}
+
+// A '?' in the pattern matches any character
+fn matches_pattern(pattern: &[u8; 11], name: &[u8; 11]) -> bool {
+    pattern.iter().zip(name).all(|(p, n)| *p == b'?' || p == n)
+}
+
+// Parse a command line argument like "B:FOO.TXT" or "*.COM" into the drive and the name of an FCB. A '*' fills the
+// rest of the name or the type with '?'.
+pub fn parse_fcb_name(arg: &str) -> [u8; 16] {
+    let mut fcb = [0x00; 16];
+    fcb[1..12].fill(b' ');
+
+    let arg = arg.as_bytes();
+    let arg = match arg {
+        [drive, b':', rest @ ..] if drive.is_ascii_uppercase() => {
+            fcb[0] = drive - b'A' + 1;
+            rest
+        },
+        _ => arg,
+    };
+
+    let (name, ext) = match arg.iter().position(|byte| *byte == b'.') {
+        Some(i) => (&arg[..i], &arg[i + 1..]),
+        None => (arg, &[][..]),
+    };
+
+    let (name_field, ext_field) = fcb[1..12].split_at_mut(8);
+
+    for (field, part) in [(name_field, name), (ext_field, ext)] {
+        for (i, byte) in part.iter().take(field.len()).enumerate() {
+            if *byte == b'*' {
+                field[i..].fill(b'?');
+                break;
+            }
+
+            field[i] = *byte;
+        }
+    }
+
+    fcb
+}
+
+impl CpmMachine {
+    // Handle a call of the BDOS entry. Return false if it has to wait for console input.
+    pub(super) fn bdos_call(&mut self) -> bool {
+        let function = self.cpu.get_reg("C");
+        let param = self.cpu.get_reg_pair("DE");
+
+        let result: u16 = match function {
+            // System reset
+            0 => {
+                self.exit();
+                0x0000
+            },
+
+            // Console input, echoed
+            1 => match self.input.pop_front() {
+                Some(byte) => {
+                    self.echo(byte);
+                    byte as u16
+                },
+                None => return false,
+            },
+
+            // Console output
+            2 => {
+                self.output.push(param as u8);
+                0x0000
+            },
+
+            // Reader input
+            3 => EOF as u16,
+
+            // Punch and list output
+            4 | 5 => 0x0000,
+
+            // Direct console I/O: input without waiting or echo, console status, or output
+            6 => match param as u8 {
+                0xFF => self.input.pop_front().unwrap_or(0x00) as u16,
+                0xFE => self.console_status() as u16,
+                byte => {
+                    self.output.push(byte);
+                    0x0000
+                },
+            },
+
+            // Get and set IOBYTE
+            7 => self.cpu.read_byte(IOBYTE) as u16,
+            8 => {
+                self.cpu.write_byte(IOBYTE, param as u8);
+                0x0000
+            },
+
+            // Print the string ending with '$'
+            9 => {
+                let mut addr = param;
+
+                while self.cpu.read_byte(addr) != b'$' {
+                    self.output.push(self.cpu.read_byte(addr));
+                    addr = addr.wrapping_add(1);
+                }
+
+                0x0000
+            },
+
+            // Read console buffer
+            10 => {
+                if !self.read_buffer() {
+                    return false;
+                }
+
+                0x0000
+            },
+
+            // Console status
+            11 => self.console_status() as u16,
+
+            // Version number, CP/M 2.2
+            12 => 0x0022,
+
+            // Reset disk system
+            13 => {
+                self.dma = DEFAULT_DMA;
+                self.select_drive(0);
+                0x0000
+            },
+
+            // Select disk, all drives are the host directory
+            14 => {
+                self.select_drive(param as u8 & 0x0F);
+                0x0000
+            },
+
+            15 => self.open_file(param) as u16,
+            16 => self.close_file(param) as u16,
+            17 => self.search_first(param) as u16,
+            18 => self.search_next() as u16,
+            19 => self.delete_file(param) as u16,
+            20 => self.read_sequential(param) as u16,
+            21 => self.write_sequential(param) as u16,
+            22 => self.make_file(param) as u16,
+            23 => self.rename_file(param) as u16,
+
+            // Login vector, the current drive and A: are logged in
+            24 => 0x0001 | 1 << self.drive,
+
+            // Current disk
+            25 => self.drive as u16,
+
+            // Set DMA address
+            26 => {
+                self.dma = param;
+                0x0000
+            },
+
+            // Allocation vector and disk parameter block addresses, for programs that compute the free space
+            27 => ALLOCATION_VECTOR,
+            31 => DPB,
+
+            // Write protect disk, read only vector and set file attributes
+            28 | 29 => 0x0000,
+            30 => if self.find_files(param).is_empty() { NOT_FOUND as u16 } else { OK as u16 },
+
+            // Get or set the user code
+            32 => {
+                if param as u8 == 0xFF {
+                    self.user as u16
+                } else {
+                    self.user = param as u8 & 0x0F;
+                    self.cpu.write_byte(DRIVE_USER, self.user << 4 | self.drive);
+                    0x0000
+                }
+            },
+
+            33 => self.read_random(param) as u16,
+            34 | 40 => self.write_random(param) as u16,
+            35 => self.compute_file_size(param) as u16,
+
+            // Set random record from the sequential position
+            36 => {
+                let record = self.fcb_position(param);
+                self.set_random_record(param, record);
+                0x0000
+            },
+
+            // Reset drive
+            37 => 0x0000,
+
+            _ => NOT_FOUND as u16,
+        };
+
+        self.set_result(result);
+        true
+    }
+
+    fn select_drive(&mut self, drive: u8) {
+        self.drive = drive;
+        self.cpu.write_byte(DRIVE_USER, self.user << 4 | drive);
+    }
+
+    // Name and type of the FCB without the attribute bits
+    fn fcb_name(&self, fcb: u16) -> [u8; 11] {
+        let mut name = [0x00; 11];
+
+        for (i, byte) in name.iter_mut().enumerate() {
+            *byte = self.cpu.read_byte(fcb.wrapping_add(1 + i as u16)) & 0x7F;
+        }
+
+        name.make_ascii_uppercase();
+        name
+    }
+
+    fn write_fcb_name(&mut self, fcb: u16, name: &[u8; 11]) {
+        for (i, byte) in name.iter().enumerate() {
+            self.cpu.write_byte(fcb.wrapping_add(1 + i as u16), *byte);
+        }
+    }
+
+    // Host files matching the name of the FCB, which may contain '?', sorted by name
+    fn find_files(&self, fcb: u16) -> Vec<HostFile> {
+        self.find_files_named(&self.fcb_name(fcb))
+    }
+
+    fn find_files_named(&self, pattern: &[u8; 11]) -> Vec<HostFile> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        let mut files: Vec<HostFile> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+                let host_name = entry.file_name().into_string().ok()?;
+                let name = to_cpm_name(&host_name).filter(|name| matches_pattern(pattern, name))?;
+
+                Some(HostFile { host_name, name, size: metadata.len() })
+            })
+            .collect();
+
+        files.sort_by_key(|file| file.name);
+        files
+    }
+
+    // Host file of the FCB, None if it doesn't exist
+    fn find_file(&self, fcb: u16) -> Option<HostFile> {
+        self.find_files(fcb).into_iter().next()
+    }
+
+    // Record number of the sequential position, from the module, the extent and the current record
+    fn fcb_position(&self, fcb: u16) -> u32 {
+        let extent = self.cpu.read_byte(fcb + FCB_EXTENT) as u32 & 0x1F;
+        let module = self.cpu.read_byte(fcb + FCB_S2) as u32 & 0x3F;
+        let record = self.cpu.read_byte(fcb + FCB_CURRENT_RECORD) as u32;
+
+        (module * 32 + extent) * RECORDS_PER_EXTENT + record
+    }
+
+    // Move the sequential position to the record and update the record count of its extent
+    fn set_fcb_position(&mut self, fcb: u16, record: u32, file_records: u32) {
+        let extent = record / RECORDS_PER_EXTENT;
+        let extent_records = file_records.saturating_sub(extent * RECORDS_PER_EXTENT).min(RECORDS_PER_EXTENT);
+
+        self.cpu.write_byte(fcb + FCB_EXTENT, (extent & 0x1F) as u8);
+        self.cpu.write_byte(fcb + FCB_S2, (extent >> 5) as u8);
+        self.cpu.write_byte(fcb + FCB_RECORD_COUNT, extent_records as u8);
+        self.cpu.write_byte(fcb + FCB_CURRENT_RECORD, (record % RECORDS_PER_EXTENT) as u8);
+    }
+
+    fn random_record(&self, fcb: u16) -> (u32, bool) {
+        let low = self.cpu.read_byte(fcb + FCB_RANDOM_RECORD) as u32;
+        let high = self.cpu.read_byte(fcb + FCB_RANDOM_RECORD + 1) as u32;
+        let overflow = self.cpu.read_byte(fcb + FCB_RANDOM_RECORD + 2) != 0;
+
+        (high << 8 | low, overflow)
+    }
+
+    fn set_random_record(&mut self, fcb: u16, record: u32) {
+        self.cpu.write_byte(fcb + FCB_RANDOM_RECORD, record as u8);
+        self.cpu.write_byte(fcb + FCB_RANDOM_RECORD + 1, (record >> 8) as u8);
+        self.cpu.write_byte(fcb + FCB_RANDOM_RECORD + 2, (record >> 16) as u8);
+    }
+
+    // Read the record into the DMA buffer, the last record of a file is padded with EOF characters
+    fn read_record(&mut self, file: &HostFile, record: u32) -> u8 {
+        let mut buffer = [EOF; RECORD_SIZE as usize];
+
+        let read = File::open(self.dir.join(&file.host_name)).and_then(|mut host_file| {
+            host_file.seek(SeekFrom::Start(record as u64 * RECORD_SIZE))?;
+            host_file.read(&mut buffer)
+        });
+
+        match read {
+            Ok(len) if len > 0 => {
+                for (i, byte) in buffer.iter().enumerate() {
+                    self.cpu.write_byte(self.dma.wrapping_add(i as u16), *byte);
+                }
+
+                OK
+            },
+            _ => END_OF_FILE,
+        }
+    }
+
+    // Write the DMA buffer into the record, the file grows as needed
+    fn write_record(&mut self, file: &HostFile, record: u32) -> u8 {
+        let buffer: Vec<u8> = (0..RECORD_SIZE as u16).map(|i| self.cpu.read_byte(self.dma.wrapping_add(i))).collect();
+
+        let written = OpenOptions::new().write(true).open(self.dir.join(&file.host_name)).and_then(|mut host_file| {
+            host_file.seek(SeekFrom::Start(record as u64 * RECORD_SIZE))?;
+            host_file.write_all(&buffer)
+        });
+
+        if written.is_ok() { OK } else { DISK_FULL }
+    }
+
+    // BDOS 15 - Open file, the name may contain '?' and is replaced by the name of the found file
+    fn open_file(&mut self, fcb: u16) -> u8 {
+        let Some(file) = self.find_file(fcb) else {
+            return NOT_FOUND;
+        };
+
+        self.write_fcb_name(fcb, &file.name);
+
+        let extent = self.cpu.read_byte(fcb + FCB_EXTENT) as u32 & 0x1F;
+        let record = self.cpu.read_byte(fcb + FCB_CURRENT_RECORD) as u32;
+        self.cpu.write_byte(fcb + FCB_S2, 0x00);
+        self.set_fcb_position(fcb, extent * RECORDS_PER_EXTENT + record, file.records());
+
+        OK
+    }
+
+    // BDOS 16 - Close file, the data is already in the host file
+    fn close_file(&mut self, fcb: u16) -> u8 {
+        if self.find_file(fcb).is_some() { OK } else { NOT_FOUND }
+    }
+
+    // BDOS 17 - Search for the first file matching the FCB. A '?' drive matches all files.
+    fn search_first(&mut self, fcb: u16) -> u8 {
+        let mut files = self.find_files(fcb);
+        files.reverse();
+
+        self.search = files;
+        self.search_next()
+    }
+
+    // BDOS 18 - Write the directory entry of the next found file into the DMA buffer
+    fn search_next(&mut self) -> u8 {
+        let Some(file) = self.search.pop() else {
+            return NOT_FOUND;
+        };
+
+        // The entry describes the last extent of the file
+        let records = file.records();
+        let last_extent = records.saturating_sub(1) / RECORDS_PER_EXTENT;
+        let extent_records = records - last_extent * RECORDS_PER_EXTENT;
+
+        let mut entry = [EMPTY_ENTRY; RECORD_SIZE as usize];
+        entry[..32].fill(0x00);
+        entry[0] = self.user;
+        entry[1..12].copy_from_slice(&file.name);
+        entry[12] = (last_extent & 0x1F) as u8;
+        entry[14] = (last_extent >> 5) as u8;
+        entry[15] = extent_records as u8;
+
+        for (i, byte) in entry.iter().enumerate() {
+            self.cpu.write_byte(self.dma.wrapping_add(i as u16), *byte);
+        }
+
+        // Index of the entry in the DMA buffer
+        OK
+    }
+
+    // BDOS 19 - Delete all files matching the FCB
+    fn delete_file(&mut self, fcb: u16) -> u8 {
+        let files = self.find_files(fcb);
+        let deleted = files.iter().filter(|file| fs::remove_file(self.dir.join(&file.host_name)).is_ok()).count();
+
+        if deleted > 0 { OK } else { NOT_FOUND }
+    }
+
+    // BDOS 20 - Read the record at the sequential position and move to the next one
+    fn read_sequential(&mut self, fcb: u16) -> u8 {
+        let Some(file) = self.find_file(fcb) else {
+            return NOT_FOUND;
+        };
+
+        let record = self.fcb_position(fcb);
+        let result = self.read_record(&file, record);
+
+        if result == OK {
+            self.set_fcb_position(fcb, record + 1, file.records());
+        }
+
+        result
+    }
+
+    // BDOS 21 - Write the record at the sequential position and move to the next one
+    fn write_sequential(&mut self, fcb: u16) -> u8 {
+        let Some(file) = self.find_file(fcb) else {
+            return NOT_FOUND;
+        };
+
+        let record = self.fcb_position(fcb);
+        let result = self.write_record(&file, record);
+
+        if result == OK {
+            self.set_fcb_position(fcb, record + 1, file.records().max(record + 1));
+        }
+
+        result
+    }
+
+    // BDOS 22 - Create an empty file, replacing an existing one
+    fn make_file(&mut self, fcb: u16) -> u8 {
+        let name = self.fcb_name(fcb);
+        if name.contains(&b'?') {
+            return NOT_FOUND;
+        }
+
+        let host_name = match self.find_file(fcb) {
+            Some(file) => file.host_name,
+            None => to_host_name(&name),
+        };
+
+        if File::create(self.dir.join(host_name)).is_err() {
+            return NOT_FOUND;
+        }
+
+        self.cpu.write_byte(fcb + FCB_EXTENT, 0x00);
+        self.cpu.write_byte(fcb + FCB_S2, 0x00);
+        self.cpu.write_byte(fcb + FCB_RECORD_COUNT, 0x00);
+
+        OK
+    }
+
+    // BDOS 23 - Rename the file to the name at FCB + 16
+    fn rename_file(&mut self, fcb: u16) -> u8 {
+        let mut new_name = [0x00; 11];
+        for (i, byte) in new_name.iter_mut().enumerate() {
+            *byte = self.cpu.read_byte(fcb + FCB_NEW_NAME + i as u16) & 0x7F;
+        }
+        new_name.make_ascii_uppercase();
+
+        let Some(file) = self.find_file(fcb) else {
+            return NOT_FOUND;
+        };
+
+        if new_name.contains(&b'?') || !self.find_files_named(&new_name).is_empty() {
+            return NOT_FOUND;
+        }
+
+        match fs::rename(self.dir.join(file.host_name), self.dir.join(to_host_name(&new_name))) {
+            Ok(()) => OK,
+            Err(_) => NOT_FOUND,
+        }
+    }
+
+    // BDOS 33 - Read the random record, the sequential position is moved to it
+    fn read_random(&mut self, fcb: u16) -> u8 {
+        let (record, overflow) = self.random_record(fcb);
+        if overflow {
+            return SEEK_PAST_END;
+        }
+
+        let Some(file) = self.find_file(fcb) else {
+            return NOT_FOUND;
+        };
+
+        self.set_fcb_position(fcb, record, file.records());
+        self.read_record(&file, record)
+    }
+
+    // BDOS 34 and 40 - Write the random record, the sequential position is moved to it. The host file system fills
+    // the skipped records with zeros.
+    fn write_random(&mut self, fcb: u16) -> u8 {
+        let (record, overflow) = self.random_record(fcb);
+        if overflow {
+            return SEEK_PAST_END;
+        }
+
+        let Some(file) = self.find_file(fcb) else {
+            return NOT_FOUND;
+        };
+
+        let result = self.write_record(&file, record);
+        self.set_fcb_position(fcb, record, file.records().max(record + 1));
+
+        result
+    }
+
+    // BDOS 35 - Set the random record to the size of the file in records
+    fn compute_file_size(&mut self, fcb: u16) -> u8 {
+        let Some(file) = self.find_file(fcb) else {
+            return NOT_FOUND;
+        };
+
+        self.set_random_record(fcb, file.records());
+        OK
+    }
+}
@@ -0,0 +1,265 @@
+/*
+CP/M disk images and the disk controller
+
+Images are raw dumps of 8" single sided single density disks in the IBM 3740 format: 77 tracks of 26 sectors of
+128 bytes, 256256 bytes in total, with the sectors of each track in physical order. CP/M reads the sectors of a track
+in the order of the skew table, so that the disk has turned far enough for the next sector while the last one is
+processed. Tracks 0 and 1 are the system tracks with the boot loader and CCP/BDOS, the file system starts on track 2.
+
+The controller uses programmed I/O, the sector is transferred through the data port one byte at a time:
+    OUT 0x40    Select drive 0-3
+    OUT 0x41    Track 0-76
+    OUT 0x42    Sector 1-26
+    OUT 0x43    Command, 0 reads the sector into the buffer, 1 writes the buffer into the sector
+    IN 0x40     Status of the last command, 0 for success
+    IN/OUT 0x44 Next byte of the sector buffer, the position is reset by the commands
+
+Writes go through to the image file immediately.
+*/
+
+use std::any::Any;
+use std::fs::{read, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::devices::Device;
+use crate::errors::EmulatorError;
+use crate::snapshot::{StateReader, StateWriter};
+
+pub const TRACKS: usize = 77;
+pub const SECTORS_PER_TRACK: usize = 26;
+pub const SECTOR_SIZE: usize = 128;
+pub const IMAGE_SIZE: usize = TRACKS * SECTORS_PER_TRACK * SECTOR_SIZE;
+
+pub const SYSTEM_TRACKS: usize = 2;
+pub const MAX_DRIVES: usize = 4;
+
+// Physical sector of each logical sector, the standard skew of 6
+pub const SKEW: [u8; SECTORS_PER_TRACK] = [
+    1, 7, 13, 19, 25, 5, 11, 17, 23, 3, 9, 15, 21, 2, 8, 14, 20, 26, 6, 12, 18, 24, 4, 10, 16, 22,
+];
+
+pub const SELECT_PORT: u8 = 0x40;
+pub const STATUS_PORT: u8 = 0x40;
+pub const TRACK_PORT: u8 = 0x41;
+pub const SECTOR_PORT: u8 = 0x42;
+pub const COMMAND_PORT: u8 = 0x43;
+pub const DATA_PORT: u8 = 0x44;
+
+pub const COMMAND_READ: u8 = 0;
+pub const COMMAND_WRITE: u8 = 1;
+
+pub const STATUS_OK: u8 = 0x00;
+pub const STATUS_ERROR: u8 = 0x01;
+
+
+pub struct DiskImage {
+    path: PathBuf,
+    data: Vec<u8>,
+}
+
+impl DiskImage {
+    pub fn open(path: &Path) -> Result<Self, EmulatorError> {
+        let data = read(path)?;
+
+        if data.len() != IMAGE_SIZE {
+            return Err(EmulatorError::DiskImageInvalid(path.display().to_string(), data.len()));
+        }
+
+        Ok(DiskImage { path: path.to_path_buf(), data })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Offset of a physical sector in the image, None if the track or the sector doesn't exist
+    fn offset(track: u8, sector: u8) -> Option<usize> {
+        if track as usize >= TRACKS || sector == 0 || sector as usize > SECTORS_PER_TRACK {
+            return None;
+        }
+
+        Some((track as usize * SECTORS_PER_TRACK + sector as usize - 1) * SECTOR_SIZE)
+    }
+
+    pub fn read_sector(&self, track: u8, sector: u8) -> Option<&[u8]> {
+        let offset = Self::offset(track, sector)?;
+        Some(&self.data[offset..offset + SECTOR_SIZE])
+    }
+
+    pub fn write_sector(&mut self, track: u8, sector: u8, bytes: &[u8; SECTOR_SIZE]) -> Result<(), EmulatorError> {
+        let offset = Self::offset(track, sector).ok_or(EmulatorError::DiskSectorInvalid(track, sector))?;
+        self.data[offset..offset + SECTOR_SIZE].copy_from_slice(bytes);
+
+        let mut file = OpenOptions::new().write(true).open(&self.path)?;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.write_all(bytes)?;
+
+        Ok(())
+    }
+
+    // Bytes of the system tracks in sector order, skipping the boot loader in the first sector
+    pub fn system(&self) -> Vec<u8> {
+        let mut system = Vec::new();
+
+        for track in 0..SYSTEM_TRACKS as u8 {
+            for sector in 1..=SECTORS_PER_TRACK as u8 {
+                if track == 0 && sector == 1 {
+                    continue;
+                }
+
+                system.extend_from_slice(self.read_sector(track, sector).unwrap_or(&[]));
+            }
+        }
+
+        system
+    }
+}
+
+
+pub struct DiskController {
+    drives: Vec<DiskImage>,
+
+    drive: u8,
+    track: u8,
+    sector: u8,
+    status: u8,
+
+    buffer: [u8; SECTOR_SIZE],
+    pos: usize,
+}
+
+impl DiskController {
+    pub fn new(drives: Vec<DiskImage>) -> Self {
+        DiskController {
+            drives,
+
+            drive: 0,
+            track: 0,
+            sector: 1,
+            status: STATUS_OK,
+
+            buffer: [0x00; SECTOR_SIZE],
+            pos: 0,
+        }
+    }
+
+    pub fn drive_count(&self) -> usize {
+        self.drives.len()
+    }
+
+    // System tracks of the boot disk in drive A:
+    pub fn system(&self) -> Option<Vec<u8>> {
+        self.drives.first().map(|disk| disk.system())
+    }
+
+    pub fn select(&mut self, drive: u8) {
+        self.drive = drive;
+    }
+
+    pub fn set_track(&mut self, track: u8) {
+        self.track = track;
+    }
+
+    pub fn set_sector(&mut self, sector: u8) {
+        self.sector = sector;
+    }
+
+    // Run a read or write command, return the status
+    pub fn command(&mut self, command: u8) -> u8 {
+        let (drive, track, sector) = (self.drive, self.track, self.sector);
+
+        self.status = match (self.drives.get_mut(drive as usize), command) {
+            (Some(disk), COMMAND_READ) => match disk.read_sector(track, sector) {
+                Some(bytes) => {
+                    self.buffer.copy_from_slice(bytes);
+                    STATUS_OK
+                },
+                None => STATUS_ERROR,
+            },
+            (Some(disk), COMMAND_WRITE) => match disk.write_sector(track, sector, &self.buffer) {
+                Ok(()) => STATUS_OK,
+                Err(_) => STATUS_ERROR,
+            },
+            _ => STATUS_ERROR,
+        };
+
+        self.pos = 0;
+        self.status
+    }
+
+    pub fn buffer(&self) -> &[u8; SECTOR_SIZE] {
+        &self.buffer
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut [u8; SECTOR_SIZE] {
+        &mut self.buffer
+    }
+}
+
+impl Device for DiskController {
+    fn name(&self) -> &str {
+        "disk"
+    }
+
+    fn input(&mut self, port: u8, _cycles: u64) -> Option<u8> {
+        match port {
+            STATUS_PORT => Some(self.status),
+            DATA_PORT => {
+                let byte = self.buffer[self.pos];
+                self.pos = (self.pos + 1) % SECTOR_SIZE;
+                Some(byte)
+            },
+            _ => None,
+        }
+    }
+
+    fn output(&mut self, port: u8, val: u8, _cycles: u64) -> bool {
+        match port {
+            SELECT_PORT => self.select(val),
+            TRACK_PORT => self.set_track(val),
+            SECTOR_PORT => self.set_sector(val),
+            COMMAND_PORT => {
+                self.command(val);
+            },
+            DATA_PORT => {
+                self.buffer[self.pos] = val;
+                self.pos = (self.pos + 1) % SECTOR_SIZE;
+            },
+            _ => return false,
+        }
+
+        true
+    }
+
+    // The disk contents are in the image files, only the controller registers are saved
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.drive);
+        writer.write_u8(self.track);
+        writer.write_u8(self.sector);
+        writer.write_u8(self.status);
+        writer.write_u16(self.pos as u16);
+
+        for byte in self.buffer {
+            writer.write_u8(byte);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.drive = reader.read_u8()?;
+        self.track = reader.read_u8()?;
+        self.sector = reader.read_u8()?;
+        self.status = reader.read_u8()?;
+        self.pos = reader.read_u16()? as usize % SECTOR_SIZE;
+
+        for byte in self.buffer.iter_mut() {
+            *byte = reader.read_u8()?;
+        }
+
+        Ok(())
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,284 @@
+/*
+Interactive command line debugger
+
+Commands:
+    s [n]           Step n instructions forwards (default 1)
+    f [n]           Run n frames with the interrupts of the machine (default 1)
+    b [n]           Step n instructions backwards (default 1)
+    c               Continue until a breakpoint or HLT
+    rc              Continue backwards until a breakpoint or the start of the history
+    bp <addr>       Toggle breakpoint at address
+    goto <index>    Move to instruction index in the execution history
+    w <addr>        Show which instruction last wrote to the address in the rewind history
+    lw <addr> [len] Show the last writers of the addresses since the start of the run
+    report <file> [start] [end]
+                    Export the last writers of the address range (default 0000-FFFF) as a report
+    m <addr> [len]  Dump memory
+    ss [type] [start] [end]
+                    Start a memory search of u8, u16, bcd or bcd16 values (default u8 over 0000-FFFF)
+    sf <rel> [value]
+                    Keep the search candidates that are eq, ne, inc or dec compared to the value or to the
+                    previous search snapshot
+    sl [n]          List the first n search candidates (default 20)
+    fz [addr value [type]]
+                    Freeze the address to the value (default u8) every frame, list the frozen addresses without
+                    arguments
+    uf <addr>       Unfreeze the address
+    prof            Show the profile report, when started with profiling enabled
+    r               Show registers
+    q               Quit
+*/
+
+use std::io::{stdin, stdout, Write};
+use std::path::Path;
+
+use emulator::emulator::Intel8080;
+use emulator::emulator::cheats::{MemorySearch, Relation, ValueType};
+use emulator::machine::Machine;
+
+// Default amount of search candidates listed
+const SEARCH_LIST_LINES: usize = 20;
+
+// Amount of instructions kept in the rewind history and the distance between full checkpoints
+const REWIND_CAPACITY: usize = 1_000_000;
+const CHECKPOINT_INTERVAL: u64 = 50_000;
+
+
+fn parse_hex(s: &str) -> Option<u16> {
+    u16::from_str_radix(s.trim_start_matches("0x").trim_start_matches("0X"), 16).ok()
+}
+
+// Decimal or 0x prefixed hexadecimal value
+fn parse_value(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn print_position(cpu: &Intel8080) {
+    let index = cpu.rewind_buffer().map_or(0, |rewind| rewind.position());
+    println!("#{index:<8} {}", cpu.dump_registers());
+
+    if let Err(err) = cpu.check_undocumented() {
+        println!("{err}");
+    }
+}
+
+fn dump_memory(cpu: &Intel8080, addr: u16, len: u16) {
+    for row in (0..len).step_by(16) {
+        let start = addr.wrapping_add(row);
+        print!("{start:04X}:");
+
+        for i in 0..16.min(len - row) {
+            print!(" {:02X}", cpu.read_byte(start.wrapping_add(i)));
+        }
+
+        println!();
+    }
+}
+
+pub fn run(machine: &mut dyn Machine) {
+    let cpu = machine.cpu_mut();
+    cpu.enable_rewind(REWIND_CAPACITY, CHECKPOINT_INTERVAL);
+    cpu.enable_provenance();
+
+    let mut breakpoints: Vec<u16> = Vec::new();
+    let mut search: Option<MemorySearch> = None;
+    let mut line = String::new();
+
+    print_position(cpu);
+
+    loop {
+        print!("> ");
+        stdout().flush().ok();
+
+        line.clear();
+        if stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let count: usize = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(1);
+        let addr: Option<u16> = args.get(1).and_then(|a| parse_hex(a));
+
+        if args.first() == Some(&"f") {
+            for _ in 0..count {
+                machine.run_frame();
+            }
+        }
+
+        let cpu = machine.cpu_mut();
+
+        match args.first().copied() {
+            Some("s") => {
+                for _ in 0..count {
+                    if cpu.is_halted() {
+                        break;
+                    }
+                    cpu.step();
+                }
+                print_position(cpu);
+            },
+            Some("b") => {
+                for _ in 0..count {
+                    if !cpu.step_back() {
+                        println!("Start of the history reached");
+                        break;
+                    }
+                }
+                print_position(cpu);
+            },
+            Some("c") => {
+                loop {
+                    cpu.step();
+                    if cpu.is_halted() || breakpoints.contains(&cpu.get_pc()) {
+                        break;
+                    }
+                }
+                print_position(cpu);
+            },
+            Some("rc") => {
+                if !cpu.run_back(&breakpoints) {
+                    println!("Start of the history reached");
+                }
+                print_position(cpu);
+            },
+            Some("bp") => match addr {
+                Some(addr) => {
+                    if let Some(i) = breakpoints.iter().position(|bp| *bp == addr) {
+                        breakpoints.remove(i);
+                        println!("Breakpoint removed from {addr:04X}");
+                    } else {
+                        breakpoints.push(addr);
+                        println!("Breakpoint set at {addr:04X}");
+                    }
+                },
+                None => println!("Usage: bp <addr>"),
+            },
+            Some("goto") => match args.get(1).and_then(|i| i.parse::<u64>().ok()) {
+                Some(index) => {
+                    if let Err(e) = cpu.rewind_to(index) {
+                        println!("{e}");
+                    }
+                    print_position(cpu);
+                },
+                None => println!("Usage: goto <index>"),
+            },
+            Some("w") => match addr {
+                Some(addr) => match cpu.last_writer(addr) {
+                    Some(info) => println!(
+                        "{addr:04X} last written by instruction #{} at PC={:04X} (cycle {}): {:02X} -> {:02X}",
+                        info.index, info.pc, info.cycles, info.old, info.new
+                    ),
+                    None => println!("{addr:04X} not written in the recorded history"),
+                },
+                None => println!("Usage: w <addr>"),
+            },
+            Some("lw") => match addr {
+                Some(addr) => {
+                    let len = args.get(2).and_then(|l| parse_hex(l)).unwrap_or(1);
+
+                    for i in 0..len {
+                        let addr = addr.wrapping_add(i);
+                        match cpu.last_write(addr) {
+                            Some(record) => println!(
+                                "{addr:04X} = {:02X} last written at PC={:04X} (cycle {})",
+                                cpu.read_byte(addr), record.pc, record.cycles
+                            ),
+                            None => println!("{addr:04X} = {:02X} never written", cpu.read_byte(addr)),
+                        }
+                    }
+                },
+                None => println!("Usage: lw <addr> [len]"),
+            },
+            Some("report") => match args.get(1) {
+                Some(file) => {
+                    let start = args.get(2).and_then(|a| parse_hex(a)).unwrap_or(0x0000);
+                    let end = args.get(3).and_then(|a| parse_hex(a)).unwrap_or(0xFFFF);
+
+                    match cpu.write_provenance_report(Path::new(file), start, end) {
+                        Ok(()) => println!("Report written to '{file}'"),
+                        Err(e) => println!("{e}"),
+                    }
+                },
+                None => println!("Usage: report <file> [start] [end]"),
+            },
+            Some("m") => match addr {
+                Some(addr) => {
+                    let len = args.get(2).and_then(|l| parse_hex(l)).unwrap_or(0x40);
+                    dump_memory(cpu, addr, len);
+                },
+                None => println!("Usage: m <addr> [len]"),
+            },
+            Some("ss") => {
+                let value_type = args.get(1).and_then(|t| ValueType::parse(t));
+                let start = args.get(2).and_then(|a| parse_hex(a)).unwrap_or(0x0000);
+                let end = args.get(3).and_then(|a| parse_hex(a)).unwrap_or(0xFFFF);
+
+                match value_type.or(args.get(1).is_none().then_some(ValueType::U8)) {
+                    Some(value_type) => {
+                        let new_search = MemorySearch::new(cpu, value_type, start, end);
+                        println!("{} candidates", new_search.candidates().len());
+                        search = Some(new_search);
+                    },
+                    None => println!("Usage: ss [u8|u16|bcd|bcd16] [start] [end]"),
+                }
+            },
+            Some("sf") => match (search.as_mut(), args.get(1).and_then(|r| Relation::parse(r))) {
+                (Some(search), Some(relation)) => {
+                    let constant = args.get(2).and_then(|v| parse_value(v));
+                    println!("{} candidates left", search.filter(cpu, relation, constant));
+                },
+                (None, _) => println!("No search started, use ss"),
+                (_, None) => println!("Usage: sf <eq|ne|inc|dec> [value]"),
+            },
+            Some("sl") => match search.as_ref() {
+                Some(search) => {
+                    let lines = args.get(1).and_then(|n| n.parse().ok()).unwrap_or(SEARCH_LIST_LINES);
+
+                    for addr in search.candidates().iter().take(lines) {
+                        println!("{addr:04X} = {}", search.snapshot_value(*addr).unwrap_or(0));
+                    }
+
+                    if search.candidates().len() > lines {
+                        println!("... {} more", search.candidates().len() - lines);
+                    }
+                },
+                None => println!("No search started, use ss"),
+            },
+            Some("fz") => match (addr, args.get(2).and_then(|v| parse_value(v))) {
+                (Some(addr), Some(val)) => {
+                    let value_type = args.get(3).and_then(|t| ValueType::parse(t)).unwrap_or(ValueType::U8);
+                    cpu.freeze(addr, value_type, val);
+                    cpu.apply_freezes();
+                    println!("{addr:04X} frozen to {val} ({value_type:?})");
+                },
+                _ if args.len() == 1 => {
+                    for freeze in cpu.freezes() {
+                        println!("{:04X} = {} ({:?})", freeze.addr, freeze.val, freeze.value_type);
+                    }
+                },
+                _ => println!("Usage: fz [addr value [u8|u16|bcd|bcd16]]"),
+            },
+            Some("uf") => match addr {
+                Some(addr) => {
+                    if cpu.unfreeze(addr) {
+                        println!("{addr:04X} unfrozen");
+                    } else {
+                        println!("{addr:04X} is not frozen");
+                    }
+                },
+                None => println!("Usage: uf <addr>"),
+            },
+            Some("prof") => match cpu.profile_report(20) {
+                Ok(report) => println!("{report}"),
+                Err(e) => println!("{e}"),
+            },
+            Some("r") | Some("f") => print_position(cpu),
+            Some("q") => break,
+            Some(cmd) => println!("Unknown command '{cmd}'"),
+            None => {},
+        }
+    }
+}
@@ -0,0 +1,191 @@
+/*
+Devices attached to the Intel 8080 IN/OUT ports
+*/
+
+use std::any::Any;
+
+use crate::errors::EmulatorError;
+use crate::snapshot::{StateReader, StateWriter};
+
+
+// A device that is wired to one or more of the 256 I/O ports of the CPU
+pub trait Device {
+    // Unique name of the device, used to match the device state in save states
+    fn name(&self) -> &str;
+
+    // IN port - Return Some(byte) if the device answers to the port, None otherwise
+    fn input(&mut self, port: u8, cycles: u64) -> Option<u8>;
+
+    // OUT port - Return true if the device handled the written byte, false otherwise
+    fn output(&mut self, port: u8, val: u8, cycles: u64) -> bool;
+
+    // Serialize the internal state of the device into a save state
+    fn save_state(&self, writer: &mut StateWriter);
+
+    // Restore the internal state of the device from a save state
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError>;
+
+    // Needed for getting the concrete device back from the CPU e.g. for setting input bits
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+
+/*
+External 16-bit shift register used by the Midway 8080 boards (e.g. Space Invaders), since the 8080 lacks a barrel
+shifter. Writing to the data port shifts the new byte in from the top:
+    15 14 13 12 11 10  9  8  7  6  5  4  3  2  1  0
+    <----- new byte ----->  <----- old byte ----->
+
+Reading the result port returns the 8 bits starting from bit (7 - offset) upwards. Some boards, e.g. Sea Wolf, have a
+second result port that returns them with the bits reversed when bit 3 of the offset is set, for drawing the sprites
+mirrored.
+*/
+pub struct ShiftRegister {
+    data: u16,
+    offset: u8,
+    reverse: bool,
+
+    offset_port: u8,
+    data_port: u8,
+    result_port: u8,
+    reversed_port: Option<u8>,
+}
+
+impl ShiftRegister {
+    pub fn new(offset_port: u8, data_port: u8, result_port: u8) -> Self {
+        ShiftRegister {
+            data: 0x0000,
+            offset: 0,
+            reverse: false,
+
+            offset_port,
+            data_port,
+            result_port,
+            reversed_port: None,
+        }
+    }
+
+    // Add the port that returns the result reversed when bit 3 of the offset is set
+    pub fn with_reversed_result(mut self, port: u8) -> Self {
+        self.reversed_port = Some(port);
+        self
+    }
+
+    fn result(&self) -> u8 {
+        (self.data >> (8 - self.offset)) as u8
+    }
+}
+
+impl Device for ShiftRegister {
+    fn name(&self) -> &str {
+        "shift_register"
+    }
+
+    fn input(&mut self, port: u8, _cycles: u64) -> Option<u8> {
+        if port == self.result_port {
+            Some(self.result())
+        } else if Some(port) == self.reversed_port {
+            Some(if self.reverse { self.result().reverse_bits() } else { self.result() })
+        } else {
+            None
+        }
+    }
+
+    fn output(&mut self, port: u8, val: u8, _cycles: u64) -> bool {
+        if port == self.offset_port {
+            // Only the lowest 3 bits are wired to the shifter
+            self.offset = val & 0x07;
+            self.reverse = self.reversed_port.is_some() && val & 0x08 != 0;
+        } else if port == self.data_port {
+            self.data = (val as u16) << 8 | self.data >> 8;
+        } else {
+            return false;
+        }
+
+        true
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.data);
+        writer.write_u8(self.offset | (self.reverse as u8) << 3);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.data = reader.read_u16()?;
+
+        let offset = reader.read_u8()?;
+        self.offset = offset & 0x07;
+        self.reverse = offset & 0x08 != 0;
+        Ok(())
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+
+// Input latch that returns the value of e.g. buttons, coin switches or DIP switches wired to an IN port
+pub struct InputLatch {
+    name: String,
+    port: u8,
+    val: u8,
+}
+
+impl InputLatch {
+    pub fn new(name: &str, port: u8, initial_val: u8) -> Self {
+        InputLatch {
+            name: name.to_string(),
+            port,
+            val: initial_val,
+        }
+    }
+
+    pub fn get(&self) -> u8 {
+        self.val
+    }
+
+    pub fn set(&mut self, val: u8) {
+        self.val = val;
+    }
+
+    // Set or clear a single bit of the latch, e.g. when a button is pressed or released
+    pub fn set_bit(&mut self, bit: u8, on: bool) {
+        if on {
+            self.val |= 1 << bit;
+        } else {
+            self.val &= !(1 << bit);
+        }
+    }
+}
+
+impl Device for InputLatch {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn input(&mut self, port: u8, _cycles: u64) -> Option<u8> {
+        if port == self.port {
+            Some(self.val)
+        } else {
+            None
+        }
+    }
+
+    fn output(&mut self, _port: u8, _val: u8, _cycles: u64) -> bool {
+        false
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.val);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.val = reader.read_u8()?;
+        Ok(())
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -1,10 +1,124 @@
+use std::collections::HashSet;
 use std::path::PathBuf;
 use std::fs::read;
 
+use crate::devices::Device;
 use crate::errors::EmulatorError;
+use crate::snapshot::{StateReader, StateWriter};
+use crate::throttle::Throttle;
+
+pub mod cheats;
+pub mod coverage;
+pub mod i8085;
+pub mod memory_map;
+pub mod profiler;
+pub mod provenance;
+pub mod rewind;
+pub mod z80;
+
+use cheats::Freeze;
+use coverage::CoverageMap;
+use i8085::I8085State;
+use memory_map::MemoryMap;
+use profiler::Profiler;
+use provenance::ProvenanceMap;
+use rewind::RewindBuffer;
+use z80::Z80State;
+
+// Clock cycles taken by each opcode, conditional CALLs and RETs take 6 extra cycles when the condition is true
+const CYCLES: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+     4, 10,  7,  5,  5,  5,  7,  4,  4, 10,  7,  5,  5,  5,  7,  4,    // 0x0x
+     4, 10,  7,  5,  5,  5,  7,  4,  4, 10,  7,  5,  5,  5,  7,  4,    // 0x1x
+     4, 10, 16,  5,  5,  5,  7,  4,  4, 10, 16,  5,  5,  5,  7,  4,    // 0x2x
+     4, 10, 13,  5, 10, 10, 10,  4,  4, 10, 13,  5,  5,  5,  7,  4,    // 0x3x
+     5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5,    // 0x4x
+     5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5,    // 0x5x
+     5,  5,  5,  5,  5,  5,  7,  5,  5,  5,  5,  5,  5,  5,  7,  5,    // 0x6x
+     7,  7,  7,  7,  7,  7,  7,  7,  5,  5,  5,  5,  5,  5,  7,  5,    // 0x7x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x8x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x9x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0xAx
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0xBx
+     5, 10, 10, 10, 11, 11,  7, 11,  5, 10, 10, 10, 11, 17,  7, 11,    // 0xCx
+     5, 10, 10, 10, 11, 11,  7, 11,  5, 10, 10, 10, 11, 17,  7, 11,    // 0xDx
+     5, 10, 10, 18, 11, 11,  7, 11,  5,  5, 10,  4, 11, 17,  7, 11,    // 0xEx
+     5, 10, 10,  4, 11, 11,  7, 11,  5,  5, 10,  4, 11, 17,  7, 11,    // 0xFx
+];
+
+// Alternate opcodes that the 8080 executes as NOP, JMP, CALL and RET, Intel never documented them
+const UNDOCUMENTED_OPCODES: [u8; 12] = [0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0xcb, 0xd9, 0xdd, 0xed, 0xfd];
+
+// CPU variants that the emulator can run as
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum CpuModel {
+    #[default]
+    I8080,
+    I8085,
+    Z80,
+}
+
+impl CpuModel {
+    // Parse the model from its part number, e.g. "8085" or "z80"
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().trim_start_matches('i') {
+            "8080" => Some(CpuModel::I8080),
+            "8085" => Some(CpuModel::I8085),
+            "z80" => Some(CpuModel::Z80),
+            _ => None,
+        }
+    }
 
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuModel::I8080 => "Intel 8080",
+            CpuModel::I8085 => "Intel 8085",
+            CpuModel::Z80 => "Zilog Z80",
+        }
+    }
+
+    // Clock cycles taken by the opcode, see the tables for conditional instructions
+    fn cycles(&self, opcode: u8) -> u64 {
+        match self {
+            CpuModel::I8080 => CYCLES[opcode as usize] as u64,
+            CpuModel::I8085 => i8085::CYCLES[opcode as usize] as u64,
+            CpuModel::Z80 => z80::CYCLES[opcode as usize] as u64,
+        }
+    }
+}
+
+// What the 8080 does with the undocumented opcodes. They are real instructions on the 8085 and the Z80, which always
+// execute them.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum UndocumentedPolicy {
+    // Execute them like the 8080 does
+    #[default]
+    Execute,
+
+    // Execute them and log a warning with the address, once per address
+    Warn,
+
+    // Stop the CPU before executing them, e.g. to catch runaway execution into data
+    Stop,
+}
+
+impl UndocumentedPolicy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "execute" => Some(UndocumentedPolicy::Execute),
+            "warn" => Some(UndocumentedPolicy::Warn),
+            "stop" => Some(UndocumentedPolicy::Stop),
+            _ => None,
+        }
+    }
+}
 
 pub struct Intel8080 {
+    // Variant of the CPU, the 8085 and the Z80 have their extra state in i8085 and z80
+    model: CpuModel,
+    i8085: I8085State,
+    z80: Z80State,
+
     registers: Registers,
     mem: Vec<u8>,
 
@@ -12,7 +126,37 @@ pub struct Intel8080 {
     halted: bool,
 
     // Interrupt system state
-    int: bool
+    int: bool,
+
+    // Total amount of clock cycles executed
+    cycles: u64,
+
+    // Devices wired to the IN/OUT ports
+    devices: Vec<Box<dyn Device>>,
+
+    // Execution history for reverse stepping, None when not recording
+    rewind: Option<RewindBuffer>,
+
+    // Last writer of every memory address, None when not tracking
+    provenance: Option<ProvenanceMap>,
+
+    // Execution counts and cycles per address, opcode and subroutine, None when not profiling
+    profiler: Option<Profiler>,
+
+    // How each memory byte has been accessed, None when not recording coverage
+    coverage: Option<CoverageMap>,
+
+    // Addresses forced to a value every frame
+    freezes: Vec<Freeze>,
+
+    // RAM, ROM and empty areas of the address space
+    memory_map: MemoryMap,
+
+    // Handling of the undocumented opcodes, the addresses already warned about and the address and opcode that the
+    // CPU stopped at
+    undocumented: UndocumentedPolicy,
+    undocumented_warned: HashSet<u16>,
+    undocumented_stop: Option<(u16, u8)>,
 }
 
 struct Registers {
@@ -38,11 +182,12 @@ struct Registers {
 struct FlagRegister {
     sign: bool,         // Bit 7 Sign flag - Set if MSB of the result is 1, unset if not
     zero: bool,         // Bit 6 Zero flag - Set if result is 0, unset if not 
-    // Always 0            Bit 5 Not used
+    k: bool,            // Bit 5 Not used, on the 8085 K flag - Set if INX or DCX wrapped around
     aux_carry: bool,    // Bit 4 Auxiliary Carry flag
     // Always 0            Bit 3 Not used
     parity: bool,       // Bit 2 Parity flag - Set if value is even, unset if not
-    // Always 1            Bit 1 Not used
+    overflow: bool,     // Bit 1 Not used, on the 8085 V flag - Set if the signed result overflowed
+    subtract: bool,     // Bit 1 on the Z80 N flag - Set if the last arithmetic instruction was a subtraction
     carry: bool,        // Bit 0 Carry flag
 }
 
@@ -99,8 +244,8 @@ impl Registers {
         *reg = val;
     }
 
-    pub fn get_psw(&mut self) -> u16 {
-        (self.get_reg("A") as u16) << 8 | self.f.get_flags() as u16
+    pub fn get_psw(&self, model: CpuModel) -> u16 {
+        (self.get_reg("A") as u16) << 8 | self.f.get_model_flags(model) as u16
     }
 
     pub fn set_psw(&mut self, val: u16) {
@@ -154,15 +299,16 @@ impl FlagRegister {
     pub fn set_flags(&mut self, val: u8) {
         self.sign =      (val >> 7) & 0x1 == 1;     // Bit 7
         self.zero =      (val >> 6) & 0x1 == 1;     // Bit 6
-                                                    // Bit 5 not used
+        self.k =         (val >> 5) & 0x1 == 1;     // Bit 5 only used by the 8085
         self.aux_carry = (val >> 4) & 0x1 == 1;     // Bit 4
                                                     // Bit 3 not used
         self.parity =    (val >> 2) & 0x1 == 1;     // Bit 2
-                                                    // Bit 1 not used
+        self.overflow =  (val >> 1) & 0x1 == 1;     // Bit 1 only used by the 8085
+        self.subtract =  (val >> 1) & 0x1 == 1;     // Bit 1 only used by the Z80
         self.carry =      val       & 0x1 == 1;     // Bit 0
     }
 
-    pub fn get_flags(&mut self) -> u8 {
+    pub fn get_flags(&self) -> u8 {
         (self.sign as u8)       << 7 |    // Bit 7
         (self.zero as u8)       << 6 |    // Bit 6
         (0x0 as u8)             << 5 |    // Bit 5 always 0
@@ -172,6 +318,16 @@ impl FlagRegister {
         (0x1 as u8)             << 1 |    // Bit 1 always 1
         (self.carry as u8)                // Bit 0
     }
+
+    // Flags as stored by PUSH PSW, the 8085 has its K and V flags and the Z80 its N flag in the bits that are fixed
+    // on the 8080
+    pub fn get_model_flags(&self, model: CpuModel) -> u8 {
+        match model {
+            CpuModel::I8080 => self.get_flags(),
+            CpuModel::I8085 => self.get_flags() & 0b11010101 | (self.k as u8) << 5 | (self.overflow as u8) << 1,
+            CpuModel::Z80 => self.get_flags() & 0b11010101 | (self.subtract as u8) << 1,
+        }
+    }
 }
 
 impl Intel8080 {
@@ -179,13 +335,20 @@ impl Intel8080 {
 
         // Initialize the CPU with 0 and false values
         Intel8080 {
+            model: CpuModel::I8080,
+            i8085: I8085State::default(),
+            z80: Z80State::default(),
+
             registers: Registers {
                 a: 0x00,
                 f: FlagRegister {
                     sign: false,
                     zero: false,
+                    k: false,
                     aux_carry: false,
                     parity: false,
+                    overflow: false,
+                    subtract: false,
                     carry: false,
                 },
                 
@@ -206,18 +369,358 @@ impl Intel8080 {
             mem: Vec::<u8>::with_capacity(0x10000),
 
             halted: false,
-            int: false
+            int: false,
+
+            cycles: 0,
+            devices: Vec::new(),
+
+            rewind: None,
+            provenance: None,
+            profiler: None,
+            coverage: None,
+
+            freezes: Vec::new(),
+            memory_map: MemoryMap::default(),
+
+            undocumented: UndocumentedPolicy::Execute,
+            undocumented_warned: HashSet::new(),
+            undocumented_stop: None,
+        }
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn model(&self) -> CpuModel {
+        self.model
+    }
+
+    // Switch the CPU variant, e.g. before loading the program of an 8085 board
+    pub fn set_model(&mut self, model: CpuModel) {
+        self.model = model;
+    }
+
+    pub fn get_pc(&self) -> u16 {
+        self.registers.pc as u16
+    }
+
+    // True after HLT, or when the CPU stopped at an undocumented opcode
+    pub fn is_halted(&self) -> bool {
+        self.halted || self.undocumented_stop.is_some()
+    }
+
+    pub fn undocumented_policy(&self) -> UndocumentedPolicy {
+        self.undocumented
+    }
+
+    pub fn set_undocumented_policy(&mut self, policy: UndocumentedPolicy) {
+        self.undocumented = policy;
+    }
+
+    // Return the error if the CPU stopped at an undocumented opcode, see UndocumentedPolicy::Stop
+    pub fn check_undocumented(&self) -> Result<(), EmulatorError> {
+        match self.undocumented_stop {
+            Some((pc, opcode)) => Err(EmulatorError::UndocumentedOpcode(opcode, pc)),
+            None => Ok(()),
+        }
+    }
+
+    // Apply the policy to the opcode at PC, returns false if the CPU stops instead of executing it
+    fn check_opcode(&mut self, opcode: u8) -> bool {
+        if self.undocumented == UndocumentedPolicy::Execute || !UNDOCUMENTED_OPCODES.contains(&opcode) {
+            return true;
+        }
+
+        let pc = self.registers.pc as u16;
+
+        if self.undocumented == UndocumentedPolicy::Warn {
+            if self.undocumented_warned.insert(pc) {
+                eprintln!("Warning: undocumented opcode {opcode:#04x} executed at {pc:#06x}");
+            }
+
+            return true;
         }
+
+        self.undocumented_stop = Some((pc, opcode));
+        false
+    }
+
+    // Register access for machines that implement system calls in Rust, e.g. the CP/M BDOS. Registers are named
+    // like in the instructions: "A", "B", "C", "D", "E", "H", "L" and the pairs "BC", "DE", "HL"
+    pub fn get_reg(&self, reg: &str) -> u8 {
+        self.registers.get_reg(reg)
+    }
+
+    pub fn set_reg(&mut self, reg: &str, val: u8) {
+        self.registers.set_reg(reg, val);
+    }
+
+    pub fn get_reg_pair(&self, pair: &str) -> u16 {
+        self.registers.get_reg_pair(pair)
+    }
+
+    pub fn set_reg_pair(&mut self, pair: &str, val: u16) {
+        self.registers.set_reg_pair(pair, val);
+    }
+
+    pub fn set_pc(&mut self, addr: u16) {
+        self.registers.pc = addr as usize;
+    }
+
+    pub fn get_sp(&self) -> u16 {
+        self.registers.sp
+    }
+
+    pub fn set_sp(&mut self, addr: u16) {
+        self.registers.sp = addr;
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        self.mem[addr as usize]
+    }
+
+    // Store a byte from outside of the program, e.g. for cheats. Only the rewind history records it, with the
+    // next executed instruction.
+    pub fn write_byte(&mut self, addr: u16, val: u8) {
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.record_write(addr, self.mem[addr as usize], val);
+        }
+
+        self.store(addr, val);
+    }
+
+    // Whole memory, e.g. for rendering the video RAM
+    pub fn memory(&self) -> &[u8] {
+        &self.mem
+    }
+
+    // Human readable one line dump of the registers and flags
+    pub fn dump_registers(&self) -> String {
+        let regs = &self.registers;
+        let f = &regs.f;
+
+        // The Z80 also shows its index registers
+        let index = match self.model {
+            CpuModel::Z80 => {
+                let (ix, iy) = self.get_index_registers();
+                format!(" IX={:04X} IY={:04X}", ix, iy)
+            },
+            _ => String::new(),
+        };
+
+        format!(
+            "PC={:04X} SP={:04X} A={:02X} BC={:04X} DE={:04X} HL={:04X}{} [{}{}{}{}{}] CYC={}",
+            regs.pc, regs.sp, regs.a, regs.get_reg_pair("BC"), regs.get_reg_pair("DE"), regs.get_reg_pair("HL"), index,
+            if f.sign { 'S' } else { '-' },
+            if f.zero { 'Z' } else { '-' },
+            if f.aux_carry { 'A' } else { '-' },
+            if f.parity { 'P' } else { '-' },
+            if f.carry { 'C' } else { '-' },
+            self.cycles,
+        )
+    }
+
+    // Wire a device to the IN/OUT ports, devices attached first get to answer first
+    pub fn attach_device(&mut self, device: Box<dyn Device>) {
+        self.devices.push(device);
+    }
+
+    // Return the first attached device of the given type
+    pub fn device_mut<T: Device + 'static>(&mut self) -> Option<&mut T> {
+        self.devices.iter_mut().find_map(|device| device.as_any_mut().downcast_mut::<T>())
+    }
+
+    // Return the attached device with the given name
+    pub fn named_device_mut<T: Device + 'static>(&mut self, name: &str) -> Option<&mut T> {
+        self.devices
+            .iter_mut()
+            .filter(|device| device.name() == name)
+            .find_map(|device| device.as_any_mut().downcast_mut::<T>())
+    }
+
+    // Read a byte from the devices, unconnected ports read as 0xFF because of the pull-up resistors on the data bus
+    fn port_in(&mut self, port: u8) -> u8 {
+        let cycles = self.cycles;
+        self.devices.iter_mut().find_map(|device| device.input(port, cycles)).unwrap_or(0xFF)
+    }
+
+    // Write a byte to the devices, writes to unconnected ports are ignored
+    fn port_out(&mut self, port: u8, val: u8) {
+        let cycles = self.cycles;
+        for device in self.devices.iter_mut() {
+            if device.output(port, val, cycles) {
+                break;
+            }
+        }
+    }
+
+    // Serialize registers, flags, SP, PC, halted and interrupt states, cycle count, memory, the device states and the
+    // state of the CPU variant
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        let regs = &self.registers;
+
+        for reg in [regs.a, regs.f.get_model_flags(self.model), regs.b, regs.c, regs.d, regs.e, regs.h, regs.l] {
+            writer.write_u8(reg);
+        }
+
+        writer.write_u16(regs.sp);
+        writer.write_u16(regs.pc as u16);
+
+        writer.write_bool(self.halted);
+        writer.write_bool(self.int);
+        writer.write_u64(self.cycles);
+
+        writer.write_bytes(&self.mem);
+
+        writer.write_u32(self.devices.len() as u32);
+        for device in self.devices.iter() {
+            writer.write_str(device.name());
+
+            // Device state is written as a length prefixed blob so that the reader can verify it was fully consumed
+            let mut device_writer = StateWriter::new();
+            device.save_state(&mut device_writer);
+            writer.write_bytes(&device_writer.into_bytes());
+        }
+
+        // State of the CPU variant, also as a blob. Older save states end before it and are always of an 8080.
+        let mut model_writer = StateWriter::new();
+        match self.model {
+            CpuModel::I8080 => (),
+            CpuModel::I8085 => self.i8085.save_state(&mut model_writer),
+            CpuModel::Z80 => self.z80.save_state(&mut model_writer),
+        }
+
+        writer.write_u8(self.model as u8);
+        writer.write_bytes(&model_writer.into_bytes());
+    }
+
+    // Restore the state serialized with save_state(), the same devices must already be attached
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        let mut regs = [0u8; 8];
+        for reg in regs.iter_mut() {
+            *reg = reader.read_u8()?;
+        }
+
+        let sp = reader.read_u16()?;
+        let pc = reader.read_u16()?;
+        let halted = reader.read_bool()?;
+        let int = reader.read_bool()?;
+        let cycles = reader.read_u64()?;
+        let mem = reader.read_bytes()?;
+
+        if mem.len() != 0x10000 {
+            return Err(EmulatorError::SaveStateInvalid(format!("memory is {} bytes instead of 65536", mem.len())));
+        }
+
+        let device_count = reader.read_u32()? as usize;
+        if device_count != self.devices.len() {
+            return Err(EmulatorError::SaveStateInvalid(
+                format!("save state has {} devices, machine has {}", device_count, self.devices.len())
+            ));
+        }
+
+        for device in self.devices.iter_mut() {
+            let name = reader.read_str()?;
+            if name != device.name() {
+                return Err(EmulatorError::SaveStateInvalid(
+                    format!("expected state for device '{}', found '{}'", device.name(), name)
+                ));
+            }
+
+            let mut device_reader = StateReader::new(reader.read_bytes()?);
+            device.load_state(&mut device_reader)?;
+
+            if !device_reader.is_at_end() {
+                return Err(EmulatorError::SaveStateInvalid(format!("state of device '{name}' has extra data")));
+            }
+        }
+
+        // Older save states end before the state of the CPU variant and are always of an 8080
+        let (model, model_state) = if reader.is_at_end() {
+            (CpuModel::I8080 as u8, &[][..])
+        } else {
+            (reader.read_u8()?, reader.read_bytes()?)
+        };
+
+        if model != self.model as u8 {
+            return Err(EmulatorError::SaveStateInvalid(format!("save state is not of an {}", self.model.name())));
+        }
+
+        match self.model {
+            CpuModel::I8080 => (),
+            CpuModel::I8085 => self.i8085 = I8085State::load_state(&mut StateReader::new(model_state))?,
+            CpuModel::Z80 => self.z80 = Z80State::load_state(&mut StateReader::new(model_state))?,
+        }
+
+        let [a, f, b, c, d, e, h, l] = regs;
+        self.registers.a = a;
+        self.registers.f.set_flags(f);
+        self.registers.b = b;
+        self.registers.c = c;
+        self.registers.d = d;
+        self.registers.e = e;
+        self.registers.h = h;
+        self.registers.l = l;
+
+        self.registers.sp = sp;
+        self.registers.pc = pc as usize;
+
+        self.halted = halted;
+        self.int = int;
+        self.cycles = cycles;
+        self.undocumented_stop = None;
+        self.mem = mem.to_vec();
+
+        Ok(())
     }
 
     // Read the whole rom into memory, if rom is in parts it must be combined manually into a single file
     pub fn read_rom_to_mem(&mut self, input_file: PathBuf) -> Result<(), EmulatorError> {
+        self.load_rom(&read(input_file)?);
+        Ok(())
+    }
+
+    // Load a rom image, e.g. a patched one, into memory from address 0
+    pub fn load_rom(&mut self, rom: &[u8]) {
         // Should be a "free operation" because no memory needs to be allocated for the vec
-        for byte in read(input_file)?.iter() {
+        for byte in rom.iter() {
             self.mem.push(*byte);
         }
-    
-        Ok(())
+
+        // Rest of the 64KB address space is RAM, so that programs can write past the end of the ROM
+        self.mem.resize(0x10000, 0x00);
+    }
+
+    // Store a byte into memory, all memory writes done by instructions go through here so that they can be tracked
+    fn write_mem(&mut self, addr: usize, val: u8) {
+        // ROM and empty areas ignore writes
+        if !self.memory_map.is_writable(addr as u16) {
+            return;
+        }
+
+        if let Some(rewind) = self.rewind.as_mut() {
+            rewind.record_write(addr as u16, self.mem[addr], val);
+        }
+
+        if let Some(provenance) = self.provenance.as_mut() {
+            provenance.record(addr as u16, self.registers.pc as u16, self.cycles);
+        }
+
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark(addr as u16, coverage::DATA_WRITE);
+        }
+
+        self.store(addr as u16, val);
+    }
+
+    // Load a byte from memory as data, i.e. not as an opcode or operand of the instruction
+    fn read_mem(&mut self, addr: usize) -> u8 {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark(addr as u16, coverage::DATA_READ);
+        }
+
+        self.mem[addr]
     }
 
     fn advance_pc(&mut self, val: usize) {
@@ -225,12 +728,13 @@ impl Intel8080 {
     }
 
     // Return 2 bytes from memory pointed to by either PC or SP
-    fn get_word(&self, pc: bool) -> u16 {
+    fn get_word(&mut self, pc: bool) -> u16 {
         // Take into account that the 8080 is little endian, so the first byte is actually the lower part of the value
         if pc {
             (self.mem[self.registers.pc + 2] as u16) << 8 | self.mem[self.registers.pc + 1] as u16
         } else {
-            (self.mem[self.registers.sp as usize + 1] as u16) << 8 | self.mem[self.registers.sp as usize] as u16
+            let sp: usize = self.registers.sp as usize;
+            (self.read_mem(sp + 1) as u16) << 8 | self.read_mem(sp) as u16
         }
     }
 
@@ -244,8 +748,8 @@ impl Intel8080 {
     // Store 2 bytes into memory pointed to by SP
     fn push_stack(&mut self, val: u16) {
         self.registers.sp = self.registers.sp.wrapping_sub(2);
-        self.mem[(self.registers.sp + 1) as usize] = (val >> 8) as u8;
-        self.mem[self.registers.sp as usize] = val as u8;
+        self.write_mem(self.registers.sp.wrapping_add(1) as usize, (val >> 8) as u8);
+        self.write_mem(self.registers.sp as usize, val as u8);
     }
 
     // No operation
@@ -264,14 +768,19 @@ impl Intel8080 {
     // STAX reg pair - Store accumulator to the mem addr in reg pair
     fn stax(&mut self, reg_pair: &str) {
         let mem_addr: usize = self.registers.get_reg_pair(reg_pair).into();
-        self.mem[mem_addr] = self.registers.a;
+        self.write_mem(mem_addr, self.registers.a);
         
         self.advance_pc(1);
     }
 
     // INX reg pair - Increment reg pair value
     fn inx(&mut self, reg_pair: &str) {
-        self.registers.set_reg_pair(reg_pair, self.registers.get_reg_pair(reg_pair).wrapping_add(1));
+        let val: u16 = self.registers.get_reg_pair(reg_pair).wrapping_add(1);
+        self.registers.set_reg_pair(reg_pair, val);
+
+        // The K flag of the 8085 tells that the value wrapped around
+        self.registers.f.k = val == 0x0000;
+
         self.advance_pc(1);
     }
 
@@ -292,6 +801,7 @@ impl Intel8080 {
             Greater than 0xF -> True, carry happened from lower 4 bits to the upper ones
         */
         self.registers.f.aux_carry = (val & 0xF) + 0x01 > 0x0F;
+        self.registers.f.overflow = incremented_val == 0x80;
         
         self.advance_pc(1);
     }
@@ -310,6 +820,7 @@ impl Intel8080 {
             Equal to 0xF -> False, because borrow I guess?
         */
         self.registers.f.aux_carry = (val & 0xF) != 0xF;
+        self.registers.f.overflow = val == 0x7F;
 
         self.advance_pc(1);
     }
@@ -334,14 +845,20 @@ impl Intel8080 {
     // LDAX reg pair - Load to accumulator indirect value from reg pair
     fn ldax(&mut self, reg_pair: &str) {
         let mem_addr: usize = self.registers.get_reg_pair(reg_pair).into();
-        self.registers.set_reg("A", self.mem[mem_addr]);
+        let val: u8 = self.read_mem(mem_addr);
+        self.registers.set_reg("A", val);
 
         self.advance_pc(1);
     }
 
     // DCX reg pair - Decrement reg pair value
     fn dcx(&mut self, reg_pair: &str) {
-        self.registers.set_reg_pair(reg_pair, self.registers.get_reg_pair(reg_pair) - 1);
+        let val: u16 = self.registers.get_reg_pair(reg_pair).wrapping_sub(1);
+        self.registers.set_reg_pair(reg_pair, val);
+
+        // The K flag of the 8085 tells that the value wrapped around
+        self.registers.f.k = val == 0xFFFF;
+
         self.advance_pc(1);
     }
 
@@ -354,14 +871,15 @@ impl Intel8080 {
     // MOV dst reg, byte from mem - Move byte from mem pointed to by reg pair HL to dst reg
     fn mov_m(&mut self, dst: &str) {
         let addr: usize = self.registers.get_reg_pair("HL").into();
-        self.registers.set_reg(dst, self.mem[addr]);
+        let val: u8 = self.read_mem(addr);
+        self.registers.set_reg(dst, val);
         self.advance_pc(1);
     }
 
     // MOV src reg, byte from mem - Move byte from src reg to mem pointed to by reg pair HL
     fn mov_r(&mut self, src: &str) {
         let addr: usize = self.registers.get_reg_pair("HL").into();
-        self.mem[addr] = self.registers.get_reg(src);
+        self.write_mem(addr, self.registers.get_reg(src));
         self.advance_pc(1);
     }
 
@@ -391,6 +909,7 @@ impl Intel8080 {
             Greater than 0xF -> True, carry happened from lower 4 bits to the upper ones
         */
         self.registers.f.aux_carry = (reg_a & 0xF) + (val & 0xF) > 0x0F;
+        self.registers.f.overflow = ((reg_a ^ added_val) & (val ^ added_val) & 0x80) != 0;
 
         self.advance_pc(1);
     }
@@ -423,6 +942,7 @@ impl Intel8080 {
             Greater than 0xF -> True, carry happened from lower 4 bits to the upper ones
         */
         self.registers.f.aux_carry = (reg_a & 0xF) + (val & 0xF) + carry > 0x0F;
+        self.registers.f.overflow = ((reg_a ^ added_val) & (val ^ added_val) & 0x80) != 0;
 
         self.advance_pc(1);
     }
@@ -453,6 +973,7 @@ impl Intel8080 {
             Less than 0x0 -> False, borrow happened from lower 4 bits to the upper ones
         */
         self.registers.f.aux_carry = (reg_a as i8 & 0x0F) - (val as i8 & 0x0F) >= 0x0;
+        self.registers.f.overflow = ((reg_a ^ val) & (reg_a ^ subtracted_val) & 0x80) != 0;
 
         self.advance_pc(1);
     }
@@ -465,7 +986,7 @@ impl Intel8080 {
 
         self.registers.set_reg("A", subtracted_val);
         self.registers.f.set_artihmetic_flags(subtracted_val);
-        self.registers.f.carry = reg_a < val + carry;
+        self.registers.f.carry = (reg_a as u16) < val as u16 + carry as u16;
 
         /*
         Check if subtracting the given value and reg A that have been casted as integers and ANDed with 0x0F + the
@@ -485,6 +1006,7 @@ impl Intel8080 {
             Less than 0x0 -> False, borrow happened from lower 4 bits to the upper ones
         */
         self.registers.f.aux_carry = (reg_a as i8 & 0x0F) - (val as i8 & 0x0F) - (carry as i8) >= 0x0;
+        self.registers.f.overflow = ((reg_a ^ val) & (reg_a ^ subtracted_val) & 0x80) != 0;
 
         self.advance_pc(1);
     }
@@ -507,7 +1029,8 @@ impl Intel8080 {
             The 8080 logical AND instructions set the flag to reflect the logical OR of bit 3 of the values involved in
             the AND operation.
         */
-        self.registers.f.aux_carry = ((reg_a | val) & 0x08) == 1;
+        self.registers.f.aux_carry = self.model == CpuModel::I8085 || ((reg_a | val) & 0x08) != 0;
+        self.registers.f.overflow = false;
 
         self.advance_pc(1);
     }
@@ -520,6 +1043,7 @@ impl Intel8080 {
         // Carry and aux carry are always set to zero
         self.registers.f.carry = false;
         self.registers.f.aux_carry = false;
+        self.registers.f.overflow = false;
         self.registers.f.set_artihmetic_flags(result);
         self.registers.set_reg("A", result);
 
@@ -534,6 +1058,7 @@ impl Intel8080 {
         // Carry and aux carry are always set to zero
         self.registers.f.carry = false;
         self.registers.f.aux_carry = false;
+        self.registers.f.overflow = false;
         self.registers.f.set_artihmetic_flags(result);
         self.registers.set_reg("A", result);
 
@@ -553,13 +1078,17 @@ impl Intel8080 {
         */
         self.sub(val);
 
+        // SUB already advanced the PC
         self.registers.set_reg("A", reg_a);
-
-        self.advance_pc(1);
     }
 
     // RET IF condition - Return from subroutine by popping stack if condition is true
     fn ret(&mut self, condition: bool) {
+        // Conditional returns (0b11CCC000) take 6 extra cycles when returning
+        if condition && self.mem[self.registers.pc] & 0x07 == 0x00 {
+            self.cycles += 6;
+        }
+
         if condition {
             self.registers.pc = self.pop_stack() as usize;
         } else {
@@ -588,7 +1117,7 @@ impl Intel8080 {
 
         // Handle PSW (Program Status Word i.e. reg A + Flag reg) separately
         if reg_pair == "PSW" {
-            val = self.registers.get_psw();
+            val = self.registers.get_psw(self.model);
         } else {
             val = self.registers.get_reg_pair(reg_pair);
         }
@@ -599,6 +1128,11 @@ impl Intel8080 {
 
     // JMP IF condition - Jump to address specified in the next two bytes
     fn jmp(&mut self, condition: bool) {
+        // Conditional jumps of the 8085 take 3 extra cycles when jumping
+        if condition && self.model == CpuModel::I8085 && self.mem[self.registers.pc] != 0xc3 {
+            self.cycles += i8085::JUMP_TAKEN_CYCLES;
+        }
+
         if condition {
             self.registers.pc = self.get_word(true) as usize;
         } else {
@@ -608,9 +1142,16 @@ impl Intel8080 {
 
     // CALL IF condition - Jump to address specified in the next two bytes
     fn call(&mut self, condition: bool) {
+        // Conditional calls (0b11CCC100) take 6 extra cycles when calling, 9 on the 8085
+        if condition && self.mem[self.registers.pc] & 0x07 == 0x04 {
+            self.cycles += if self.model == CpuModel::I8085 { i8085::CALL_TAKEN_CYCLES } else { 6 };
+        }
+
         if condition {
-            self.push_stack(self.registers.pc as u16);
-            self.registers.pc = self.get_word(true).into();
+            // Return to the instruction after the CALL
+            let addr: u16 = self.get_word(true);
+            self.push_stack((self.registers.pc + 3) as u16);
+            self.registers.pc = addr.into();
         } else {
             self.advance_pc(3);
         }
@@ -618,13 +1159,33 @@ impl Intel8080 {
 
     // RST num - Restart from a predefined address based on restart num
     fn rst(&mut self, val: u8) {
-        self.push_stack(self.registers.pc as u16);
-        self.registers.pc = self.mem[(0x08 * val) as usize].into();
+        // Return to the instruction after the RST
+        self.push_stack((self.registers.pc + 1) as u16);
+        self.registers.pc = (0x08 * val) as usize;
     }
 
     // Execute the matching opcode and set the registers to their corresponding state
     fn exec_opcode(&mut self) {
-        match self.mem[self.registers.pc] {
+        // The Z80 decodes its prefixes and operands itself
+        if self.model == CpuModel::Z80 {
+            self.exec_z80_opcode();
+            return;
+        }
+
+        let opcode: u8 = self.mem[self.registers.pc];
+
+        if self.model == CpuModel::I8080 && !self.check_opcode(opcode) {
+            return;
+        }
+
+        self.cycles += self.model.cycles(opcode);
+
+        // The 8085 has its own instructions in place of some of the alternate opcodes
+        if self.model == CpuModel::I8085 && self.exec_8085_opcode(opcode) {
+            return;
+        }
+
+        match opcode {
         
             // 0x0x
             0x00 => {
@@ -816,8 +1377,8 @@ impl Intel8080 {
 
                 let addr: u16 = self.get_word(true);
 
-                self.mem[addr as usize] = l;
-                self.mem[(addr + 1) as usize] = h;
+                self.write_mem(addr as usize, l);
+                self.write_mem((addr + 1) as usize, h);
 
                 self.advance_pc(3);
             },
@@ -889,8 +1450,10 @@ impl Intel8080 {
                 // LHLD - Load reg H and reg L from mem addr given in pc+1 and pc+2
                 let addr: u16 = self.get_word(true);
 
-                self.registers.set_reg("L", self.mem[addr as usize]);
-                self.registers.set_reg("H", self.mem[(addr + 1) as usize]);
+                let l: u8 = self.read_mem(addr as usize);
+                let h: u8 = self.read_mem((addr + 1) as usize);
+                self.registers.set_reg("L", l);
+                self.registers.set_reg("H", h);
 
                 self.advance_pc(3);
             },
@@ -934,7 +1497,7 @@ impl Intel8080 {
             0x32 => {
                 // STA - Store accumulator direct
                 let addr: u16 = self.get_word(true);
-                self.mem[addr as usize] = self.registers.get_reg("A");
+                self.write_mem(addr as usize, self.registers.get_reg("A"));
 
                 self.advance_pc(3);
             },
@@ -946,10 +1509,10 @@ impl Intel8080 {
             0x34 => {
                 // INR M - Increment byte in memory pointed by reg pair HL
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                let val: u8 = self.mem[addr];
+                let val: u8 = self.read_mem(addr);
                 let incremented_val: u8 = val.wrapping_add(1);
 
-                self.mem[addr] = incremented_val;
+                self.write_mem(addr, incremented_val);
                 self.registers.f.set_artihmetic_flags(incremented_val);
 
                 /*
@@ -967,9 +1530,9 @@ impl Intel8080 {
             0x35 => {
                 // DCR M - Decrement byte in memory pointed by reg pair HL
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                let val: u8 = self.mem[addr].wrapping_sub(1);
+                let val: u8 = self.read_mem(addr).wrapping_sub(1);
 
-                self.mem[addr] = val;
+                self.write_mem(addr, val);
                 self.registers.f.set_artihmetic_flags(val);
 
                 /*
@@ -986,7 +1549,7 @@ impl Intel8080 {
             0x36 => {
                 // MVI M - Move immediate value to mem addr pointed by reg pair HL
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                self.mem[addr] = self.mem[self.registers.pc + 1];
+                self.write_mem(addr, self.mem[self.registers.pc + 1]);
                 self.advance_pc(2);
             },
             0x37 => {
@@ -1001,7 +1564,7 @@ impl Intel8080 {
             0x39 => {
                 // DAD SP - Add SP to register pair HL
                 let val: u32 = self.registers.sp as u32 + self.registers.get_reg_pair("HL") as u32;
-                self.registers.sp = val as u16;
+                self.registers.set_reg_pair("HL", val as u16);
 
                 // Check if adding the two reg pairs overflows over u16 max val
                 self.registers.f.carry = val > 0xFFFF;
@@ -1011,7 +1574,8 @@ impl Intel8080 {
             0x3a => {
                 // LDA - Load byte from mem to accumulator
                 let addr: u16 = self.get_word(true);
-                self.registers.set_reg("A", self.mem[addr as usize]);
+                let val: u8 = self.read_mem(addr as usize);
+                self.registers.set_reg("A", val);
 
                 self.advance_pc(3);
             },
@@ -1331,7 +1895,8 @@ impl Intel8080 {
             0x86 => {
                 // ADD M - Add byte from mem pointed to by reg pair HL to reg A
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                self.add(self.mem[addr]);
+                let val: u8 = self.read_mem(addr);
+                self.add(val);
             },
             0x87 => {
                 // ADD A - Add reg A to reg A
@@ -1364,7 +1929,8 @@ impl Intel8080 {
             0x8e => {
                 // ADC M - Add byte from mem pointed to by reg pair HL to reg A with carry
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                self.adc(self.mem[addr]);
+                let val: u8 = self.read_mem(addr);
+                self.adc(val);
             },
             0x8f => {
                 // ADC A - Add reg A to reg A with carry
@@ -1399,7 +1965,8 @@ impl Intel8080 {
             0x96 => {
                 // SUB M - Subtract byte from mem pointed to by reg pair HL from reg A
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                self.sub(self.mem[addr]);
+                let val: u8 = self.read_mem(addr);
+                self.sub(val);
             },
             0x97 => {
                 // SUB A - Subtract reg A from reg A
@@ -1432,7 +1999,8 @@ impl Intel8080 {
             0x9e => {
                 // SBB M - Subtract byte from mem pointed to by reg pair HL from reg A with borrow 
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                self.sbb(self.mem[addr]);
+                let val: u8 = self.read_mem(addr);
+                self.sbb(val);
             },
             0x9f => {
                 // SBB A - Subtract reg A from reg A with borrow
@@ -1467,7 +2035,8 @@ impl Intel8080 {
             0xa6 => {
                 // ANA M - Logical AND byte from mem pointed to by reg pair HL with reg A
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                self.ana(self.mem[addr]);
+                let val: u8 = self.read_mem(addr);
+                self.ana(val);
             },
             0xa7 => {
                 // ANA A - Logical AND reg A with reg A
@@ -1500,7 +2069,8 @@ impl Intel8080 {
             0xae => {
                 // XRA M - Logical XOR byte from mem pointed to by reg pair HL with reg A
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                self.xra(self.mem[addr]);
+                let val: u8 = self.read_mem(addr);
+                self.xra(val);
             },
             0xaf => {
                 // XRA A - Logical XOR reg A with reg A
@@ -1535,7 +2105,8 @@ impl Intel8080 {
             0xb6 => {
                 // ORA M - Logical OR byte from mem pointed to by reg pair HL with reg A
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                self.ora(self.mem[addr]);
+                let val: u8 = self.read_mem(addr);
+                self.ora(val);
             },
             0xb7 => {
                 // ORA A - Logical OR reg A with reg A
@@ -1568,7 +2139,8 @@ impl Intel8080 {
             0xbe => {
                 // CMP M - Compare byte from mem pointed to by reg pair HL with reg A
                 let addr: usize = self.registers.get_reg_pair("HL").into();
-                self.cmp(self.mem[addr]);
+                let val: u8 = self.read_mem(addr);
+                self.cmp(val);
             },
             0xbf => {
                 // CMP A - Compare reg A with reg A
@@ -1662,10 +2234,8 @@ impl Intel8080 {
             },
             0xd3 => {
                 // OUT - Output accumulator to port specified in the next byte
-
-                /*
-                    ## Will be implemented later, when all other instructions are emulated ##
-                */
+                let port: u8 = self.mem[self.registers.pc + 1];
+                self.port_out(port, self.registers.get_reg("A"));
 
                 self.advance_pc(2);
             },
@@ -1702,10 +2272,9 @@ impl Intel8080 {
             },
             0xdb => {
                 // IN - Write byte to accumulator from port specified in the next byte
-
-                /*
-                    ## Will be implemented later, when all other instructions are emulated ##
-                */
+                let port: u8 = self.mem[self.registers.pc + 1];
+                let val: u8 = self.port_in(port);
+                self.registers.set_reg("A", val);
 
                 self.advance_pc(2);
             },
@@ -1748,8 +2317,8 @@ impl Intel8080 {
                 let hl: u16 = self.registers.get_reg_pair("HL");
 
                 self.registers.set_reg_pair("HL", mem_val);
-                self.mem[self.registers.sp as usize] = hl as u8;
-                self.mem[(self.registers.sp + 1) as usize] = (hl >> 8) as u8;
+                self.write_mem(self.registers.sp as usize, hl as u8);
+                self.write_mem((self.registers.sp + 1) as usize, (hl >> 8) as u8);
 
                 self.advance_pc(1);
             },
@@ -1779,7 +2348,6 @@ impl Intel8080 {
             0xe9 => {
                 // PCHL - Move reg pair HL to PC
                 self.registers.pc = self.registers.get_reg_pair("HL").into();
-                self.advance_pc(1);
             },
             0xea => {
                 // JPE - Jump if parity flag set (even)
@@ -1893,9 +2461,107 @@ impl Intel8080 {
         };
     }
 
+    // Execute a single instruction
+    pub fn step(&mut self) {
+        // A CPU stopped at an undocumented opcode doesn't continue
+        if self.undocumented_stop.is_some() {
+            return;
+        }
+
+        // Interrupts from the pins of the 8085 are taken before the next instruction
+        if self.model == CpuModel::I8085 && self.take_pin_interrupt() {
+            return;
+        }
+
+        let profile_start = self.profiler.as_ref().map(|_| self.profile_start());
+
+        if self.coverage.is_some() {
+            self.cover_instruction();
+        }
+
+        if self.rewind.is_some() {
+            self.record_step(Intel8080::exec_opcode);
+        } else {
+            self.exec_opcode();
+        }
+
+        if let Some(start) = profile_start {
+            self.profile_end(start);
+        }
+    }
+
+    // Execute instructions until the cycle count reaches the target, a halted CPU just idles until the target
+    pub fn run_until(&mut self, target_cycles: u64) {
+        while self.cycles < target_cycles && self.undocumented_stop.is_none() {
+            if self.halted && !(self.model == CpuModel::I8085 && self.has_pin_interrupt()) {
+                self.cycles = target_cycles;
+                break;
+            }
+
+            self.step();
+        }
+    }
+
+    /*
+    Interrupt the CPU like an external device would, by placing an RST instruction on the data bus. The interrupt is
+    only accepted when interrupts are enabled, and accepting it disables further interrupts until the program
+    executes EI again. Returns true if the interrupt was accepted.
+    */
+    pub fn interrupt(&mut self, rst_num: u8) -> bool {
+        if !self.int || self.undocumented_stop.is_some() {
+            return false;
+        }
+
+        if self.model == CpuModel::Z80 {
+            self.z80_interrupt(rst_num);
+            return true;
+        }
+
+        self.enter_interrupt(0x08 * (rst_num & 0x07) as u16, self.model.cycles(0xc7));
+        true
+    }
+
+    // Push the PC and jump to the interrupt vector with interrupts disabled, recorded like an executed instruction
+    fn enter_interrupt(&mut self, vector: u16, cycles: u64) {
+        let profile_start = self.profiler.as_ref().map(|_| self.profile_start());
+
+        let accept = |cpu: &mut Intel8080| {
+            cpu.int = false;
+            cpu.halted = false;
+
+            // Unlike the RST opcode, the interrupted instruction at PC has not been executed yet
+            cpu.push_stack(cpu.registers.pc as u16);
+            cpu.registers.pc = vector as usize;
+            cpu.cycles += cycles;
+        };
+
+        if self.rewind.is_some() {
+            self.record_step(accept);
+        } else {
+            accept(self);
+        }
+
+        if let Some(start) = profile_start {
+            self.profile_interrupt(start);
+        }
+    }
+
     pub fn emulate(&mut self) {
-        while !self.halted {
-            self.exec_opcode()
+        while !self.is_halted() {
+            self.step()
+        }
+    }
+
+    // Run like emulate(), but at the clock speed of the throttle, which is synchronized after every slice of the cycles
+    pub fn emulate_throttled(&mut self, throttle: &mut Throttle, slice_cycles: u64) {
+        while !self.is_halted() {
+            let target = self.cycles + slice_cycles.max(1);
+
+            while !self.is_halted() && self.cycles < target {
+                self.step();
+            }
+
+            throttle.sync(self.cycles);
         }
     }
 
@@ -1915,3 +2581,9 @@ impl Intel8080 {
         //println!("HL: {:04X}\n", self.registers.get_reg_pair("HL"));
     }
 }
+
+impl Default for Intel8080 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
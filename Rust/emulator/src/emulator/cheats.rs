@@ -0,0 +1,269 @@
+/*
+Memory search and cheats - Find game variables such as lives, score or positions by comparing memory snapshots,
+and freeze addresses to a value
+
+A search starts with every address of a range as a candidate. Each filter compares the current value of the
+candidates either to their value in the previous snapshot or to a constant, keeps the matching ones and takes a
+new snapshot. E.g. for finding the lives counter: start a search, lose a life, filter with "decreased", play
+without dying, filter with "eq" against the previous values and so on.
+*/
+
+use super::Intel8080;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ValueType {
+    U8,
+    // Little endian
+    U16,
+    // Packed BCD, two digits per byte
+    Bcd8,
+    // Packed BCD in little endian byte order, e.g. the scores of many games
+    Bcd16,
+}
+
+impl ValueType {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "u8" => Some(ValueType::U8),
+            "u16" => Some(ValueType::U16),
+            "bcd" | "bcd8" => Some(ValueType::Bcd8),
+            "bcd16" => Some(ValueType::Bcd16),
+            _ => None,
+        }
+    }
+
+    pub fn size(self) -> usize {
+        match self {
+            ValueType::U8 | ValueType::Bcd8 => 1,
+            ValueType::U16 | ValueType::Bcd16 => 2,
+        }
+    }
+
+    // Decode the value at the address, None if the bytes are not valid BCD
+    pub fn read(self, mem: &[u8], addr: usize) -> Option<u32> {
+        match self {
+            ValueType::U8 => Some(mem[addr] as u32),
+            ValueType::U16 => Some(mem[addr] as u32 | (mem[addr + 1] as u32) << 8),
+            ValueType::Bcd8 => decode_bcd(mem[addr]),
+            ValueType::Bcd16 => Some(decode_bcd(mem[addr])? + decode_bcd(mem[addr + 1])? * 100),
+        }
+    }
+
+    // Encode the value into its bytes in memory order, values that don't fit are truncated
+    pub fn encode(self, val: u32) -> Vec<u8> {
+        let bcd = |val: u32| (((val / 10 % 10) << 4) | (val % 10)) as u8;
+
+        match self {
+            ValueType::U8 => vec![val as u8],
+            ValueType::U16 => vec![val as u8, (val >> 8) as u8],
+            ValueType::Bcd8 => vec![bcd(val)],
+            ValueType::Bcd16 => vec![bcd(val), bcd(val / 100)],
+        }
+    }
+}
+
+fn decode_bcd(byte: u8) -> Option<u32> {
+    if byte >> 4 > 9 || byte & 0x0F > 9 {
+        return None;
+    }
+
+    Some((byte >> 4) as u32 * 10 + (byte & 0x0F) as u32)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Relation {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+}
+
+impl Relation {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "eq" | "equal" => Some(Relation::Equal),
+            "ne" | "changed" => Some(Relation::Changed),
+            "inc" | "increased" => Some(Relation::Increased),
+            "dec" | "decreased" => Some(Relation::Decreased),
+            _ => None,
+        }
+    }
+
+    fn matches(self, val: u32, other: u32) -> bool {
+        match self {
+            Relation::Equal => val == other,
+            Relation::Changed => val != other,
+            Relation::Increased => val > other,
+            Relation::Decreased => val < other,
+        }
+    }
+}
+
+
+pub struct MemorySearch {
+    value_type: ValueType,
+    candidates: Vec<u16>,
+    snapshot: Vec<u8>,
+}
+
+impl MemorySearch {
+    // Start a search over the address range with every address as a candidate
+    pub fn new(cpu: &Intel8080, value_type: ValueType, start: u16, end: u16) -> Self {
+        let mem = cpu.memory();
+
+        // Values must fit into the range, there are no candidates if the range is shorter than a value
+        let range_end = (end as usize + 1).min(mem.len());
+        let candidates = match range_end.checked_sub(value_type.size()) {
+            Some(last) => (start as usize..=last)
+                .filter(|addr| value_type.read(mem, *addr).is_some())
+                .map(|addr| addr as u16)
+                .collect(),
+            None => Vec::new(),
+        };
+
+        MemorySearch {
+            value_type,
+            candidates,
+            snapshot: mem.to_vec(),
+        }
+    }
+
+    // Keep the candidates whose value has the relation to the constant, or to the previous snapshot when no
+    // constant is given. Return the amount of candidates left.
+    pub fn filter(&mut self, cpu: &Intel8080, relation: Relation, constant: Option<u32>) -> usize {
+        let mem = cpu.memory();
+        let value_type = self.value_type;
+        let snapshot = &self.snapshot;
+
+        self.candidates.retain(|addr| {
+            let addr = *addr as usize;
+            let other = match constant {
+                Some(val) => Some(val),
+                None => value_type.read(snapshot, addr),
+            };
+
+            match (value_type.read(mem, addr), other) {
+                (Some(val), Some(other)) => relation.matches(val, other),
+                _ => false,
+            }
+        });
+
+        self.snapshot = mem.to_vec();
+        self.candidates.len()
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    // Value of the candidate in the latest snapshot
+    pub fn snapshot_value(&self, addr: u16) -> Option<u32> {
+        self.value_type.read(&self.snapshot, addr as usize)
+    }
+}
+
+
+// Address that is forced to a value at the start of every frame
+#[derive(Clone, Copy)]
+pub struct Freeze {
+    pub addr: u16,
+    pub value_type: ValueType,
+    pub val: u32,
+}
+
+impl Intel8080 {
+    // Freeze the address to the value, replacing an existing freeze of the address
+    pub fn freeze(&mut self, addr: u16, value_type: ValueType, val: u32) {
+        self.unfreeze(addr);
+        self.freezes.push(Freeze { addr, value_type, val });
+    }
+
+    // Return false if the address was not frozen
+    pub fn unfreeze(&mut self, addr: u16) -> bool {
+        let len = self.freezes.len();
+        self.freezes.retain(|freeze| freeze.addr != addr);
+        self.freezes.len() != len
+    }
+
+    pub fn freezes(&self) -> &[Freeze] {
+        &self.freezes
+    }
+
+    // Write the frozen values into memory, called by the machines at the start of every frame
+    pub fn apply_freezes(&mut self) {
+        for i in 0..self.freezes.len() {
+            let freeze = self.freezes[i];
+
+            for (offset, byte) in freeze.value_type.encode(freeze.val).into_iter().enumerate() {
+                self.write_byte(freeze.addr.wrapping_add(offset as u16), byte);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu_with(bytes: &[u8]) -> Intel8080 {
+        let mut cpu = Intel8080::new();
+        cpu.load_rom(bytes);
+        cpu
+    }
+
+    #[test]
+    fn search_range_shorter_than_the_value() {
+        let cpu = cpu_with(&[0x12, 0x34]);
+
+        assert!(MemorySearch::new(&cpu, ValueType::U16, 0, 0).candidates().is_empty());
+        assert_eq!(MemorySearch::new(&cpu, ValueType::U8, 0, 0).candidates(), &[0]);
+        assert_eq!(MemorySearch::new(&cpu, ValueType::U16, 0, 1).candidates(), &[0]);
+        assert!(MemorySearch::new(&Intel8080::new(), ValueType::U8, 0, 0xFFFF).candidates().is_empty());
+    }
+
+    #[test]
+    fn search_ends_at_the_end_of_memory() {
+        let cpu = cpu_with(&[]);
+
+        let search = MemorySearch::new(&cpu, ValueType::U16, 0xFFF0, 0xFFFF);
+        assert_eq!(search.candidates().last(), Some(&0xFFFE));
+    }
+
+    #[test]
+    fn filter_against_the_snapshot_and_a_constant() {
+        let mut cpu = cpu_with(&[3, 5, 7]);
+        let mut search = MemorySearch::new(&cpu, ValueType::U8, 0, 2);
+
+        cpu.write_byte(0, 2);
+        cpu.write_byte(2, 8);
+        assert_eq!(search.filter(&cpu, Relation::Decreased, None), 1);
+        assert_eq!(search.candidates(), &[0]);
+
+        assert_eq!(search.filter(&cpu, Relation::Equal, Some(2)), 1);
+        assert_eq!(search.filter(&cpu, Relation::Changed, None), 0);
+    }
+
+    #[test]
+    fn bcd_values() {
+        assert_eq!(ValueType::Bcd16.read(&[0x50, 0x12], 0), Some(1250));
+        assert_eq!(ValueType::Bcd8.read(&[0x1A], 0), None);
+        assert_eq!(ValueType::Bcd16.encode(1250), vec![0x50, 0x12]);
+        assert_eq!(ValueType::U16.encode(0x1234), vec![0x34, 0x12]);
+    }
+
+    #[test]
+    fn freeze_writes_the_value() {
+        let mut cpu = cpu_with(&[]);
+        cpu.freeze(0x2000, ValueType::Bcd16, 99);
+        cpu.apply_freezes();
+        assert_eq!(&cpu.memory()[0x2000..0x2002], &[0x99, 0x00]);
+
+        assert!(cpu.unfreeze(0x2000));
+        assert!(!cpu.unfreeze(0x2000));
+    }
+}
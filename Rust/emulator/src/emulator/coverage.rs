@@ -0,0 +1,132 @@
+/*
+Code coverage - Records which bytes were fetched as opcodes, which as operands and which were read or written as
+data. The map can be exported as a coverage file that the disassembler uses for its code/data split.
+*/
+
+use std::path::Path;
+
+use disassembler::coverage::{instruction_length, write_coverage, ADDR_SPACE, OPCODE, OPERAND};
+
+use crate::emulator::{i8085, z80, CpuModel, Intel8080};
+use crate::errors::EmulatorError;
+
+pub use disassembler::coverage::{DATA_READ, DATA_WRITE};
+
+
+pub struct CoverageMap {
+    flags: Vec<u8>,
+}
+
+impl CoverageMap {
+    pub fn new() -> Self {
+        CoverageMap {
+            flags: vec![0; ADDR_SPACE],
+        }
+    }
+
+    pub fn mark(&mut self, addr: u16, flag: u8) {
+        self.flags[addr as usize] |= flag;
+    }
+
+    // Mark the opcode and the operand bytes of the instruction that is about to be executed
+    pub fn mark_instruction(&mut self, pc: u16, length: usize) {
+        self.mark(pc, OPCODE);
+
+        for i in 1..length {
+            self.mark(pc.wrapping_add(i as u16), OPERAND);
+        }
+    }
+
+    pub fn flags(&self, addr: u16) -> u8 {
+        self.flags[addr as usize]
+    }
+}
+
+impl Default for CoverageMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Intel8080 {
+    // Mark the instruction at PC that is about to be executed
+    pub(super) fn cover_instruction(&mut self) {
+        let pc = self.registers.pc as u16;
+        let length = self.instruction_length_at(pc);
+
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.mark_instruction(pc, length);
+        }
+    }
+
+    // Length of the instruction at the address, which depends on the CPU model
+    fn instruction_length_at(&self, addr: u16) -> usize {
+        let opcode = self.mem[addr as usize];
+
+        match self.model {
+            CpuModel::I8080 => instruction_length(opcode),
+            CpuModel::I8085 => i8085::instruction_length(opcode),
+            CpuModel::Z80 => z80::instruction_length(&self.mem, addr),
+        }
+    }
+
+    pub fn enable_coverage(&mut self) {
+        if self.coverage.is_none() {
+            self.coverage = Some(CoverageMap::new());
+        }
+    }
+
+    pub fn coverage(&self) -> Option<&CoverageMap> {
+        self.coverage.as_ref()
+    }
+
+    pub fn write_coverage(&self, path: &Path) -> Result<(), EmulatorError> {
+        let Some(coverage) = self.coverage.as_ref() else {
+            return Err(EmulatorError::CoverageDisabled);
+        };
+
+        write_coverage(path, &coverage.flags).map_err(|e| EmulatorError::FileCantOpen(e.to_string()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage_uses_the_lengths_of_the_model() {
+        // LDHI 10h; NOP
+        let program = [0x28, 0x10, 0x00];
+
+        for (model, operand) in [(CpuModel::I8080, 0), (CpuModel::I8085, OPERAND)] {
+            let mut cpu = Intel8080::new();
+            cpu.set_model(model);
+            cpu.load_rom(&program);
+            cpu.enable_coverage();
+            cpu.step();
+
+            let flags = &cpu.coverage().unwrap().flags;
+            assert_eq!(flags[0], OPCODE);
+            assert_eq!(flags[1], operand);
+        }
+    }
+
+    #[test]
+    fn coverage_of_the_z80_prefixes_and_relative_jumps() {
+        // LD (IX+40h),12h; JR 0; DD prefix acting as a NOP before LD IY,1234h
+        let program = [0xdd, 0x36, 0x40, 0x12, 0x18, 0x00, 0xdd, 0xfd, 0x21, 0x34, 0x12];
+
+        let mut cpu = Intel8080::new();
+        cpu.set_model(CpuModel::Z80);
+        cpu.load_rom(&program);
+        cpu.enable_coverage();
+        for _ in 0..4 {
+            cpu.step();
+        }
+
+        let flags = &cpu.coverage().unwrap().flags;
+        let expected = [OPCODE, OPERAND, OPERAND, OPERAND, OPCODE, OPERAND, OPCODE, OPCODE, OPERAND, OPERAND, OPERAND];
+        assert_eq!(&flags[..program.len()], &expected);
+    }
+}
@@ -0,0 +1,618 @@
+/*
+Intel 8085 - the differences of the 8085 to the 8080, enabled with CpuModel::I8085
+
+Interrupts, on top of the INTR input that the 8080 has:
+    TRAP        0x24    Non-maskable, taken on the rising edge of the pin
+    RST 7.5     0x3C    Rising edge sets a flip-flop that stays set until the interrupt is taken or SIM resets it
+    RST 6.5     0x34    Taken while the pin is high
+    RST 5.5     0x2C    Taken while the pin is high
+TRAP has the highest priority and RST 5.5 the lowest. The RST inputs are only taken when interrupts are enabled and
+not masked by SIM, all three are masked after reset.
+
+RIM (0x20) reads into the accumulator:
+    Bit 7   SID, the serial input pin
+    Bit 6-4 Pending RST 7.5, 6.5 and 5.5
+    Bit 3   Interrupts enabled, right after a TRAP the state from before it
+    Bit 2-0 Masks of RST 7.5, 6.5 and 5.5
+SIM (0x30) writes from the accumulator:
+    Bit 7   Serial output data, latched to the SOD pin when bit 6 is set
+    Bit 6   Serial output enable
+    Bit 4   Reset the RST 7.5 flip-flop
+    Bit 3   Mask set enable, bits 2-0 are the new masks when set
+    Bit 2-0 Masks of RST 7.5, 6.5 and 5.5
+
+The undocumented instructions that Intel left out of the manuals, with their usual mnemonics:
+    0x08    DSUB        HL = HL - BC
+    0x10    ARHL        Arithmetic shift right of HL, bit 0 goes to the carry
+    0x18    RDEL        Rotate DE left through the carry
+    0x28    LDHI d8     DE = HL + d8
+    0x38    LDSI d8     DE = SP + d8
+    0xCB    RSTV        RST 8 (0x40) if the overflow flag is set
+    0xD9    SHLX        Store HL at the address in DE
+    0xDD    JNK a16     Jump if the K flag is not set
+    0xED    LHLX        Load HL from the address in DE
+    0xFD    JK a16      Jump if the K flag is set
+They use the undocumented flags in bits 1 and 5 of the flag register, which are fixed on the 8080: V (bit 1) is the
+two's complement overflow of the arithmetic instructions and DSUB, and K (bit 5) is set when INX or DCX wraps around.
+
+The 8085 also differs in cycle counts, e.g. INX takes 6 cycles and CALL 18, and conditional jumps and calls take
+fewer cycles when they are not taken. Logical AND always sets the auxiliary carry.
+*/
+
+use disassembler::coverage;
+
+use super::Intel8080;
+use crate::errors::EmulatorError;
+use crate::snapshot::{StateReader, StateWriter};
+
+// Clock cycles taken by each opcode on the 8085. Conditional jumps, calls and returns are listed with the cycles when
+// the condition is false, jumps take 3, calls 9 and returns 6 extra cycles when it is true.
+pub(super) const CYCLES: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+     4, 10,  7,  6,  4,  4,  7,  4, 10, 10,  7,  6,  4,  4,  7,  4,    // 0x0x
+     7, 10,  7,  6,  4,  4,  7,  4, 10, 10,  7,  6,  4,  4,  7,  4,    // 0x1x
+     4, 10, 16,  6,  4,  4,  7,  4, 10, 10, 16,  6,  4,  4,  7,  4,    // 0x2x
+     4, 10, 13,  6, 10, 10, 10,  4, 10, 10, 13,  6,  4,  4,  7,  4,    // 0x3x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x4x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x5x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x6x
+     7,  7,  7,  7,  7,  7,  5,  7,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x7x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x8x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x9x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0xAx
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0xBx
+     6, 10,  7, 10,  9, 12,  7, 12,  6, 10,  7,  6,  9, 18,  7, 12,    // 0xCx
+     6, 10,  7, 10,  9, 12,  7, 12,  6, 10,  7, 10,  9,  7,  7, 12,    // 0xDx
+     6, 10,  7, 16,  9, 12,  7, 12,  6,  6,  7,  4,  9, 10,  7, 12,    // 0xEx
+     6, 10,  7,  4,  9, 12,  7, 12,  6,  6,  7,  4,  9,  7,  7, 12,    // 0xFx
+];
+
+// Length of the instruction in bytes on the 8085, whose undocumented instructions replace the alternates of the 8080
+pub(super) fn instruction_length(opcode: u8) -> usize {
+    match opcode {
+        // LDHI and LDSI
+        0x28 | 0x38 => 2,
+
+        // RSTV and LHLX
+        0xcb | 0xed => 1,
+
+        _ => coverage::instruction_length(opcode),
+    }
+}
+
+// Mnemonic of the undocumented instructions in the format of the 8080 disassembler, which only knows their 8080
+// alternates
+pub(super) fn mnemonic(bytes: &[u8; 3]) -> Option<String> {
+    let text = match bytes[0] {
+        0x08 => String::from("DSUB"),
+        0x10 => String::from("ARHL"),
+        0x18 => String::from("RDEL"),
+        0x20 => String::from("RIM"),
+        0x28 => format!("{:<9} #{:#04X}", "LDHI", bytes[1]),
+        0x30 => String::from("SIM"),
+        0x38 => format!("{:<9} #{:#04X}", "LDSI", bytes[1]),
+        0xcb => String::from("RSTV"),
+        0xd9 => String::from("SHLX"),
+        0xdd => format!("{:<9} {:#04X}{:02X}", "JNK", bytes[2], bytes[1]),
+        0xed => String::from("LHLX"),
+        0xfd => format!("{:<9} {:#04X}{:02X}", "JK", bytes[2], bytes[1]),
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+pub(super) const JUMP_TAKEN_CYCLES: u64 = 3;
+pub(super) const CALL_TAKEN_CYCLES: u64 = 9;
+const RSTV_TAKEN_CYCLES: u64 = 6;
+
+// Cycles for taking TRAP or one of the RST interrupts, like the RST instruction
+const INTERRUPT_CYCLES: u64 = 12;
+
+const TRAP_VECTOR: u16 = 0x24;
+const RSTV_VECTOR: u16 = 0x40;
+
+// Masks set by SIM and reported by RIM
+const MASK_55: u8 = 0x01;
+const MASK_65: u8 = 0x02;
+const MASK_75: u8 = 0x04;
+const MASKS: u8 = MASK_55 | MASK_65 | MASK_75;
+
+// Control bits of SIM
+const SIM_MASK_ENABLE: u8 = 0x08;
+const SIM_RESET_75: u8 = 0x10;
+const SIM_SOD_ENABLE: u8 = 0x40;
+
+
+// Interrupt inputs of the 8085 besides INTR
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InterruptPin {
+    Trap,
+    Rst75,
+    Rst65,
+    Rst55,
+}
+
+impl InterruptPin {
+    fn vector(&self) -> u16 {
+        match self {
+            InterruptPin::Trap => TRAP_VECTOR,
+            InterruptPin::Rst75 => 0x3C,
+            InterruptPin::Rst65 => 0x34,
+            InterruptPin::Rst55 => 0x2C,
+        }
+    }
+}
+
+// Interrupt and serial pins of the 8085 and the state that RIM and SIM work with
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct I8085State {
+    masks: u8,
+
+    // Pin levels, TRAP and RST 7.5 are latched on their rising edge
+    trap_pin: bool,
+    trap_pending: bool,
+    rst75_pin: bool,
+    rst75_pending: bool,
+    rst65_pin: bool,
+    rst55_pin: bool,
+
+    // Interrupt enable state from before the last TRAP, reported by the next RIM
+    trap_int: Option<bool>,
+
+    sid: bool,
+    sod: bool,
+}
+
+impl Default for I8085State {
+    fn default() -> Self {
+        I8085State {
+            masks: MASKS,
+
+            trap_pin: false,
+            trap_pending: false,
+            rst75_pin: false,
+            rst75_pending: false,
+            rst65_pin: false,
+            rst55_pin: false,
+
+            trap_int: None,
+
+            sid: false,
+            sod: false,
+        }
+    }
+}
+
+impl I8085State {
+    // Highest priority interrupt that can be taken now
+    fn next_interrupt(&self, int: bool) -> Option<InterruptPin> {
+        if self.trap_pending {
+            return Some(InterruptPin::Trap);
+        }
+
+        if !int {
+            return None;
+        }
+
+        [
+            (InterruptPin::Rst75, self.rst75_pending, MASK_75),
+            (InterruptPin::Rst65, self.rst65_pin, MASK_65),
+            (InterruptPin::Rst55, self.rst55_pin, MASK_55),
+        ]
+        .into_iter()
+        .find(|(_, requested, mask)| *requested && self.masks & mask == 0)
+        .map(|(pin, _, _)| pin)
+    }
+
+    pub(super) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.masks);
+
+        let pins = [
+            self.trap_pin, self.trap_pending, self.rst75_pin, self.rst75_pending, self.rst65_pin, self.rst55_pin,
+        ];
+        for pin in pins {
+            writer.write_bool(pin);
+        }
+
+        // 0 = no TRAP since the last RIM, 1 = interrupts were disabled, 2 = enabled
+        writer.write_u8(self.trap_int.map_or(0, |int| int as u8 + 1));
+        writer.write_bool(self.sid);
+        writer.write_bool(self.sod);
+    }
+
+    pub(super) fn load_state(reader: &mut StateReader) -> Result<Self, EmulatorError> {
+        let masks = reader.read_u8()? & MASKS;
+
+        let mut pins = [false; 6];
+        for pin in pins.iter_mut() {
+            *pin = reader.read_bool()?;
+        }
+
+        let trap_int = match reader.read_u8()? {
+            0 => None,
+            1 => Some(false),
+            2 => Some(true),
+            val => return Err(EmulatorError::SaveStateInvalid(format!("invalid 8085 TRAP state {val}"))),
+        };
+
+        let [trap_pin, trap_pending, rst75_pin, rst75_pending, rst65_pin, rst55_pin] = pins;
+
+        Ok(I8085State {
+            masks,
+            trap_pin,
+            trap_pending,
+            rst75_pin,
+            rst75_pending,
+            rst65_pin,
+            rst55_pin,
+            trap_int,
+            sid: reader.read_bool()?,
+            sod: reader.read_bool()?,
+        })
+    }
+}
+
+
+impl Intel8080 {
+    // Drive an interrupt pin of the 8085 high or low, e.g. from a device or a timer of the machine
+    pub fn set_interrupt_pin(&mut self, pin: InterruptPin, level: bool) {
+        let state = &mut self.i8085;
+
+        match pin {
+            InterruptPin::Trap => {
+                state.trap_pending |= level && !state.trap_pin;
+                state.trap_pin = level;
+            },
+            InterruptPin::Rst75 => {
+                state.rst75_pending |= level && !state.rst75_pin;
+                state.rst75_pin = level;
+            },
+            InterruptPin::Rst65 => state.rst65_pin = level,
+            InterruptPin::Rst55 => state.rst55_pin = level,
+        }
+    }
+
+    // Serial input pin of the 8085, read by RIM
+    pub fn set_sid(&mut self, level: bool) {
+        self.i8085.sid = level;
+    }
+
+    // Serial output pin of the 8085, written by SIM
+    pub fn sod(&self) -> bool {
+        self.i8085.sod
+    }
+
+    // True if an interrupt of the 8085 pins can be taken now, so that a halted CPU wakes up to take it
+    pub(super) fn has_pin_interrupt(&self) -> bool {
+        self.i8085.next_interrupt(self.int).is_some()
+    }
+
+    // Take the highest priority interrupt of the 8085 pins, returns true if one was taken
+    pub(super) fn take_pin_interrupt(&mut self) -> bool {
+        let Some(pin) = self.i8085.next_interrupt(self.int) else {
+            return false;
+        };
+
+        match pin {
+            InterruptPin::Trap => {
+                self.i8085.trap_pending = false;
+                self.i8085.trap_int = Some(self.int);
+            },
+            InterruptPin::Rst75 => self.i8085.rst75_pending = false,
+            InterruptPin::Rst65 | InterruptPin::Rst55 => (),
+        }
+
+        self.enter_interrupt(pin.vector(), INTERRUPT_CYCLES);
+        true
+    }
+
+    // Execute the opcodes that are alternates of other instructions on the 8080, returns false for the other opcodes
+    pub(super) fn exec_8085_opcode(&mut self, opcode: u8) -> bool {
+        match opcode {
+            0x08 => {
+                // DSUB - Subtract reg pair BC from reg pair HL
+                let hl: u16 = self.registers.get_reg_pair("HL");
+                let bc: u16 = self.registers.get_reg_pair("BC");
+                let result: u16 = hl.wrapping_sub(bc);
+
+                self.registers.set_reg_pair("HL", result);
+                self.registers.f.set_artihmetic_flags((result >> 8) as u8);
+                self.registers.f.zero = result == 0;
+                self.registers.f.carry = hl < bc;
+
+                // Like SBB of the high bytes with the borrow from the low bytes
+                let borrow: i8 = ((hl & 0xFF) < (bc & 0xFF)) as i8;
+                self.registers.f.aux_carry = ((hl >> 8) as i8 & 0x0F) - ((bc >> 8) as i8 & 0x0F) - borrow >= 0x0;
+                self.registers.f.overflow = ((hl ^ bc) & (hl ^ result) & 0x8000) != 0;
+
+                self.advance_pc(1);
+            },
+            0x10 => {
+                // ARHL - Arithmetic shift reg pair HL right, the sign bit stays and bit 0 goes to the carry
+                let hl: u16 = self.registers.get_reg_pair("HL");
+                self.registers.f.carry = hl & 0x1 == 1;
+                self.registers.set_reg_pair("HL", (hl >> 1) | (hl & 0x8000));
+
+                self.advance_pc(1);
+            },
+            0x18 => {
+                // RDEL - Rotate reg pair DE left through carry
+                let de: u16 = self.registers.get_reg_pair("DE");
+                let carry: u16 = self.registers.f.carry as u16;
+
+                self.registers.f.carry = de >> 15 == 1;
+                self.registers.f.overflow = ((de ^ (de << 1)) & 0x8000) != 0;
+                self.registers.set_reg_pair("DE", (de << 1) | carry);
+
+                self.advance_pc(1);
+            },
+            0x20 => {
+                // RIM - Read the interrupt masks, pending interrupts and serial input into the accumulator
+                let state = &mut self.i8085;
+                let int: bool = state.trap_int.take().unwrap_or(self.int);
+
+                let val: u8 = (state.sid as u8) << 7
+                    | (state.rst75_pending as u8) << 6
+                    | (state.rst65_pin as u8) << 5
+                    | (state.rst55_pin as u8) << 4
+                    | (int as u8) << 3
+                    | state.masks;
+                self.registers.set_reg("A", val);
+
+                self.advance_pc(1);
+            },
+            0x28 | 0x38 => {
+                // LDHI / LDSI - Load reg pair DE with reg pair HL or SP plus the immediate byte
+                let base: u16 = if opcode == 0x28 { self.registers.get_reg_pair("HL") } else { self.registers.sp };
+                let offset: u16 = self.mem[self.registers.pc + 1] as u16;
+                self.registers.set_reg_pair("DE", base.wrapping_add(offset));
+
+                self.advance_pc(2);
+            },
+            0x30 => {
+                // SIM - Set the interrupt masks and the serial output from the accumulator
+                let val: u8 = self.registers.get_reg("A");
+                let state = &mut self.i8085;
+
+                if val & SIM_MASK_ENABLE != 0 {
+                    state.masks = val & MASKS;
+                }
+
+                if val & SIM_RESET_75 != 0 {
+                    state.rst75_pending = false;
+                }
+
+                if val & SIM_SOD_ENABLE != 0 {
+                    state.sod = val >> 7 == 1;
+                }
+
+                self.advance_pc(1);
+            },
+            0xcb => {
+                // RSTV - Restart from addr 0x40 if the overflow flag is set
+                if self.registers.f.overflow {
+                    self.cycles += RSTV_TAKEN_CYCLES;
+                    self.push_stack((self.registers.pc + 1) as u16);
+                    self.registers.pc = RSTV_VECTOR as usize;
+                } else {
+                    self.advance_pc(1);
+                }
+            },
+            0xd9 => {
+                // SHLX - Store reg pair HL to the mem addr in reg pair DE
+                let addr: u16 = self.registers.get_reg_pair("DE");
+                self.write_mem(addr as usize, self.registers.l);
+                self.write_mem(addr.wrapping_add(1) as usize, self.registers.h);
+
+                self.advance_pc(1);
+            },
+            0xdd => {
+                // JNK - Jump if the K flag is not set
+                self.jmp(!self.registers.f.k);
+            },
+            0xed => {
+                // LHLX - Load reg pair HL from the mem addr in reg pair DE
+                let addr: u16 = self.registers.get_reg_pair("DE");
+                let low: u8 = self.read_mem(addr as usize);
+                let high: u8 = self.read_mem(addr.wrapping_add(1) as usize);
+                self.registers.set_reg_pair("HL", (high as u16) << 8 | low as u16);
+
+                self.advance_pc(1);
+            },
+            0xfd => {
+                // JK - Jump if the K flag is set
+                self.jmp(self.registers.f.k);
+            },
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::CpuModel;
+
+    // 8085 with the program at 0x0000 in 64KB of memory, run until HLT
+    fn run_program(program: &[u8], data: &[(u16, u8)]) -> Intel8080 {
+        let mut cpu = Intel8080::new();
+        cpu.set_model(CpuModel::I8085);
+        cpu.load_rom(program);
+
+        for (addr, val) in data.iter() {
+            cpu.write_byte(*addr, *val);
+        }
+
+        cpu.emulate();
+        cpu
+    }
+
+    fn pop_word(cpu: &Intel8080) -> u16 {
+        let sp: u16 = cpu.get_sp();
+        cpu.read_byte(sp) as u16 | (cpu.read_byte(sp + 1) as u16) << 8
+    }
+
+    #[test]
+    fn rim_and_sim() {
+        // RIM; HLT
+        let mut cpu = Intel8080::new();
+        cpu.set_model(CpuModel::I8085);
+        cpu.load_rom(&[0x20, 0x76]);
+        cpu.set_sid(true);
+        cpu.set_interrupt_pin(InterruptPin::Rst65, true);
+        cpu.emulate();
+
+        // SID, RST 6.5 pending and all masked after reset
+        assert_eq!(cpu.get_reg("A"), 0xA7);
+
+        // MVI A,0Dh; SIM; RIM; HLT - Mask RST 7.5 and 5.5
+        let cpu = run_program(&[0x3e, 0x0d, 0x30, 0x20, 0x76], &[]);
+        assert_eq!(cpu.get_reg("A"), 0x05);
+
+        // MVI A,03h; SIM; RIM; HLT - Without the mask set enable the masks stay
+        let cpu = run_program(&[0x3e, 0x03, 0x30, 0x20, 0x76], &[]);
+        assert_eq!(cpu.get_reg("A"), 0x07);
+    }
+
+    #[test]
+    fn sod_is_only_written_when_enabled() {
+        // MVI A,C0h; SIM; HLT
+        let cpu = run_program(&[0x3e, 0xc0, 0x30, 0x76], &[]);
+        assert!(cpu.sod());
+
+        // MVI A,C0h; SIM; MVI A,00h; SIM; HLT
+        let cpu = run_program(&[0x3e, 0xc0, 0x30, 0x3e, 0x00, 0x30, 0x76], &[]);
+        assert!(cpu.sod());
+
+        // MVI A,C0h; SIM; MVI A,40h; SIM; HLT
+        let cpu = run_program(&[0x3e, 0xc0, 0x30, 0x3e, 0x40, 0x30, 0x76], &[]);
+        assert!(!cpu.sod());
+    }
+
+    #[test]
+    fn trap_is_taken_on_the_rising_edge() {
+        // LXI SP,8000h; EI; HLT, with RIM; HLT at the TRAP vector
+        let mut cpu = run_program(&[0x31, 0x00, 0x80, 0xfb, 0x76], &[(0x0024, 0x20), (0x0025, 0x76)]);
+        let pc = cpu.get_pc();
+
+        cpu.set_interrupt_pin(InterruptPin::Trap, true);
+        cpu.step();
+        assert_eq!(cpu.get_pc(), TRAP_VECTOR);
+        assert_eq!(pop_word(&cpu), pc);
+        assert!(!cpu.int);
+
+        // RIM reports the interrupt enable from before the TRAP once
+        cpu.step();
+        assert_eq!(cpu.get_reg("A") & 0x08, 0x08);
+        assert_eq!(cpu.i8085.trap_int, None);
+
+        // Holding the pin high doesn't take it again, even with interrupts disabled a new edge does
+        assert!(!cpu.has_pin_interrupt());
+        cpu.set_interrupt_pin(InterruptPin::Trap, false);
+        cpu.set_interrupt_pin(InterruptPin::Trap, true);
+        assert!(cpu.has_pin_interrupt());
+    }
+
+    #[test]
+    fn rst_75_latches_the_edge_until_taken() {
+        // LXI SP,8000h; MVI A,0Bh; SIM; EI; HLT - Unmask only RST 7.5, HLT at its vector
+        let mut cpu = run_program(&[0x31, 0x00, 0x80, 0x3e, 0x0b, 0x30, 0xfb, 0x76], &[(0x003C, 0x76)]);
+
+        // A pulse that is over before the interrupt is taken
+        cpu.set_interrupt_pin(InterruptPin::Rst75, true);
+        cpu.set_interrupt_pin(InterruptPin::Rst75, false);
+        cpu.set_interrupt_pin(InterruptPin::Rst65, true);
+        cpu.set_interrupt_pin(InterruptPin::Rst55, true);
+
+        cpu.step();
+        assert_eq!(cpu.get_pc(), 0x003C);
+        assert!(!cpu.i8085.rst75_pending);
+
+        // RST 6.5 and 5.5 are masked
+        cpu.int = true;
+        assert!(!cpu.has_pin_interrupt());
+    }
+
+    #[test]
+    fn masked_rst_75_stays_pending_until_sim_resets_it() {
+        // EI; RIM; MOV B,A; MVI A,10h; SIM; RIM; HLT
+        let mut cpu = Intel8080::new();
+        cpu.set_model(CpuModel::I8085);
+        cpu.load_rom(&[0xfb, 0x20, 0x47, 0x3e, 0x10, 0x30, 0x20, 0x76]);
+        cpu.set_interrupt_pin(InterruptPin::Rst75, true);
+        cpu.emulate();
+
+        assert_eq!(cpu.get_reg("B") & 0x44, 0x44);
+        assert_eq!(cpu.get_reg("A") & 0x40, 0x00);
+        assert_eq!(cpu.get_pc(), 0x0008);
+    }
+
+    #[test]
+    fn dsub_sets_the_overflow_flag_for_rstv() {
+        // LXI SP,8000h; LXI H,8000h; LXI B,0001h; DSUB; RSTV; HLT, with HLT at the RSTV vector
+        let program = [0x31, 0x00, 0x80, 0x21, 0x00, 0x80, 0x01, 0x01, 0x00, 0x08, 0xcb, 0x76];
+        let cpu = run_program(&program, &[(0x0040, 0x76)]);
+
+        assert_eq!(cpu.get_reg_pair("HL"), 0x7FFF);
+        assert!(!cpu.registers.f.carry);
+        assert_eq!(cpu.get_pc(), 0x0041);
+        assert_eq!(pop_word(&cpu), 0x000B);
+
+        // LXI SP,8000h; LXI H,0002h; LXI B,0003h; DSUB; RSTV; HLT
+        let program = [0x31, 0x00, 0x80, 0x21, 0x02, 0x00, 0x01, 0x03, 0x00, 0x08, 0xcb, 0x76];
+        let cpu = run_program(&program, &[(0x0040, 0x76)]);
+
+        assert_eq!(cpu.get_reg_pair("HL"), 0xFFFF);
+        assert!(cpu.registers.f.carry);
+        assert!(!cpu.registers.f.overflow);
+        assert_eq!(cpu.get_pc(), 0x000C);
+    }
+
+    #[test]
+    fn ldhi_shlx_and_lhlx() {
+        // LXI H,1234h; LDHI 10h; SHLX; LXI H,0000h; LHLX; HLT
+        let cpu = run_program(&[0x21, 0x34, 0x12, 0x28, 0x10, 0xd9, 0x21, 0x00, 0x00, 0xed, 0x76], &[]);
+
+        assert_eq!(cpu.get_reg_pair("DE"), 0x1244);
+        assert_eq!(cpu.read_byte(0x1244), 0x34);
+        assert_eq!(cpu.read_byte(0x1245), 0x12);
+        assert_eq!(cpu.get_reg_pair("HL"), 0x1234);
+    }
+
+    #[test]
+    fn jk_and_jnk_use_the_k_flag_of_inx() {
+        // LXI H,FFFFh; INX H; JK 0008h; HLT; HLT
+        let cpu = run_program(&[0x21, 0xff, 0xff, 0x23, 0xfd, 0x08, 0x00, 0x76, 0x76], &[]);
+
+        assert!(cpu.registers.f.k);
+        assert_eq!(cpu.registers.f.get_model_flags(CpuModel::I8085) & 0x20, 0x20);
+        assert_eq!(cpu.get_pc(), 0x0009);
+
+        // LXI H,0001h; INX H; JK 0008h; HLT; HLT
+        let cpu = run_program(&[0x21, 0x01, 0x00, 0x23, 0xfd, 0x08, 0x00, 0x76, 0x76], &[]);
+        assert_eq!(cpu.get_pc(), 0x0008);
+
+        // LXI H,0000h; DCX H; JNK 0008h; HLT; HLT
+        let cpu = run_program(&[0x21, 0x00, 0x00, 0x2b, 0xdd, 0x08, 0x00, 0x76, 0x76], &[]);
+        assert_eq!(cpu.get_pc(), 0x0008);
+    }
+
+    #[test]
+    fn overflow_flag_of_the_arithmetic() {
+        // MVI A,7Fh; ADI 01h; HLT
+        let cpu = run_program(&[0x3e, 0x7f, 0xc6, 0x01, 0x76], &[]);
+
+        assert!(cpu.registers.f.overflow);
+        assert_eq!(cpu.registers.f.get_model_flags(CpuModel::I8085) & 0x02, 0x02);
+
+        // MVI A,7Fh; ADI FFh; HLT
+        let cpu = run_program(&[0x3e, 0x7f, 0xc6, 0xff, 0x76], &[]);
+
+        assert!(!cpu.registers.f.overflow);
+        assert_eq!(cpu.registers.f.get_model_flags(CpuModel::I8085) & 0x02, 0x00);
+    }
+}
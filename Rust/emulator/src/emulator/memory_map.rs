@@ -0,0 +1,134 @@
+/*
+Memory map - RAM, ROM and empty areas of the 64KB address space, in pages of 256 bytes
+
+The program can only change RAM, and empty areas read as 0xFF like an open data bus. The whole address space is RAM
+unless mapped otherwise. Writes from outside of the program, e.g. loading the ROM or cheats, are not restricted.
+
+Boards that don't decode all address lines show the same memory at several addresses. Such mirrors are kept as copies
+of their target that every write updates, so that reading memory and fetching instructions stay plain array accesses.
+*/
+
+use super::Intel8080;
+
+pub const PAGE_SIZE: usize = 0x100;
+const PAGES: usize = 0x10000 / PAGE_SIZE;
+
+// Value read from addresses without memory
+const OPEN_BUS: u8 = 0xFF;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MemoryKind {
+    Ram,
+    Rom,
+    Empty,
+}
+
+// Range of addresses that shows the memory starting at the target address
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Mirror {
+    pub start: u16,
+    pub len: usize,
+    pub target: u16,
+}
+
+impl Mirror {
+    fn contains(&self, addr: u16) -> bool {
+        (self.start as usize..self.start as usize + self.len).contains(&(addr as usize))
+    }
+
+    fn contains_target(&self, addr: u16) -> bool {
+        (self.target as usize..self.target as usize + self.len).contains(&(addr as usize))
+    }
+}
+
+pub struct MemoryMap {
+    pages: [MemoryKind; PAGES],
+    mirrors: Vec<Mirror>,
+}
+
+impl Default for MemoryMap {
+    fn default() -> Self {
+        MemoryMap { pages: [MemoryKind::Ram; PAGES], mirrors: Vec::new() }
+    }
+}
+
+impl MemoryMap {
+    pub fn kind(&self, addr: u16) -> MemoryKind {
+        self.pages[addr as usize / PAGE_SIZE]
+    }
+
+    pub fn is_writable(&self, addr: u16) -> bool {
+        self.kind(addr) == MemoryKind::Ram
+    }
+
+    pub fn mirrors(&self) -> &[Mirror] {
+        &self.mirrors
+    }
+
+    // Every address that shows the same byte as the address, starting with the one in the target range
+    fn aliases(&self, addr: u16) -> Vec<u16> {
+        let target = self.mirrors.iter()
+            .find(|mirror| mirror.contains(addr))
+            .map_or(addr, |mirror| mirror.target + (addr - mirror.start));
+
+        let mut aliases = vec![target];
+        for mirror in self.mirrors.iter().filter(|mirror| mirror.contains_target(target)) {
+            aliases.push(mirror.start + (target - mirror.target));
+        }
+
+        aliases
+    }
+}
+
+impl Intel8080 {
+    // Map the pages covering the range, call after loading the ROM. Empty pages are filled with the open bus value.
+    pub fn map_memory(&mut self, start: u16, len: usize, kind: MemoryKind) {
+        if len == 0 {
+            return;
+        }
+
+        let first = start as usize / PAGE_SIZE;
+        let last = ((start as usize + len - 1) / PAGE_SIZE).min(PAGES - 1);
+
+        for page in first..=last {
+            self.memory_map.pages[page] = kind;
+
+            if kind == MemoryKind::Empty {
+                self.mem[page * PAGE_SIZE..(page + 1) * PAGE_SIZE].fill(OPEN_BUS);
+            }
+        }
+    }
+
+    // Show the memory at the target address also at the start address, e.g. RAM that repeats every 8KB. The mirror
+    // gets the kind and the contents of its target, call after loading the ROM.
+    pub fn mirror_memory(&mut self, start: u16, len: usize, target: u16) {
+        let len = len.min(0x10000 - start as usize).min(0x10000 - target as usize);
+        if len == 0 {
+            return;
+        }
+
+        for page in start as usize / PAGE_SIZE..=(start as usize + len - 1) / PAGE_SIZE {
+            let offset = (page * PAGE_SIZE).saturating_sub(start as usize);
+            self.memory_map.pages[page] = self.memory_map.kind(target + offset as u16);
+        }
+
+        self.mem.copy_within(target as usize..target as usize + len, start as usize);
+        self.memory_map.mirrors.push(Mirror { start, len, target });
+    }
+
+    pub fn memory_kind(&self, addr: u16) -> MemoryKind {
+        self.memory_map.kind(addr)
+    }
+
+    // Store the byte at the address and at all of its mirrors
+    pub(super) fn store(&mut self, addr: u16, val: u8) {
+        if self.memory_map.mirrors.is_empty() {
+            self.mem[addr as usize] = val;
+            return;
+        }
+
+        for alias in self.memory_map.aliases(addr) {
+            self.mem[alias as usize] = val;
+        }
+    }
+}
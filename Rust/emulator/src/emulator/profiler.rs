@@ -0,0 +1,395 @@
+/*
+Execution profiler
+
+Counts executions and cycles per PC address and per opcode. CALL, RST and RET instructions are tracked with a shadow
+call stack, so that the cycles can also be attributed to subroutines:
+    inclusive cycles = cycles spent in the subroutine and everything it called
+    exclusive cycles = cycles spent in the subroutine itself
+
+The exclusive cycles of every distinct call stack are also collected, and can be exported as a folded-stack file
+that flamegraph tools understand, one stack per line e.g.
+    main;sub_18D4;sub_1A5C 12345
+*/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use disassembler::disassembler::decode;
+
+use crate::emulator::{i8085, z80, CpuModel, Intel8080};
+use crate::errors::EmulatorError;
+
+
+#[derive(Default, Clone, Copy)]
+pub struct SubroutineStats {
+    pub calls: u64,
+    pub inclusive_cycles: u64,
+    pub exclusive_cycles: u64,
+}
+
+struct Frame {
+    addr: u16,              // Entry address of the subroutine
+    sp: u16,                // SP before the call, i.e. SP after the matching return
+    start_cycles: u64,      // Cycle count when the subroutine was entered
+    child_cycles: u64,      // Inclusive cycles of the subroutines called from this one
+    self_cycles: u64,       // Exclusive cycles not yet added to the folded stacks
+}
+
+// CPU state before the profiled instruction was executed
+pub(crate) struct ProfileStart {
+    pc: u16,
+    sp: u16,
+    opcode: u8,
+    cycles: u64,
+
+    // Whether the instruction is a call or a return when taken
+    call: bool,
+    ret: bool,
+}
+
+pub struct Profiler {
+    pc_counts: Vec<u64>,
+    pc_cycles: Vec<u64>,
+    opcode_counts: [u64; 256],
+    opcode_cycles: [u64; 256],
+
+    call_stack: Vec<Frame>,
+    subroutines: HashMap<u16, SubroutineStats>,
+
+    // Exclusive cycles of each call stack, the stack is a list of subroutine addresses starting from the outermost
+    folded: HashMap<Vec<u16>, u64>,
+
+    // Exclusive cycles of the code that runs outside of any tracked subroutine
+    root_cycles: u64,
+}
+
+// Is the opcode CALL, conditional call or RST, or an alternate that the model executes as a call: CALL* on the 8080
+// and RSTV on the 8085. The Z80 uses the alternates as prefixes, e.g. DD E5 is PUSH IX.
+fn is_call(model: CpuModel, opcode: u8) -> bool {
+    let alternate = match model {
+        CpuModel::I8080 => matches!(opcode, 0xdd | 0xed | 0xfd),
+        CpuModel::I8085 => opcode == 0xcb,
+        CpuModel::Z80 => false,
+    };
+
+    alternate || opcode == 0xcd || opcode & 0xc7 == 0xc4 || opcode & 0xc7 == 0xc7
+}
+
+// Is the opcode RET or conditional return, or an alternate that the model executes as a return: RET* on the 8080
+// and RETI or RETN on the Z80, which are ED followed by the next byte
+fn is_ret(model: CpuModel, opcode: u8, next: u8) -> bool {
+    let alternate = match model {
+        CpuModel::I8080 => opcode == 0xd9,
+        CpuModel::I8085 => false,
+        CpuModel::Z80 => opcode == 0xed && next & 0xc7 == 0x45,
+    };
+
+    alternate || opcode == 0xc9 || opcode & 0xc7 == 0xc0
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            pc_counts: vec![0; 0x10000],
+            pc_cycles: vec![0; 0x10000],
+            opcode_counts: [0; 256],
+            opcode_cycles: [0; 256],
+
+            call_stack: Vec::new(),
+            subroutines: HashMap::new(),
+
+            folded: HashMap::new(),
+            root_cycles: 0,
+        }
+    }
+
+    pub fn subroutine_stats(&self, addr: u16) -> Option<SubroutineStats> {
+        self.subroutines.get(&addr).copied()
+    }
+
+    // Move the exclusive cycles of the innermost frame into the folded stacks
+    fn flush_top_frame(&mut self) {
+        let Some(top) = self.call_stack.last_mut() else {
+            return;
+        };
+
+        if top.self_cycles > 0 {
+            let cycles = std::mem::take(&mut top.self_cycles);
+            let stack: Vec<u16> = self.call_stack.iter().map(|frame| frame.addr).collect();
+            *self.folded.entry(stack).or_insert(0) += cycles;
+        }
+    }
+
+    fn enter(&mut self, addr: u16, sp: u16, cycles: u64) {
+        self.flush_top_frame();
+
+        self.call_stack.push(Frame {
+            addr,
+            sp,
+            start_cycles: cycles,
+            child_cycles: 0,
+            self_cycles: 0,
+        });
+
+        self.subroutines.entry(addr).or_default().calls += 1;
+    }
+
+    fn leave(&mut self, cycles: u64) {
+        self.flush_top_frame();
+
+        let Some(frame) = self.call_stack.pop() else {
+            return;
+        };
+
+        let inclusive = cycles - frame.start_cycles;
+        let stats = self.subroutines.entry(frame.addr).or_default();
+        stats.inclusive_cycles += inclusive;
+        stats.exclusive_cycles += inclusive.saturating_sub(frame.child_cycles);
+
+        if let Some(parent) = self.call_stack.last_mut() {
+            parent.child_cycles += inclusive;
+        }
+    }
+
+    // Accepting an interrupt is like a CALL to the interrupt handler
+    pub(crate) fn record_interrupt(&mut self, start: &ProfileStart, pc: u16, cycles: u64) {
+        self.enter(pc, start.sp, start.cycles);
+
+        if let Some(frame) = self.call_stack.last_mut() {
+            frame.self_cycles += cycles - start.cycles;
+        }
+    }
+
+    pub(crate) fn record(&mut self, start: &ProfileStart, pc: u16, sp: u16, cycles: u64) {
+        let spent = cycles - start.cycles;
+
+        self.pc_counts[start.pc as usize] += 1;
+        self.pc_cycles[start.pc as usize] += spent;
+        self.opcode_counts[start.opcode as usize] += 1;
+        self.opcode_cycles[start.opcode as usize] += spent;
+
+        match self.call_stack.last_mut() {
+            Some(frame) => frame.self_cycles += spent,
+            None => self.root_cycles += spent,
+        }
+
+        // A taken call or return is recognized from the return address being pushed or popped
+        if start.call && sp == start.sp.wrapping_sub(2) {
+            self.enter(pc, start.sp, cycles);
+        } else if start.ret && sp == start.sp.wrapping_add(2) {
+            // Also unwind frames that the program abandoned e.g. by popping the return address itself
+            while self.call_stack.last().is_some_and(|frame| frame.sp <= sp) {
+                self.leave(cycles);
+            }
+        }
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Intel8080 {
+    pub fn enable_profiler(&mut self) {
+        if self.profiler.is_none() {
+            self.profiler = Some(Profiler::new());
+        }
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub(crate) fn profile_start(&self) -> ProfileStart {
+        let opcode = self.mem[self.registers.pc];
+        let next = self.mem[(self.registers.pc as u16).wrapping_add(1) as usize];
+
+        ProfileStart {
+            pc: self.registers.pc as u16,
+            sp: self.registers.sp,
+            opcode,
+            cycles: self.cycles,
+
+            call: is_call(self.model, opcode),
+            ret: is_ret(self.model, opcode, next),
+        }
+    }
+
+    pub(crate) fn profile_end(&mut self, start: ProfileStart) {
+        let (pc, sp, cycles) = (self.registers.pc as u16, self.registers.sp, self.cycles);
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(&start, pc, sp, cycles);
+        }
+    }
+
+    pub(crate) fn profile_interrupt(&mut self, start: ProfileStart) {
+        let (pc, cycles) = (self.registers.pc as u16, self.cycles);
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record_interrupt(&start, pc, cycles);
+        }
+    }
+
+    // Disassemble the instruction at the address, the 8080 disassembler is used for the opcodes that the model
+    // executes like the 8080
+    fn mnemonic_at(&self, addr: u16) -> String {
+        let bytes = [0, 1, 2].map(|i| self.mem[addr.wrapping_add(i) as usize]);
+
+        let mnemonic = match self.model {
+            CpuModel::I8080 => None,
+            CpuModel::I8085 => i8085::mnemonic(&bytes),
+            CpuModel::Z80 => z80::mnemonic(&self.mem, addr),
+        };
+
+        mnemonic.unwrap_or_else(|| decode(&bytes, 0).0)
+    }
+
+    // Mnemonic of the opcode without its operands
+    fn opcode_mnemonic(&self, opcode: u8) -> String {
+        let mnemonic = match self.model {
+            CpuModel::I8080 => None,
+            CpuModel::I8085 => i8085::mnemonic(&[opcode, 0, 0]),
+            CpuModel::Z80 => z80::opcode_mnemonic(opcode).map(String::from),
+        };
+
+        // Only the mnemonic is interesting here, so the padded operand value is cut off
+        let mnemonic = mnemonic.unwrap_or_else(|| decode(&[opcode, 0, 0], 0).0);
+        mnemonic.split("  ").next().unwrap_or("").to_string()
+    }
+
+    // Return a report of the hottest addresses, opcodes and subroutines sorted by cycles
+    pub fn profile_report(&self, top: usize) -> Result<String, EmulatorError> {
+        let Some(profiler) = self.profiler.as_ref() else {
+            return Err(EmulatorError::ProfilerDisabled);
+        };
+
+        let total: u64 = profiler.pc_cycles.iter().sum::<u64>().max(1);
+        let percent = |cycles: u64| cycles as f64 * 100.0 / total as f64;
+        let mut report = String::new();
+
+        report += &format!("### Profile: {} cycles ###\n\n", total);
+
+        report += "Hot spots\n  Addr  Count       Cycles      %       Instruction\n";
+        let mut addrs: Vec<usize> = (0..0x10000).filter(|addr| profiler.pc_counts[*addr] > 0).collect();
+        addrs.sort_by_key(|addr| std::cmp::Reverse(profiler.pc_cycles[*addr]));
+
+        for addr in addrs.iter().take(top) {
+            report += &format!(
+                "  {:04X}  {:<10}  {:<10}  {:>6.2}  {}\n",
+                addr, profiler.pc_counts[*addr], profiler.pc_cycles[*addr], percent(profiler.pc_cycles[*addr]),
+                self.mnemonic_at(*addr as u16),
+            );
+        }
+
+        report += "\nOpcodes\n  Op  Count       Cycles      %       Mnemonic\n";
+        let mut opcodes: Vec<usize> = (0..256).filter(|op| profiler.opcode_counts[*op] > 0).collect();
+        opcodes.sort_by_key(|op| std::cmp::Reverse(profiler.opcode_cycles[*op]));
+
+        for op in opcodes.iter().take(top) {
+            let name = self.opcode_mnemonic(*op as u8);
+
+            report += &format!(
+                "  {:02X}  {:<10}  {:<10}  {:>6.2}  {}\n",
+                op,
+                profiler.opcode_counts[*op],
+                profiler.opcode_cycles[*op],
+                percent(profiler.opcode_cycles[*op]),
+                name,
+            );
+        }
+
+        report += "\nSubroutines\n  Addr  Calls       Inclusive   %       Exclusive   %       First instruction\n";
+        let mut subroutines: Vec<(&u16, &SubroutineStats)> = profiler.subroutines.iter().collect();
+        subroutines.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.inclusive_cycles));
+
+        for (addr, stats) in subroutines.iter().take(top) {
+            report += &format!(
+                "  {:04X}  {:<10}  {:<10}  {:>6.2}  {:<10}  {:>6.2}  {}\n",
+                addr, stats.calls, stats.inclusive_cycles, percent(stats.inclusive_cycles), stats.exclusive_cycles,
+                percent(stats.exclusive_cycles), self.mnemonic_at(**addr),
+            );
+        }
+
+        Ok(report)
+    }
+
+    // Export the exclusive cycles per call stack in the folded-stack format used by flamegraph tools
+    pub fn write_folded_stacks(&mut self, path: &Path) -> Result<(), EmulatorError> {
+        let Some(profiler) = self.profiler.as_mut() else {
+            return Err(EmulatorError::ProfilerDisabled);
+        };
+
+        // Frames that are still running have cycles that are not yet in the folded stacks
+        for depth in (1..=profiler.call_stack.len()).rev() {
+            let frame = &mut profiler.call_stack[depth - 1];
+            let cycles = std::mem::take(&mut frame.self_cycles);
+
+            if cycles > 0 {
+                let stack: Vec<u16> = profiler.call_stack[..depth].iter().map(|frame| frame.addr).collect();
+                *profiler.folded.entry(stack).or_insert(0) += cycles;
+            }
+        }
+
+        let mut lines: Vec<String> = profiler.folded.iter().map(|(stack, cycles)| {
+            let names: Vec<String> = stack.iter().map(|addr| format!("sub_{addr:04X}")).collect();
+            format!("main;{} {}", names.join(";"), cycles)
+        }).collect();
+
+        if profiler.root_cycles > 0 {
+            lines.push(format!("main {}", profiler.root_cycles));
+        }
+
+        lines.sort();
+
+        let mut out = BufWriter::new(File::create(path)?);
+        for line in lines {
+            writeln!(out, "{line}")?;
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_and_returns_of_the_model() {
+        assert!(is_call(CpuModel::I8080, 0xdd));
+        assert!(!is_call(CpuModel::Z80, 0xdd));
+        assert!(is_call(CpuModel::I8085, 0xcb));
+        assert!(is_call(CpuModel::Z80, 0xcd));
+
+        assert!(is_ret(CpuModel::I8080, 0xd9, 0x00));
+        assert!(!is_ret(CpuModel::Z80, 0xd9, 0x00));
+        assert!(is_ret(CpuModel::Z80, 0xed, 0x4d));
+        assert!(is_ret(CpuModel::Z80, 0xed, 0x45));
+        assert!(!is_ret(CpuModel::Z80, 0xed, 0x44));
+    }
+    #[test]
+    fn mnemonics_of_the_model() {
+        // DJNZ 0000h; LD (IX+40h),12h; RIM
+        let mut cpu = Intel8080::new();
+        cpu.load_rom(&[0x10, 0xfe, 0xdd, 0x36, 0x40, 0x12, 0x20]);
+        assert_eq!(cpu.mnemonic_at(0), "NOP*");
+        assert_eq!(cpu.opcode_mnemonic(0xdd), "CALL*");
+
+        cpu.set_model(CpuModel::Z80);
+        assert_eq!(cpu.mnemonic_at(0), "DJNZ      0x0000");
+        assert_eq!(cpu.mnemonic_at(2), "DD 36 40 12");
+        assert_eq!(cpu.opcode_mnemonic(0xdd), "IX prefix");
+        assert_eq!(cpu.opcode_mnemonic(0x3e), "MVI A");
+
+        cpu.set_model(CpuModel::I8085);
+        assert_eq!(cpu.mnemonic_at(6), "RIM");
+        assert_eq!(cpu.opcode_mnemonic(0x28), "LDHI");
+    }
+}
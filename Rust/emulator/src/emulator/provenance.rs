@@ -0,0 +1,101 @@
+/*
+Memory write provenance - Shadow map that remembers which instruction last wrote each byte of memory
+
+Unlike the rewind buffer this covers the whole run, but only the latest write to every address is kept. Useful for
+finding out what corrupted a memory location, e.g. the RAM at 0x20xx in Space Invaders.
+
+Note that the map is not rewound by step_back() or restored by save states, it only tracks executed writes.
+*/
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::emulator::Intel8080;
+use crate::errors::EmulatorError;
+
+
+// Last write into a single memory address
+#[derive(Clone, Copy)]
+pub struct WriteRecord {
+    pub pc: u16,        // Address of the instruction that did the write
+    pub cycles: u64,    // Cycle count when the writing instruction finished
+}
+
+pub struct ProvenanceMap {
+    entries: Vec<Option<WriteRecord>>,
+}
+
+impl ProvenanceMap {
+    pub fn new() -> Self {
+        ProvenanceMap {
+            entries: vec![None; 0x10000],
+        }
+    }
+
+    pub fn record(&mut self, addr: u16, pc: u16, cycles: u64) {
+        self.entries[addr as usize] = Some(WriteRecord { pc, cycles });
+    }
+
+    pub fn get(&self, addr: u16) -> Option<WriteRecord> {
+        self.entries[addr as usize]
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
+
+impl Default for ProvenanceMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Intel8080 {
+    // Start tracking the last writer of every memory address
+    pub fn enable_provenance(&mut self) {
+        if self.provenance.is_none() {
+            self.provenance = Some(ProvenanceMap::new());
+        }
+    }
+
+    pub fn disable_provenance(&mut self) {
+        self.provenance = None;
+    }
+
+    pub fn provenance(&self) -> Option<&ProvenanceMap> {
+        self.provenance.as_ref()
+    }
+
+    // Return the instruction that last wrote to the address, None if not written or tracking is disabled
+    pub fn last_write(&self, addr: u16) -> Option<WriteRecord> {
+        self.provenance.as_ref()?.get(addr)
+    }
+
+    /*
+    Write a text report of the written addresses in the range with their current value and last writer e.g.
+        Addr  Value  PC    Cycle
+        20C0  0A     0135  2017
+    */
+    pub fn write_provenance_report(&self, path: &Path, start: u16, end: u16) -> Result<(), EmulatorError> {
+        let Some(provenance) = self.provenance.as_ref() else {
+            return Err(EmulatorError::ProvenanceDisabled);
+        };
+
+        let mut out = BufWriter::new(File::create(path)?);
+
+        writeln!(out, "# Memory write provenance report {start:04X}-{end:04X}")?;
+        writeln!(out, "# Addr  Value  PC    Cycle")?;
+
+        for addr in start..=end {
+            if let Some(record) = provenance.get(addr) {
+                let val = self.mem[addr as usize];
+                writeln!(out, "{addr:04X}    {val:02X}     {:04X}  {}", record.pc, record.cycles)?;
+            }
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,461 @@
+/*
+Rewind buffer for reverse stepping
+
+Every executed instruction is recorded as a compact delta: the register state before and after the instruction and
+the memory bytes it overwrote (old and new value). Deltas can be applied in both directions, so stepping backwards
+undoes the latest delta and stepping forwards again redoes it without executing anything.
+
+Full save states are taken as checkpoints every N instructions. When jumping far away in the history the nearest
+checkpoint is restored and the remaining deltas are applied from there, which also restores the device states that
+the deltas don't track.
+
+Both the deltas and the checkpoints are kept in ring buffers, so only the recent past is available.
+*/
+
+use std::collections::VecDeque;
+
+use crate::emulator::Intel8080;
+use crate::emulator::i8085::I8085State;
+use crate::emulator::z80::Z80State;
+use crate::errors::EmulatorError;
+use crate::snapshot;
+
+
+// Registers, flags and the other small pieces of CPU state that an instruction can change, including the pins of the
+// 8085 and the extra registers of the Z80
+#[derive(Clone, Copy)]
+pub struct RegisterState {
+    regs: [u8; 8],  // A, F, B, C, D, E, H, L
+    sp: u16,
+    pc: u16,
+    halted: bool,
+    int: bool,
+    cycles: u64,
+    i8085: I8085State,
+    z80: Z80State,
+}
+
+// Single memory write done by an instruction
+#[derive(Clone, Copy)]
+pub struct MemWrite {
+    pub addr: u16,
+    pub old: u8,
+    pub new: u8,
+}
+
+struct InstrRecord {
+    before: RegisterState,
+    after: RegisterState,
+    writes: Vec<MemWrite>,
+}
+
+struct Checkpoint {
+    index: u64,
+    state: Vec<u8>,
+}
+
+// Answer to "who last wrote to this address"
+pub struct WriterInfo {
+    pub index: u64,     // Number of the instruction in the execution history
+    pub pc: u16,        // Address of the instruction
+    pub cycles: u64,    // Cycle count before the instruction was executed
+    pub old: u8,
+    pub new: u8,
+}
+
+pub struct RewindBuffer {
+    records: VecDeque<InstrRecord>,
+    checkpoints: VecDeque<Checkpoint>,
+
+    // Max amount of instruction deltas to keep
+    capacity: usize,
+
+    // Amount of instructions between checkpoints
+    checkpoint_interval: u64,
+
+    // Instruction index of the first record in the ring buffer
+    first_index: u64,
+
+    // Instruction index of the current CPU state, less than the end of the history after stepping backwards
+    position: u64,
+
+    // Writes done by the instruction that is currently being executed
+    pending_writes: Vec<MemWrite>,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize, checkpoint_interval: u64) -> Self {
+        RewindBuffer {
+            records: VecDeque::with_capacity(capacity),
+            checkpoints: VecDeque::new(),
+
+            capacity,
+            checkpoint_interval: checkpoint_interval.max(1),
+
+            first_index: 0,
+            position: 0,
+
+            pending_writes: Vec::new(),
+        }
+    }
+
+    pub fn record_write(&mut self, addr: u16, old: u8, new: u8) {
+        self.pending_writes.push(MemWrite { addr, old, new });
+    }
+
+    // Index one past the latest recorded instruction
+    fn end_index(&self) -> u64 {
+        self.first_index + self.records.len() as u64
+    }
+
+    // Oldest instruction index that can still be rewound to
+    pub fn oldest_index(&self) -> u64 {
+        self.first_index
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    fn record(&self, index: u64) -> &InstrRecord {
+        &self.records[(index - self.first_index) as usize]
+    }
+
+    // Forget the future when the execution continues from a rewound point, because it may diverge from the recording
+    fn truncate_future(&mut self) {
+        if self.position == self.end_index() {
+            return;
+        }
+
+        let keep = (self.position - self.first_index) as usize;
+        self.records.truncate(keep);
+
+        let position = self.position;
+        self.checkpoints.retain(|checkpoint| checkpoint.index <= position);
+    }
+
+    fn push_record(&mut self, record: InstrRecord) {
+        self.records.push_back(record);
+        self.position += 1;
+
+        if self.records.len() > self.capacity {
+            self.records.pop_front();
+            self.first_index += 1;
+        }
+
+        // Drop the checkpoints before the oldest record, the deltas from them to the history are gone
+        while self.checkpoints.front().is_some_and(|checkpoint| checkpoint.index < self.first_index) {
+            self.checkpoints.pop_front();
+        }
+    }
+}
+
+impl Intel8080 {
+    // Start recording the execution history, replaces any earlier history
+    pub fn enable_rewind(&mut self, capacity: usize, checkpoint_interval: u64) {
+        self.rewind = Some(RewindBuffer::new(capacity, checkpoint_interval));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    pub fn rewind_buffer(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    pub(crate) fn register_state(&self) -> RegisterState {
+        let regs = &self.registers;
+
+        RegisterState {
+            regs: [regs.a, regs.f.get_model_flags(self.model), regs.b, regs.c, regs.d, regs.e, regs.h, regs.l],
+            sp: regs.sp,
+            pc: regs.pc as u16,
+            halted: self.halted,
+            int: self.int,
+            cycles: self.cycles,
+            i8085: self.i8085,
+            z80: self.z80,
+        }
+    }
+
+    pub(crate) fn restore_register_state(&mut self, state: &RegisterState) {
+        let [a, f, b, c, d, e, h, l] = state.regs;
+        self.registers.a = a;
+        self.registers.f.set_flags(f);
+        self.registers.b = b;
+        self.registers.c = c;
+        self.registers.d = d;
+        self.registers.e = e;
+        self.registers.h = h;
+        self.registers.l = l;
+
+        self.registers.sp = state.sp;
+        self.registers.pc = state.pc as usize;
+
+        self.halted = state.halted;
+        self.int = state.int;
+        self.cycles = state.cycles;
+        self.i8085 = state.i8085;
+        self.z80 = state.z80;
+        self.undocumented_stop = None;
+    }
+
+    // Execute a single instruction, or accept an interrupt, and record its delta into the rewind buffer
+    pub(crate) fn record_step<F: FnOnce(&mut Intel8080)>(&mut self, action: F) {
+        let before = self.register_state();
+
+        // Take the save state before borrowing the buffer, the buffer itself is not part of the save state
+        let rewind = self.rewind.as_ref().unwrap();
+        let checkpoint_due = rewind.position.is_multiple_of(rewind.checkpoint_interval)
+            && rewind.checkpoints.back().is_none_or(|checkpoint| checkpoint.index != rewind.position);
+        let state = if checkpoint_due { Some(snapshot::save_state(self)) } else { None };
+
+        let rewind = self.rewind.as_mut().unwrap();
+        rewind.truncate_future();
+
+        if let Some(state) = state {
+            rewind.checkpoints.push_back(Checkpoint { index: rewind.position, state });
+        }
+
+        action(self);
+
+        let after = self.register_state();
+        let rewind = self.rewind.as_mut().unwrap();
+        let writes = std::mem::take(&mut rewind.pending_writes);
+        rewind.push_record(InstrRecord { before, after, writes });
+    }
+
+    // Undo the latest executed instruction, returns false if there is no more history
+    pub fn step_back(&mut self) -> bool {
+        let Some(rewind) = self.rewind.as_mut() else {
+            return false;
+        };
+
+        if rewind.position == rewind.first_index {
+            return false;
+        }
+
+        rewind.position -= 1;
+        let record = rewind.record(rewind.position);
+        let (before, writes) = (record.before, record.writes.clone());
+
+        // Undo in reverse order in case the instruction wrote the same address twice, storing also updates the mirrors
+        for write in writes.iter().rev() {
+            self.store(write.addr, write.old);
+        }
+
+        self.restore_register_state(&before);
+        true
+    }
+
+    // Redo an instruction that was undone with step_back(), returns false if already at the end of the history
+    pub fn step_forward(&mut self) -> bool {
+        let Some(rewind) = self.rewind.as_mut() else {
+            return false;
+        };
+
+        if rewind.position == rewind.end_index() {
+            return false;
+        }
+
+        let record = rewind.record(rewind.position);
+        let (after, writes) = (record.after, record.writes.clone());
+        rewind.position += 1;
+
+        for write in writes.iter() {
+            self.store(write.addr, write.new);
+        }
+
+        self.restore_register_state(&after);
+        true
+    }
+
+    // Step backwards until PC is at one of the breakpoints, returns false if the history ran out before that
+    pub fn run_back(&mut self, breakpoints: &[u16]) -> bool {
+        while self.step_back() {
+            if breakpoints.contains(&(self.registers.pc as u16)) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Move to the given instruction index in the history, using the nearest checkpoint if that is closer
+    pub fn rewind_to(&mut self, index: u64) -> Result<(), EmulatorError> {
+        let Some(rewind) = self.rewind.as_ref() else {
+            return Err(EmulatorError::RewindUnavailable(index));
+        };
+
+        if index < rewind.first_index || index > rewind.end_index() {
+            return Err(EmulatorError::RewindUnavailable(index));
+        }
+
+        let distance = rewind.position.abs_diff(index);
+        let checkpoint = rewind.checkpoints.iter().rev().find(|checkpoint| checkpoint.index <= index);
+
+        if let Some(checkpoint) = checkpoint {
+            if index - checkpoint.index < distance {
+                let (checkpoint_index, state) = (checkpoint.index, checkpoint.state.clone());
+                let mut rewind = self.rewind.take().unwrap();
+
+                let result = snapshot::load_state(self, &state);
+                rewind.position = checkpoint_index;
+                self.rewind = Some(rewind);
+                result?;
+            }
+        }
+
+        while self.rewind.as_ref().unwrap().position > index {
+            self.step_back();
+        }
+
+        while self.rewind.as_ref().unwrap().position < index {
+            self.step_forward();
+        }
+
+        Ok(())
+    }
+
+    // Find the latest instruction before the given history index that wrote to the address
+    pub fn last_writer_at(&self, addr: u16, index: u64) -> Option<WriterInfo> {
+        let rewind = self.rewind.as_ref()?;
+        let end = index.min(rewind.end_index());
+
+        (rewind.first_index..end).rev().find_map(|i| {
+            let record = rewind.record(i);
+
+            record.writes.iter().rev().find(|write| write.addr == addr).map(|write| WriterInfo {
+                index: i,
+                pc: record.before.pc,
+                cycles: record.before.cycles,
+                old: write.old,
+                new: write.new,
+            })
+        })
+    }
+
+    // Find the latest instruction before the current point in time that wrote to the address
+    pub fn last_writer(&self, addr: u16) -> Option<WriterInfo> {
+        let position = self.rewind.as_ref()?.position;
+        self.last_writer_at(addr, position)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Increments the byte at 0x2000 in a loop: LXI H,2000h; INR M; JMP 0003h
+    fn counting_cpu() -> Intel8080 {
+        let mut cpu = Intel8080::new();
+        cpu.load_rom(&[0x21, 0x00, 0x20, 0x34, 0xc3, 0x03, 0x00]);
+        cpu
+    }
+
+    fn run_steps(cpu: &mut Intel8080, steps: u64) {
+        for _ in 0..steps {
+            cpu.step();
+        }
+    }
+
+    fn assert_same_state(cpu: &Intel8080, expected: &Intel8080) {
+        assert_eq!(cpu.dump_registers(), expected.dump_registers());
+        assert_eq!(cpu.memory(), expected.memory());
+    }
+
+    #[test]
+    fn step_back_and_forward_restore_the_state() {
+        let mut cpu = counting_cpu();
+        cpu.enable_rewind(1000, 100);
+        run_steps(&mut cpu, 50);
+
+        for _ in 0..21 {
+            assert!(cpu.step_back());
+        }
+
+        let mut expected = counting_cpu();
+        run_steps(&mut expected, 29);
+        assert_same_state(&cpu, &expected);
+
+        while cpu.step_forward() {}
+
+        run_steps(&mut expected, 21);
+        assert_same_state(&cpu, &expected);
+    }
+
+    #[test]
+    fn step_back_stops_at_the_start_of_the_history() {
+        let mut cpu = counting_cpu();
+        cpu.enable_rewind(10, 5);
+        run_steps(&mut cpu, 25);
+
+        let mut steps = 0;
+        while cpu.step_back() {
+            steps += 1;
+        }
+
+        assert_eq!(steps, 10);
+        assert_eq!(cpu.rewind_buffer().unwrap().oldest_index(), 15);
+    }
+
+    #[test]
+    fn rewind_to_after_the_history_wrapped() {
+        let mut cpu = counting_cpu();
+        cpu.enable_rewind(100, 100);
+        run_steps(&mut cpu, 250);
+
+        cpu.rewind_to(155).unwrap();
+        assert_eq!(cpu.rewind_buffer().unwrap().position(), 155);
+
+        let mut expected = counting_cpu();
+        run_steps(&mut expected, 155);
+        assert_same_state(&cpu, &expected);
+
+        assert!(cpu.step_forward());
+        expected.step();
+        assert_same_state(&cpu, &expected);
+    }
+
+    #[test]
+    fn rewind_to_outside_the_history_fails() {
+        let mut cpu = counting_cpu();
+        cpu.enable_rewind(100, 10);
+        run_steps(&mut cpu, 250);
+
+        assert!(cpu.rewind_to(149).is_err());
+        assert!(cpu.rewind_to(251).is_err());
+        assert!(cpu.rewind_to(150).is_ok());
+    }
+
+    #[test]
+    fn stepping_restores_the_mirrors() {
+        let mut cpu = counting_cpu();
+        cpu.mirror_memory(0x4000, 0x2000, 0x2000);
+        cpu.enable_rewind(100, 10);
+        run_steps(&mut cpu, 4);
+        assert_eq!(cpu.read_byte(0x4000), 2);
+
+        assert!(cpu.step_back());
+        assert!(cpu.step_back());
+        assert_eq!((cpu.read_byte(0x2000), cpu.read_byte(0x4000)), (1, 1));
+
+        assert!(cpu.step_forward());
+        assert!(cpu.step_forward());
+        assert_eq!((cpu.read_byte(0x2000), cpu.read_byte(0x4000)), (2, 2));
+    }
+
+    #[test]
+    fn last_writer_finds_the_latest_write() {
+        let mut cpu = counting_cpu();
+        cpu.enable_rewind(100, 10);
+        run_steps(&mut cpu, 7);
+
+        // Instructions 1, 3 and 5 are the INR M
+        let writer = cpu.last_writer(0x2000).unwrap();
+        assert_eq!((writer.index, writer.pc, writer.old, writer.new), (5, 0x0003, 2, 3));
+        assert!(cpu.last_writer(0x2001).is_none());
+    }
+}
@@ -0,0 +1,1273 @@
+/*
+Zilog Z80 - the Z80 core mode, enabled with CpuModel::Z80
+
+The Z80 runs the 8080 programs on the same registers and memory, but decodes the opcodes that are alternates on the
+8080 as its own instructions and prefixes:
+    0x08        EX AF,AF'
+    0x10        DJNZ d
+    0x18        JR d
+    0x20-0x38   JR NZ/Z/NC/C,d
+    0xD9        EXX
+    0xCB        Rotates, shifts and the bit instructions BIT, RES and SET
+    0xDD, 0xFD  Use IX or IY instead of HL, (HL) becomes (IX+d) or (IY+d)
+    0xED        Block instructions, 16-bit ADC/SBC, IN/OUT (C), NEG, interrupt modes, I and R registers, RLD/RRD
+
+Registers besides the ones of the 8080:
+    AF' BC' DE' HL'     Alternate register set, swapped with EX AF,AF' and EXX
+    IX IY               Index registers, also as the undocumented 8-bit halves IXH, IXL, IYH and IYL
+    I                   Interrupt vector base for interrupt mode 2
+    R                   Memory refresh counter, counts the opcode fetches in its lower 7 bits
+
+The flags mostly work like on the 8080 except that the parity flag is the overflow flag of the arithmetic
+instructions (P/V), and bit 1 is the N flag that DAA uses to tell a subtraction from an addition. The undocumented
+flags in bits 3 and 5 always read as 0.
+
+Interrupts from interrupt() depend on the mode set by IM:
+    IM 0    The RST instruction on the data bus is executed, like on the 8080
+    IM 1    RST 38h, whatever is on the data bus
+    IM 2    Jump to the vector at the address I * 256 + the RST opcode on the data bus
+nmi() triggers the non-maskable interrupt at 0x66.
+*/
+
+use disassembler::coverage;
+
+use super::{CpuModel, FlagRegister, Intel8080};
+use crate::errors::EmulatorError;
+use crate::snapshot::{StateReader, StateWriter};
+
+// Clock cycles taken by each unprefixed opcode on the Z80. Conditional instructions are listed with the cycles when
+// the condition is false, relative jumps take 5, calls 7 and returns 6 extra cycles when it is true. The prefixes are
+// listed with the cycles of their own fetch.
+pub(super) const CYCLES: [u8; 256] = [
+//  x0  x1  x2  x3  x4  x5  x6  x7  x8  x9  xA  xB  xC  xD  xE  xF
+     4, 10,  7,  6,  4,  4,  7,  4,  4, 11,  7,  6,  4,  4,  7,  4,    // 0x0x
+     8, 10,  7,  6,  4,  4,  7,  4, 12, 11,  7,  6,  4,  4,  7,  4,    // 0x1x
+     7, 10, 16,  6,  4,  4,  7,  4,  7, 11, 16,  6,  4,  4,  7,  4,    // 0x2x
+     7, 10, 13,  6, 11, 11, 10,  4,  7, 11, 13,  6,  4,  4,  7,  4,    // 0x3x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x4x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x5x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x6x
+     7,  7,  7,  7,  7,  7,  4,  7,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x7x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x8x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0x9x
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0xAx
+     4,  4,  4,  4,  4,  4,  7,  4,  4,  4,  4,  4,  4,  4,  7,  4,    // 0xBx
+     5, 10, 10, 10, 10, 11,  7, 11,  5, 10, 10,  4, 10, 17,  7, 11,    // 0xCx
+     5, 10, 10, 11, 10, 11,  7, 11,  5,  4, 10, 11, 10,  4,  7, 11,    // 0xDx
+     5, 10, 10, 19, 10, 11,  7, 11,  5,  4, 10,  4, 10,  4,  7, 11,    // 0xEx
+     5, 10, 10,  4, 10, 11,  7, 11,  5,  6, 10,  4, 10,  4,  7, 11,    // 0xFx
+];
+
+const JR_TAKEN_CYCLES: u64 = 5;
+const CALL_TAKEN_CYCLES: u64 = 7;
+const RET_TAKEN_CYCLES: u64 = 6;
+const BLOCK_REPEAT_CYCLES: u64 = 5;
+
+// Extra cycles of the DD and FD prefixes, more when (HL) becomes (IX+d) and the displacement is added
+const INDEX_CYCLES: u64 = 4;
+const INDEX_MEMORY_CYCLES: u64 = 12;
+const INDEX_MEMORY_IMMEDIATE_CYCLES: u64 = 9;
+
+const NMI_VECTOR: u16 = 0x66;
+const IM1_VECTOR: u16 = 0x38;
+const NMI_CYCLES: u64 = 11;
+const IM0_CYCLES: u64 = 13;
+const IM2_CYCLES: u64 = 19;
+
+// Register numbers of the opcodes, 6 is the memory operand (HL)
+const REG_H: u8 = 4;
+const REG_L: u8 = 5;
+const REG_MEM: u8 = 6;
+
+// Interrupt modes set by IM 0, IM 1 and IM 2, including the undocumented opcodes
+const IM_MODES: [u8; 8] = [0, 0, 1, 2, 0, 0, 1, 2];
+
+
+// Register that the DD and FD prefixes use instead of HL
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Index {
+    Hl,
+    Ix,
+    Iy,
+}
+
+// Registers and interrupt state that the Z80 has on top of the 8080, IFF1 is the interrupt enable of the 8080
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct Z80State {
+    af_alt: u16,
+    bc_alt: u16,
+    de_alt: u16,
+    hl_alt: u16,
+
+    ix: u16,
+    iy: u16,
+
+    i: u8,
+    r: u8,
+
+    // Interrupt mode and the interrupt enable that NMI saves IFF1 into
+    im: u8,
+    iff2: bool,
+}
+
+impl Z80State {
+    pub(super) fn save_state(&self, writer: &mut StateWriter) {
+        for reg in [self.af_alt, self.bc_alt, self.de_alt, self.hl_alt, self.ix, self.iy] {
+            writer.write_u16(reg);
+        }
+
+        writer.write_u8(self.i);
+        writer.write_u8(self.r);
+        writer.write_u8(self.im);
+        writer.write_bool(self.iff2);
+    }
+
+    pub(super) fn load_state(reader: &mut StateReader) -> Result<Self, EmulatorError> {
+        let mut regs = [0u16; 6];
+        for reg in regs.iter_mut() {
+            *reg = reader.read_u16()?;
+        }
+
+        let [af_alt, bc_alt, de_alt, hl_alt, ix, iy] = regs;
+        let (i, r) = (reader.read_u8()?, reader.read_u8()?);
+
+        let im = reader.read_u8()?;
+        if im > 2 {
+            return Err(EmulatorError::SaveStateInvalid(format!("invalid Z80 interrupt mode {im}")));
+        }
+
+        Ok(Z80State { af_alt, bc_alt, de_alt, hl_alt, ix, iy, i, r, im, iff2: reader.read_bool()? })
+    }
+}
+
+
+// True if the unprefixed opcode has (HL) as an operand, which the index prefixes turn into (IX+d)
+fn uses_memory_operand(opcode: u8) -> bool {
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 0x07, opcode & 0x07);
+
+    match x {
+        0 => y == REG_MEM && (4..=6).contains(&z),
+        1 => (y == REG_MEM) != (z == REG_MEM),
+        2 => z == REG_MEM,
+        _ => false,
+    }
+}
+
+// Length of the instruction at the address in bytes including its prefixes. A prefix that is followed by another
+// prefix acts as a NOP on its own.
+pub(super) fn instruction_length(mem: &[u8], addr: u16) -> usize {
+    let byte = |offset: u16| mem[addr.wrapping_add(offset) as usize];
+
+    match byte(0) {
+        0xcb => 2,
+        // LD (nn),rr and LD rr,(nn)
+        0xed if byte(1) & 0xc7 == 0x43 => 4,
+        0xed => 2,
+        0xdd | 0xfd => match byte(1) {
+            0xdd | 0xed | 0xfd => 1,
+            // DD CB d op
+            0xcb => 4,
+            opcode => 1 + unprefixed_length(opcode) + uses_memory_operand(opcode) as usize,
+        },
+        opcode => unprefixed_length(opcode),
+    }
+}
+
+// Length of the unprefixed opcode, like on the 8080 except for the relative jumps
+fn unprefixed_length(opcode: u8) -> usize {
+    match opcode {
+        // DJNZ and JR
+        0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => 2,
+        _ => coverage::instruction_length(opcode),
+    }
+}
+
+// Mnemonic of the opcodes that the Z80 decodes differently from the 8080, the others keep the mnemonics of the 8080
+// disassembler
+pub(super) fn opcode_mnemonic(opcode: u8) -> Option<&'static str> {
+    let name = match opcode {
+        0x08 => "EX AF,AF'",
+        0x10 => "DJNZ",
+        0x18 => "JR",
+        0x20 => "JR NZ",
+        0x28 => "JR Z",
+        0x30 => "JR NC",
+        0x38 => "JR C",
+        0xd9 => "EXX",
+        0xcb => "CB prefix",
+        0xdd => "IX prefix",
+        0xed => "ED prefix",
+        0xfd => "IY prefix",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+// Mnemonic of the instruction at the address in the format of the 8080 disassembler, for the opcodes that it doesn't
+// know. The relative jumps show their target and the prefixed instructions their bytes.
+pub(super) fn mnemonic(mem: &[u8], addr: u16) -> Option<String> {
+    let byte = |offset: u16| mem[addr.wrapping_add(offset) as usize];
+    let name = opcode_mnemonic(byte(0))?;
+
+    let text = match byte(0) {
+        0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
+            let target = addr.wrapping_add(2).wrapping_add(byte(1) as i8 as u16);
+            format!("{:<9} {:#06X}", name, target)
+        },
+        0xcb | 0xdd | 0xed | 0xfd => {
+            let bytes: Vec<String> = (0..instruction_length(mem, addr) as u16)
+                .map(|offset| format!("{:02X}", byte(offset)))
+                .collect();
+            bytes.join(" ")
+        },
+        _ => String::from(name),
+    };
+
+    Some(text)
+}
+
+// Cycles of the ED prefixed opcodes including the prefix, undefined ones act as two NOPs
+fn ed_cycles(opcode: u8) -> u64 {
+    match opcode {
+        0x47 | 0x4f | 0x57 | 0x5f => 9,
+        0x67 | 0x6f => 18,
+        0x40..=0x7f => match opcode & 0x07 {
+            0 | 1 => 12,
+            2 => 15,
+            3 => 20,
+            5 => 14,
+            _ => 8,
+        },
+        0xa0..=0xa3 | 0xa8..=0xab | 0xb0..=0xb3 | 0xb8..=0xbb => 16,
+        _ => 8,
+    }
+}
+
+fn set_sign_zero(f: &mut FlagRegister, val: u8) {
+    f.sign = val & 0x80 != 0;
+    f.zero = val == 0;
+}
+
+
+impl Intel8080 {
+    // Index registers IX and IY of the Z80
+    pub fn get_index_registers(&self) -> (u16, u16) {
+        (self.z80.ix, self.z80.iy)
+    }
+
+    // Trigger the non-maskable interrupt of the Z80, it's taken even when interrupts are disabled
+    pub fn nmi(&mut self) {
+        self.z80.iff2 = self.int;
+        self.enter_interrupt(NMI_VECTOR, NMI_CYCLES);
+    }
+
+    // Take an interrupt from the data bus in the interrupt mode of the Z80, interrupts must be enabled
+    pub(super) fn z80_interrupt(&mut self, rst_num: u8) {
+        let rst_opcode: u8 = 0xc7 | (rst_num & 0x07) << 3;
+        self.z80.iff2 = false;
+
+        match self.z80.im {
+            0 => self.enter_interrupt((rst_num & 0x07) as u16 * 0x08, IM0_CYCLES),
+            1 => self.enter_interrupt(IM1_VECTOR, IM0_CYCLES),
+            _ => {
+                let table: u16 = (self.z80.i as u16) << 8 | rst_opcode as u16;
+                let vector: u16 = self.read_word(table);
+                self.enter_interrupt(vector, IM2_CYCLES);
+            },
+        }
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let val: u8 = self.mem[self.registers.pc];
+        self.registers.pc = (self.registers.pc + 1) & 0xFFFF;
+        val
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let low: u8 = self.fetch();
+        (self.fetch() as u16) << 8 | low as u16
+    }
+
+    // Fetch an opcode or a prefix, which counts up the lower 7 bits of R
+    fn fetch_opcode(&mut self) -> u8 {
+        self.z80.r = (self.z80.r & 0x80) | (self.z80.r.wrapping_add(1) & 0x7F);
+        self.fetch()
+    }
+
+    fn read_word(&mut self, addr: u16) -> u16 {
+        let low: u8 = self.read_mem(addr as usize);
+        (self.read_mem(addr.wrapping_add(1) as usize) as u16) << 8 | low as u16
+    }
+
+    fn write_word(&mut self, addr: u16, val: u16) {
+        self.write_mem(addr as usize, val as u8);
+        self.write_mem(addr.wrapping_add(1) as usize, (val >> 8) as u8);
+    }
+
+    fn index_reg(&self, index: Index) -> u16 {
+        match index {
+            Index::Hl => self.registers.get_reg_pair("HL"),
+            Index::Ix => self.z80.ix,
+            Index::Iy => self.z80.iy,
+        }
+    }
+
+    fn set_index_reg(&mut self, index: Index, val: u16) {
+        match index {
+            Index::Hl => self.registers.set_reg_pair("HL", val),
+            Index::Ix => self.z80.ix = val,
+            Index::Iy => self.z80.iy = val,
+        }
+    }
+
+    // Address of the memory operand, (HL) or (IX+d) and (IY+d) with the displacement that follows the opcode
+    fn memory_operand(&mut self, index: Index) -> u16 {
+        match index {
+            Index::Hl => self.registers.get_reg_pair("HL"),
+            _ => {
+                let displacement: i8 = self.fetch() as i8;
+                self.index_reg(index).wrapping_add(displacement as u16)
+            },
+        }
+    }
+
+    // Register by its number in the opcode, H and L are the halves of the index register with a prefix
+    fn reg_num(&self, reg: u8, index: Index) -> u8 {
+        let regs = &self.registers;
+
+        match reg {
+            0 => regs.b,
+            1 => regs.c,
+            2 => regs.d,
+            3 => regs.e,
+            REG_H if index == Index::Hl => regs.h,
+            REG_L if index == Index::Hl => regs.l,
+            REG_H => (self.index_reg(index) >> 8) as u8,
+            REG_L => self.index_reg(index) as u8,
+            _ => regs.a,
+        }
+    }
+
+    fn set_reg_num(&mut self, reg: u8, index: Index, val: u8) {
+        match reg {
+            0 => self.registers.b = val,
+            1 => self.registers.c = val,
+            2 => self.registers.d = val,
+            3 => self.registers.e = val,
+            REG_H if index == Index::Hl => self.registers.h = val,
+            REG_L if index == Index::Hl => self.registers.l = val,
+            REG_H => self.set_index_reg(index, (self.index_reg(index) & 0x00FF) | (val as u16) << 8),
+            REG_L => self.set_index_reg(index, (self.index_reg(index) & 0xFF00) | val as u16),
+            _ => self.registers.a = val,
+        }
+    }
+
+    // Register or the memory operand at the address
+    fn operand(&mut self, reg: u8, index: Index, addr: u16) -> u8 {
+        if reg == REG_MEM {
+            self.read_mem(addr as usize)
+        } else {
+            self.reg_num(reg, index)
+        }
+    }
+
+    fn set_operand(&mut self, reg: u8, index: Index, addr: u16, val: u8) {
+        if reg == REG_MEM {
+            self.write_mem(addr as usize, val);
+        } else {
+            self.set_reg_num(reg, index, val);
+        }
+    }
+
+    // Register pair by its number in the opcode, 3 is SP
+    fn reg_pair_num(&self, pair: u8, index: Index) -> u16 {
+        match pair {
+            0 => self.registers.get_reg_pair("BC"),
+            1 => self.registers.get_reg_pair("DE"),
+            2 => self.index_reg(index),
+            _ => self.registers.sp,
+        }
+    }
+
+    fn set_reg_pair_num(&mut self, pair: u8, index: Index, val: u16) {
+        match pair {
+            0 => self.registers.set_reg_pair("BC", val),
+            1 => self.registers.set_reg_pair("DE", val),
+            2 => self.set_index_reg(index, val),
+            _ => self.registers.sp = val,
+        }
+    }
+
+    // NZ, Z, NC, C, PO, PE, P and M
+    fn condition(&self, cond: u8) -> bool {
+        let f = &self.registers.f;
+
+        match cond {
+            0 => !f.zero,
+            1 => f.zero,
+            2 => !f.carry,
+            3 => f.carry,
+            4 => !f.parity,
+            5 => f.parity,
+            6 => !f.sign,
+            _ => f.sign,
+        }
+    }
+
+    fn jump_relative(&mut self, condition: bool) {
+        let displacement: i8 = self.fetch() as i8;
+
+        if condition {
+            self.registers.pc = (self.registers.pc as u16).wrapping_add(displacement as u16) as usize;
+            self.cycles += JR_TAKEN_CYCLES;
+        }
+    }
+
+    // ADD, ADC, SUB, SBC, AND, XOR, OR and CP with the accumulator
+    fn alu(&mut self, op: u8, val: u8) {
+        let a: u8 = self.registers.a;
+        let f = &mut self.registers.f;
+
+        let result: u8 = match op {
+            0 | 1 => {
+                let carry: u8 = (op == 1 && f.carry) as u8;
+                let sum: u16 = a as u16 + val as u16 + carry as u16;
+                let result: u8 = sum as u8;
+
+                f.aux_carry = (a & 0xF) + (val & 0xF) + carry > 0xF;
+                f.parity = ((a ^ result) & (val ^ result) & 0x80) != 0;
+                f.subtract = false;
+                f.carry = sum > 0xFF;
+                result
+            },
+            2 | 3 | 7 => {
+                let borrow: u8 = (op == 3 && f.carry) as u8;
+                let result: u8 = a.wrapping_sub(val).wrapping_sub(borrow);
+
+                f.aux_carry = (a & 0xF) < (val & 0xF) + borrow;
+                f.parity = ((a ^ val) & (a ^ result) & 0x80) != 0;
+                f.subtract = true;
+                f.carry = (a as u16) < val as u16 + borrow as u16;
+                result
+            },
+            _ => {
+                let result: u8 = match op {
+                    4 => a & val,
+                    5 => a ^ val,
+                    _ => a | val,
+                };
+
+                f.set_artihmetic_flags(result);
+                f.aux_carry = op == 4;
+                f.subtract = false;
+                f.carry = false;
+                result
+            },
+        };
+
+        set_sign_zero(f, result);
+
+        // CP only sets the flags
+        if op != 7 {
+            self.registers.a = result;
+        }
+    }
+
+    fn inc(&mut self, val: u8) -> u8 {
+        let result: u8 = val.wrapping_add(1);
+        let f = &mut self.registers.f;
+
+        set_sign_zero(f, result);
+        f.aux_carry = val & 0xF == 0xF;
+        f.parity = val == 0x7F;
+        f.subtract = false;
+        result
+    }
+
+    fn dec(&mut self, val: u8) -> u8 {
+        let result: u8 = val.wrapping_sub(1);
+        let f = &mut self.registers.f;
+
+        set_sign_zero(f, result);
+        f.aux_carry = val & 0xF == 0x0;
+        f.parity = val == 0x80;
+        f.subtract = true;
+        result
+    }
+
+    // ADD HL,rr - Only changes the carries
+    fn add_16(&mut self, a: u16, b: u16) -> u16 {
+        let f = &mut self.registers.f;
+
+        f.aux_carry = (a & 0xFFF) + (b & 0xFFF) > 0xFFF;
+        f.subtract = false;
+        f.carry = a as u32 + b as u32 > 0xFFFF;
+        a.wrapping_add(b)
+    }
+
+    // ADC HL,rr and SBC HL,rr
+    fn adc_sbc_16(&mut self, val: u16, subtract: bool) {
+        let hl: u16 = self.registers.get_reg_pair("HL");
+        let carry: u16 = self.registers.f.carry as u16;
+        let f = &mut self.registers.f;
+
+        let result: u16 = if subtract {
+            let result: u16 = hl.wrapping_sub(val).wrapping_sub(carry);
+            f.aux_carry = (hl & 0xFFF) < (val & 0xFFF) + carry;
+            f.parity = ((hl ^ val) & (hl ^ result) & 0x8000) != 0;
+            f.carry = (hl as u32) < val as u32 + carry as u32;
+            result
+        } else {
+            let result: u16 = hl.wrapping_add(val).wrapping_add(carry);
+            f.aux_carry = (hl & 0xFFF) + (val & 0xFFF) + carry > 0xFFF;
+            f.parity = ((hl ^ result) & (val ^ result) & 0x8000) != 0;
+            f.carry = hl as u32 + val as u32 + carry as u32 > 0xFFFF;
+            result
+        };
+
+        f.sign = result & 0x8000 != 0;
+        f.zero = result == 0;
+        f.subtract = subtract;
+        self.registers.set_reg_pair("HL", result);
+    }
+
+    // RLC, RRC, RL, RR, SLA, SRA, SLL (undocumented) and SRL
+    fn rotate(&mut self, op: u8, val: u8) -> u8 {
+        let carry: u8 = self.registers.f.carry as u8;
+
+        let (result, carry_out) = match op {
+            0 => (val.rotate_left(1), val >> 7),
+            1 => (val.rotate_right(1), val & 0x1),
+            2 => (val << 1 | carry, val >> 7),
+            3 => (val >> 1 | carry << 7, val & 0x1),
+            4 => (val << 1, val >> 7),
+            5 => (val >> 1 | (val & 0x80), val & 0x1),
+            6 => (val << 1 | 0x1, val >> 7),
+            _ => (val >> 1, val & 0x1),
+        };
+
+        let f = &mut self.registers.f;
+        f.set_artihmetic_flags(result);
+        f.aux_carry = false;
+        f.subtract = false;
+        f.carry = carry_out == 1;
+        result
+    }
+
+    // Decimal adjust after an addition or a subtraction, told apart by the N flag
+    fn daa(&mut self) {
+        let a: u8 = self.registers.a;
+        let f = &mut self.registers.f;
+
+        let mut correction: u8 = 0;
+        let mut carry: bool = f.carry;
+
+        if f.aux_carry || a & 0xF > 9 {
+            correction |= 0x06;
+        }
+
+        if f.carry || a > 0x99 {
+            correction |= 0x60;
+            carry = true;
+        }
+
+        let result: u8 = if f.subtract {
+            f.aux_carry = f.aux_carry && a & 0xF < 6;
+            a.wrapping_sub(correction)
+        } else {
+            f.aux_carry = a & 0xF > 9;
+            a.wrapping_add(correction)
+        };
+
+        f.set_artihmetic_flags(result);
+        f.carry = carry;
+        self.registers.a = result;
+    }
+
+    fn exchange_af(&mut self) {
+        let af: u16 = self.registers.get_psw(CpuModel::Z80);
+        self.registers.set_psw(self.z80.af_alt);
+        self.z80.af_alt = af;
+    }
+
+    fn exchange_registers(&mut self) {
+        for (pair, alt) in [("BC", &mut self.z80.bc_alt), ("DE", &mut self.z80.de_alt), ("HL", &mut self.z80.hl_alt)] {
+            let val: u16 = self.registers.get_reg_pair(pair);
+            self.registers.set_reg_pair(pair, *alt);
+            *alt = val;
+        }
+    }
+
+    // Execute the instruction at PC with its prefixes
+    pub(super) fn exec_z80_opcode(&mut self) {
+        let opcode: u8 = self.fetch_opcode();
+
+        match opcode {
+            0xcb => {
+                let opcode: u8 = self.fetch_opcode();
+                let addr: u16 = self.registers.get_reg_pair("HL");
+                self.exec_cb(opcode, Index::Hl, addr);
+            },
+            0xdd => self.exec_indexed(Index::Ix),
+            0xed => self.exec_ed(),
+            0xfd => self.exec_indexed(Index::Iy),
+            _ => {
+                self.cycles += CYCLES[opcode as usize] as u64;
+                self.exec_main(opcode, Index::Hl);
+            },
+        }
+    }
+
+    // Instruction after a DD or FD prefix
+    fn exec_indexed(&mut self, index: Index) {
+        // Another prefix makes this one act as a NOP
+        if matches!(self.mem[self.registers.pc], 0xdd | 0xed | 0xfd) {
+            self.cycles += CYCLES[0xdd] as u64;
+            return;
+        }
+
+        let opcode: u8 = self.fetch_opcode();
+
+        if opcode == 0xcb {
+            // DD CB d op - The displacement comes before the opcode
+            let addr: u16 = self.memory_operand(index);
+            let opcode: u8 = self.fetch();
+            self.exec_cb(opcode, index, addr);
+            return;
+        }
+
+        self.cycles += CYCLES[opcode as usize] as u64 + match opcode {
+            0x36 => INDEX_MEMORY_IMMEDIATE_CYCLES,
+            _ if uses_memory_operand(opcode) => INDEX_MEMORY_CYCLES,
+            _ => INDEX_CYCLES,
+        };
+
+        self.exec_main(opcode, index);
+    }
+
+    // Unprefixed instructions, with HL replaced by the index register
+    fn exec_main(&mut self, opcode: u8, index: Index) {
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 0x07, opcode & 0x07);
+        let (p, q) = (y >> 1, y & 0x1);
+
+        let addr: u16 = if uses_memory_operand(opcode) { self.memory_operand(index) } else { 0 };
+
+        match (x, z) {
+            (0, 0) => match y {
+                0 => (),
+                1 => self.exchange_af(),
+                2 => {
+                    // DJNZ - Decrement B and jump if not zero
+                    self.registers.b = self.registers.b.wrapping_sub(1);
+                    self.jump_relative(self.registers.b != 0);
+                },
+                3 => self.jump_relative(true),
+                _ => self.jump_relative(self.condition(y - 4)),
+            },
+            (0, 1) => {
+                if q == 0 {
+                    // LD rr,nn
+                    let val: u16 = self.fetch_word();
+                    self.set_reg_pair_num(p, index, val);
+                } else {
+                    // ADD HL,rr
+                    let result: u16 = self.add_16(self.index_reg(index), self.reg_pair_num(p, index));
+                    self.set_index_reg(index, result);
+                }
+            },
+            (0, 2) => match (p, q) {
+                (0, 0) => self.write_mem(self.registers.get_reg_pair("BC") as usize, self.registers.a),
+                (1, 0) => self.write_mem(self.registers.get_reg_pair("DE") as usize, self.registers.a),
+                (2, 0) => {
+                    let addr: u16 = self.fetch_word();
+                    self.write_word(addr, self.index_reg(index));
+                },
+                (3, 0) => {
+                    let addr: u16 = self.fetch_word();
+                    self.write_mem(addr as usize, self.registers.a);
+                },
+                (0, _) => self.registers.a = self.read_mem(self.registers.get_reg_pair("BC") as usize),
+                (1, _) => self.registers.a = self.read_mem(self.registers.get_reg_pair("DE") as usize),
+                (2, _) => {
+                    let addr: u16 = self.fetch_word();
+                    let val: u16 = self.read_word(addr);
+                    self.set_index_reg(index, val);
+                },
+                _ => {
+                    let addr: u16 = self.fetch_word();
+                    self.registers.a = self.read_mem(addr as usize);
+                },
+            },
+            (0, 3) => {
+                // INC rr and DEC rr don't change the flags
+                let val: u16 = self.reg_pair_num(p, index);
+                let result: u16 = if q == 0 { val.wrapping_add(1) } else { val.wrapping_sub(1) };
+                self.set_reg_pair_num(p, index, result);
+            },
+            (0, 4) | (0, 5) => {
+                let val: u8 = self.operand(y, index, addr);
+                let result: u8 = if z == 4 { self.inc(val) } else { self.dec(val) };
+                self.set_operand(y, index, addr, result);
+            },
+            (0, 6) => {
+                let val: u8 = self.fetch();
+                self.set_operand(y, index, addr, val);
+            },
+            (0, _) => self.exec_accumulator_op(y),
+            (1, _) if y == REG_MEM && z == REG_MEM => {
+                // HALT
+                self.halted = true;
+            },
+            (1, _) => {
+                // LD r,r' - With a memory operand the other register is H or L, not a half of the index register
+                let reg_index: Index = if y == REG_MEM || z == REG_MEM { Index::Hl } else { index };
+                let val: u8 = if z == REG_MEM { self.read_mem(addr as usize) } else { self.reg_num(z, reg_index) };
+                self.set_operand(y, reg_index, addr, val);
+            },
+            (2, _) => {
+                let val: u8 = self.operand(z, index, addr);
+                self.alu(y, val);
+            },
+            (_, 0) => {
+                // RET cc
+                if self.condition(y) {
+                    self.cycles += RET_TAKEN_CYCLES;
+                    self.registers.pc = self.pop_stack() as usize;
+                }
+            },
+            (_, 1) => match (q, p) {
+                (0, 3) => {
+                    // POP AF
+                    let val: u16 = self.pop_stack();
+                    self.registers.set_psw(val);
+                },
+                (0, _) => {
+                    let val: u16 = self.pop_stack();
+                    self.set_reg_pair_num(p, index, val);
+                },
+                (_, 0) => self.registers.pc = self.pop_stack() as usize,
+                (_, 1) => self.exchange_registers(),
+                (_, 2) => self.registers.pc = self.index_reg(index) as usize,
+                _ => self.registers.sp = self.index_reg(index),
+            },
+            (_, 2) => {
+                // JP cc,nn
+                let addr: u16 = self.fetch_word();
+                if self.condition(y) {
+                    self.registers.pc = addr as usize;
+                }
+            },
+            (_, 3) => self.exec_misc(y, index),
+            (_, 4) => {
+                // CALL cc,nn
+                let addr: u16 = self.fetch_word();
+                if self.condition(y) {
+                    self.cycles += CALL_TAKEN_CYCLES;
+                    self.push_stack(self.registers.pc as u16);
+                    self.registers.pc = addr as usize;
+                }
+            },
+            (_, 5) => match (q, p) {
+                (0, 3) => self.push_stack(self.registers.get_psw(CpuModel::Z80)),
+                (0, _) => self.push_stack(self.reg_pair_num(p, index)),
+                _ => {
+                    // CALL nn, the other opcodes are the prefixes
+                    let addr: u16 = self.fetch_word();
+                    self.push_stack(self.registers.pc as u16);
+                    self.registers.pc = addr as usize;
+                },
+            },
+            (_, 6) => {
+                let val: u8 = self.fetch();
+                self.alu(y, val);
+            },
+            _ => {
+                // RST
+                self.push_stack(self.registers.pc as u16);
+                self.registers.pc = y as usize * 0x08;
+            },
+        }
+    }
+
+    // RLCA, RRCA, RLA, RRA, DAA, CPL, SCF and CCF
+    fn exec_accumulator_op(&mut self, op: u8) {
+        match op {
+            0..=3 => {
+                // The rotates of the accumulator keep S, Z and P/V
+                let f = &self.registers.f;
+                let (sign, zero, parity) = (f.sign, f.zero, f.parity);
+
+                self.registers.a = self.rotate(op, self.registers.a);
+
+                let f = &mut self.registers.f;
+                f.sign = sign;
+                f.zero = zero;
+                f.parity = parity;
+            },
+            4 => self.daa(),
+            5 => {
+                self.registers.a = !self.registers.a;
+                self.registers.f.aux_carry = true;
+                self.registers.f.subtract = true;
+            },
+            6 => {
+                self.registers.f.carry = true;
+                self.registers.f.aux_carry = false;
+                self.registers.f.subtract = false;
+            },
+            _ => {
+                self.registers.f.aux_carry = self.registers.f.carry;
+                self.registers.f.carry = !self.registers.f.carry;
+                self.registers.f.subtract = false;
+            },
+        }
+    }
+
+    // JP nn, OUT (n),A, IN A,(n), EX (SP),HL, EX DE,HL, DI and EI
+    fn exec_misc(&mut self, op: u8, index: Index) {
+        match op {
+            0 => self.registers.pc = self.fetch_word() as usize,
+            2 => {
+                let port: u8 = self.fetch();
+                self.port_out(port, self.registers.a);
+            },
+            3 => {
+                let port: u8 = self.fetch();
+                self.registers.a = self.port_in(port);
+            },
+            4 => {
+                let sp: u16 = self.registers.sp;
+                let val: u16 = self.read_word(sp);
+                self.write_word(sp, self.index_reg(index));
+                self.set_index_reg(index, val);
+            },
+            5 => {
+                // EX DE,HL always uses HL
+                let hl: u16 = self.registers.get_reg_pair("HL");
+                self.registers.set_reg_pair("HL", self.registers.get_reg_pair("DE"));
+                self.registers.set_reg_pair("DE", hl);
+            },
+            6 => {
+                self.int = false;
+                self.z80.iff2 = false;
+            },
+            _ => {
+                self.int = true;
+                self.z80.iff2 = true;
+            },
+        }
+    }
+
+    // CB prefixed instructions on the register or the memory operand at the address. With an index register the
+    // result of the rotates, RES and SET is also copied into the register of the opcode.
+    fn exec_cb(&mut self, opcode: u8, index: Index, addr: u16) {
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 0x07, opcode & 0x07);
+
+        let reg: u8 = if index == Index::Hl { z } else { REG_MEM };
+        self.cycles += match (index, reg, x) {
+            (Index::Hl, REG_MEM, 1) => 12,
+            (Index::Hl, REG_MEM, _) => 15,
+            (Index::Hl, _, _) => 8,
+            (_, _, 1) => 20,
+            _ => 23,
+        };
+
+        let val: u8 = self.operand(reg, Index::Hl, addr);
+
+        let result: u8 = match x {
+            0 => self.rotate(y, val),
+            1 => {
+                // BIT - Test the bit, Z and P/V are set when it's 0
+                let set: bool = val & (1 << y) != 0;
+                let f = &mut self.registers.f;
+                f.zero = !set;
+                f.parity = !set;
+                f.sign = y == 7 && set;
+                f.aux_carry = true;
+                f.subtract = false;
+                return;
+            },
+            2 => val & !(1 << y),
+            _ => val | (1 << y),
+        };
+
+        self.set_operand(reg, Index::Hl, addr, result);
+
+        if reg != z && z != REG_MEM {
+            self.set_reg_num(z, Index::Hl, result);
+        }
+    }
+
+    // ED prefixed instructions
+    fn exec_ed(&mut self) {
+        let opcode: u8 = self.fetch_opcode();
+        let (x, y, z) = (opcode >> 6, (opcode >> 3) & 0x07, opcode & 0x07);
+        let (p, q) = (y >> 1, y & 0x1);
+
+        self.cycles += ed_cycles(opcode);
+
+        match (x, z) {
+            (1, 0) => {
+                // IN r,(C) - IN (C) only sets the flags
+                let val: u8 = self.port_in(self.registers.c);
+                if y != REG_MEM {
+                    self.set_reg_num(y, Index::Hl, val);
+                }
+
+                let f = &mut self.registers.f;
+                f.set_artihmetic_flags(val);
+                f.aux_carry = false;
+                f.subtract = false;
+            },
+            (1, 1) => {
+                // OUT (C),r - OUT (C),0 for the memory operand
+                let val: u8 = if y == REG_MEM { 0 } else { self.reg_num(y, Index::Hl) };
+                self.port_out(self.registers.c, val);
+            },
+            (1, 2) => self.adc_sbc_16(self.reg_pair_num(p, Index::Hl), q == 0),
+            (1, 3) => {
+                let addr: u16 = self.fetch_word();
+                if q == 0 {
+                    self.write_word(addr, self.reg_pair_num(p, Index::Hl));
+                } else {
+                    let val: u16 = self.read_word(addr);
+                    self.set_reg_pair_num(p, Index::Hl, val);
+                }
+            },
+            (1, 4) => {
+                // NEG - Subtract the accumulator from 0
+                let val: u8 = self.registers.a;
+                self.registers.a = 0;
+                self.alu(2, val);
+            },
+            (1, 5) => {
+                // RETN and RETI - Return and restore the interrupt enable from IFF2
+                self.registers.pc = self.pop_stack() as usize;
+                self.int = self.z80.iff2;
+            },
+            (1, 6) => self.z80.im = IM_MODES[y as usize],
+            (1, 7) => self.exec_ed_special(y),
+            (2, 0..=3) if y >= 4 => self.exec_block(y, z),
+            _ => (),
+        }
+    }
+
+    // LD I,A, LD R,A, LD A,I, LD A,R, RRD and RLD
+    fn exec_ed_special(&mut self, op: u8) {
+        match op {
+            0 => self.z80.i = self.registers.a,
+            1 => self.z80.r = self.registers.a,
+            2 | 3 => {
+                let val: u8 = if op == 2 { self.z80.i } else { self.z80.r };
+                self.registers.a = val;
+
+                let iff2: bool = self.z80.iff2;
+                let f = &mut self.registers.f;
+                set_sign_zero(f, val);
+                f.aux_carry = false;
+                f.subtract = false;
+                f.parity = iff2;
+            },
+            4 | 5 => {
+                // Rotate the digits of A and (HL) right or left
+                let addr: usize = self.registers.get_reg_pair("HL") as usize;
+                let mem: u8 = self.read_mem(addr);
+                let a: u8 = self.registers.a;
+
+                let (mem, a) = if op == 4 {
+                    (a << 4 | mem >> 4, (a & 0xF0) | (mem & 0x0F))
+                } else {
+                    (mem << 4 | (a & 0x0F), (a & 0xF0) | mem >> 4)
+                };
+
+                self.write_mem(addr, mem);
+                self.registers.a = a;
+
+                let f = &mut self.registers.f;
+                f.set_artihmetic_flags(a);
+                f.aux_carry = false;
+                f.subtract = false;
+            },
+            _ => (),
+        }
+    }
+
+    // LDI, CPI, INI, OUTI and their decrementing (y = 5) and repeating (y = 6 and 7) versions
+    fn exec_block(&mut self, y: u8, z: u8) {
+        let hl: u16 = self.registers.get_reg_pair("HL");
+        let step: u16 = if y & 0x1 == 0 { 1 } else { 0xFFFF };
+        let repeat: bool = y >= 6;
+
+        let again: bool = match z {
+            0 => {
+                // LDI - Copy (HL) to (DE)
+                let de: u16 = self.registers.get_reg_pair("DE");
+                let val: u8 = self.read_mem(hl as usize);
+                self.write_mem(de as usize, val);
+
+                self.registers.set_reg_pair("DE", de.wrapping_add(step));
+                let bc: u16 = self.registers.get_reg_pair("BC").wrapping_sub(1);
+                self.registers.set_reg_pair("BC", bc);
+
+                let f = &mut self.registers.f;
+                f.aux_carry = false;
+                f.subtract = false;
+                f.parity = bc != 0;
+                bc != 0
+            },
+            1 => {
+                // CPI - Compare (HL) with A, the carry stays
+                let val: u8 = self.read_mem(hl as usize);
+                let a: u8 = self.registers.a;
+                let bc: u16 = self.registers.get_reg_pair("BC").wrapping_sub(1);
+                self.registers.set_reg_pair("BC", bc);
+
+                let f = &mut self.registers.f;
+                set_sign_zero(f, a.wrapping_sub(val));
+                f.aux_carry = (a & 0xF) < (val & 0xF);
+                f.subtract = true;
+                f.parity = bc != 0;
+                bc != 0 && a != val
+            },
+            2 => {
+                // INI - Input from port C to (HL)
+                let val: u8 = self.port_in(self.registers.c);
+                self.write_mem(hl as usize, val);
+                self.registers.b = self.registers.b.wrapping_sub(1);
+
+                let b: u8 = self.registers.b;
+                set_sign_zero(&mut self.registers.f, b);
+                self.registers.f.subtract = true;
+                b != 0
+            },
+            _ => {
+                // OUTI - Output (HL) to port C
+                self.registers.b = self.registers.b.wrapping_sub(1);
+                let val: u8 = self.read_mem(hl as usize);
+                self.port_out(self.registers.c, val);
+
+                let b: u8 = self.registers.b;
+                set_sign_zero(&mut self.registers.f, b);
+                self.registers.f.subtract = true;
+                b != 0
+            },
+        };
+
+        self.registers.set_reg_pair("HL", hl.wrapping_add(step));
+
+        // The repeating versions execute again until done, so that interrupts can be taken in between
+        if repeat && again {
+            self.registers.pc = (self.registers.pc as u16).wrapping_sub(2) as usize;
+            self.cycles += BLOCK_REPEAT_CYCLES;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Z80 with the program at 0x0000 in 64KB of memory, run until HALT
+    fn run_program(program: &[u8], data: &[(u16, u8)]) -> Intel8080 {
+        let mut cpu = Intel8080::new();
+        cpu.set_model(CpuModel::Z80);
+        cpu.load_rom(program);
+
+        for (addr, val) in data.iter() {
+            cpu.write_byte(*addr, *val);
+        }
+
+        cpu.emulate();
+        cpu
+    }
+
+    #[test]
+    fn djnz_and_jr() {
+        // LD B,3; XOR A; INC A; DJNZ -3; JR +1; HALT; JR Z,+1; HALT
+        let cpu = run_program(&[0x06, 0x03, 0xaf, 0x3c, 0x10, 0xfd, 0x18, 0x01, 0x76, 0x28, 0x01, 0x76], &[]);
+
+        assert_eq!(cpu.get_reg("A"), 3);
+        assert_eq!(cpu.get_reg("B"), 0);
+        assert_eq!(cpu.get_pc(), 0x000C);
+    }
+
+    #[test]
+    fn ldir_copies_until_bc_is_zero() {
+        // LD HL,1000h; LD DE,2000h; LD BC,3; LDIR; HALT
+        let program = [0x21, 0x00, 0x10, 0x11, 0x00, 0x20, 0x01, 0x03, 0x00, 0xed, 0xb0, 0x76];
+        let cpu = run_program(&program, &[(0x1000, 0x11), (0x1001, 0x22), (0x1002, 0x33), (0x1003, 0x44)]);
+
+        assert_eq!(&cpu.memory()[0x2000..0x2004], &[0x11, 0x22, 0x33, 0x00]);
+        assert_eq!(cpu.get_reg_pair("HL"), 0x1003);
+        assert_eq!(cpu.get_reg_pair("DE"), 0x2003);
+        assert_eq!(cpu.get_reg_pair("BC"), 0x0000);
+        assert!(!cpu.registers.f.parity);
+        assert!(!cpu.registers.f.subtract);
+    }
+
+    #[test]
+    fn cpir_stops_at_the_match() {
+        // LD HL,1000h; LD BC,5; LD A,22h; CPIR; HALT
+        let program = [0x21, 0x00, 0x10, 0x01, 0x05, 0x00, 0x3e, 0x22, 0xed, 0xb1, 0x76];
+        let cpu = run_program(&program, &[(0x1000, 0x11), (0x1001, 0x22), (0x1002, 0x33)]);
+
+        assert_eq!(cpu.get_reg_pair("HL"), 0x1002);
+        assert_eq!(cpu.get_reg_pair("BC"), 0x0003);
+        assert!(cpu.registers.f.zero);
+        assert!(cpu.registers.f.parity);
+        assert!(cpu.registers.f.subtract);
+    }
+
+    #[test]
+    fn index_with_a_negative_displacement() {
+        // LD IX,1005h; LD A,(IX-2); LD (IX-1),A; HALT
+        let program = [0xdd, 0x21, 0x05, 0x10, 0xdd, 0x7e, 0xfe, 0xdd, 0x77, 0xff, 0x76];
+        let cpu = run_program(&program, &[(0x1003, 0x5a)]);
+
+        assert_eq!(cpu.get_index_registers(), (0x1005, 0x0000));
+        assert_eq!(cpu.get_reg("A"), 0x5a);
+        assert_eq!(cpu.read_byte(0x1004), 0x5a);
+    }
+
+    #[test]
+    fn sbc_and_adc_hl_overflow() {
+        // AND A; LD HL,8000h; LD DE,1; SBC HL,DE; HALT
+        let cpu = run_program(&[0xa7, 0x21, 0x00, 0x80, 0x11, 0x01, 0x00, 0xed, 0x52, 0x76], &[]);
+
+        assert_eq!(cpu.get_reg_pair("HL"), 0x7FFF);
+        let f = &cpu.registers.f;
+        assert!(f.parity && f.subtract && f.aux_carry);
+        assert!(!f.sign && !f.zero && !f.carry);
+
+        // SCF; LD HL,7FFEh; LD DE,1; ADC HL,DE; HALT
+        let cpu = run_program(&[0x37, 0x21, 0xfe, 0x7f, 0x11, 0x01, 0x00, 0xed, 0x5a, 0x76], &[]);
+
+        assert_eq!(cpu.get_reg_pair("HL"), 0x8000);
+        let f = &cpu.registers.f;
+        assert!(f.parity && f.sign && f.aux_carry);
+        assert!(!f.subtract && !f.zero && !f.carry);
+
+        // Bit 1 of the pushed flags is N
+        assert_eq!(f.get_model_flags(CpuModel::Z80) & 0b10, 0);
+    }
+
+    #[test]
+    fn neg() {
+        // LD A,1; NEG; HALT
+        let cpu = run_program(&[0x3e, 0x01, 0xed, 0x44, 0x76], &[]);
+
+        assert_eq!(cpu.get_reg("A"), 0xFF);
+        let f = &cpu.registers.f;
+        assert!(f.carry && f.subtract && f.sign);
+        assert_eq!(f.get_model_flags(CpuModel::Z80) & 0b10, 0b10);
+
+        // LD A,80h; NEG; HALT
+        let cpu = run_program(&[0x3e, 0x80, 0xed, 0x44, 0x76], &[]);
+
+        assert_eq!(cpu.get_reg("A"), 0x80);
+        assert!(cpu.registers.f.parity);
+    }
+
+    #[test]
+    fn daa_after_a_subtraction() {
+        // LD A,42h; SUB 15h; DAA; HALT
+        let cpu = run_program(&[0x3e, 0x42, 0xd6, 0x15, 0x27, 0x76], &[]);
+
+        assert_eq!(cpu.get_reg("A"), 0x27);
+        assert!(cpu.registers.f.subtract);
+        assert!(!cpu.registers.f.carry);
+
+        // LD A,15h; SUB 42h; DAA; HALT
+        let cpu = run_program(&[0x3e, 0x15, 0xd6, 0x42, 0x27, 0x76], &[]);
+
+        assert_eq!(cpu.get_reg("A"), 0x73);
+        assert!(cpu.registers.f.carry);
+    }
+
+    #[test]
+    fn exchange_af_and_the_register_set() {
+        // LD A,12h; SCF; EX AF,AF'; LD A,34h; AND A; EX AF,AF'; HALT
+        let cpu = run_program(&[0x3e, 0x12, 0x37, 0x08, 0x3e, 0x34, 0xa7, 0x08, 0x76], &[]);
+
+        assert_eq!(cpu.get_reg("A"), 0x12);
+        assert!(cpu.registers.f.carry);
+        assert_eq!(cpu.z80.af_alt >> 8, 0x34);
+
+        // LD BC,1111h; LD DE,2222h; LD HL,3333h; EXX; LD HL,4444h; EXX; HALT
+        let program = [0x01, 0x11, 0x11, 0x11, 0x22, 0x22, 0x21, 0x33, 0x33, 0xd9, 0x21, 0x44, 0x44, 0xd9, 0x76];
+        let cpu = run_program(&program, &[]);
+
+        assert_eq!(cpu.get_reg_pair("BC"), 0x1111);
+        assert_eq!(cpu.get_reg_pair("DE"), 0x2222);
+        assert_eq!(cpu.get_reg_pair("HL"), 0x3333);
+        assert_eq!((cpu.z80.bc_alt, cpu.z80.de_alt, cpu.z80.hl_alt), (0x0000, 0x0000, 0x4444));
+    }
+
+    #[test]
+    fn interrupt_mode_2_jumps_through_the_vector_table() {
+        // LD SP,8000h; LD A,10h; LD I,A; IM 2; EI; HALT
+        let program = [0x31, 0x00, 0x80, 0x3e, 0x10, 0xed, 0x47, 0xed, 0x5e, 0xfb, 0x76];
+
+        // Vector of RST 7 (0xFF) at 0x10FF
+        let mut cpu = run_program(&program, &[(0x10FF, 0x50), (0x1100, 0x02)]);
+        let pc = cpu.get_pc();
+
+        assert!(cpu.interrupt(7));
+        assert_eq!(cpu.get_pc(), 0x0250);
+        assert_eq!(cpu.get_sp(), 0x7FFE);
+        assert_eq!(cpu.read_byte(0x7FFE) as u16 | (cpu.read_byte(0x7FFF) as u16) << 8, pc);
+        assert!(!cpu.is_halted());
+
+        // Interrupts are disabled until the next EI
+        assert!(!cpu.interrupt(7));
+    }
+
+    #[test]
+    fn nmi_is_taken_with_interrupts_disabled_and_retn_restores_them() {
+        // LD SP,8000h; EI; HALT, with RETN at 0x66
+        let mut cpu = run_program(&[0x31, 0x00, 0x80, 0xfb, 0x76], &[(0x0066, 0xed), (0x0067, 0x45)]);
+        let pc = cpu.get_pc();
+
+        cpu.nmi();
+        assert_eq!(cpu.get_pc(), NMI_VECTOR);
+        assert!(!cpu.int);
+        assert!(cpu.z80.iff2);
+
+        cpu.step();
+        assert_eq!(cpu.get_pc(), pc);
+        assert!(cpu.int);
+
+        // DI; HALT
+        let mut cpu = run_program(&[0xf3, 0x76], &[]);
+        assert!(!cpu.interrupt(0));
+
+        cpu.nmi();
+        assert_eq!(cpu.get_pc(), NMI_VECTOR);
+        assert!(!cpu.z80.iff2);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let state = Z80State {
+            af_alt: 0x1234, bc_alt: 0x2345, de_alt: 0x3456, hl_alt: 0x4567,
+            ix: 0x5678, iy: 0x6789,
+            i: 0x10, r: 0x85,
+            im: 2, iff2: true,
+        };
+
+        let mut writer = StateWriter::new();
+        state.save_state(&mut writer);
+        let bytes = writer.into_bytes();
+
+        assert_eq!(Z80State::load_state(&mut StateReader::new(&bytes)).unwrap(), state);
+
+        // Interrupt mode 3 doesn't exist
+        let mut bytes = bytes;
+        bytes[14] = 3;
+        assert!(matches!(Z80State::load_state(&mut StateReader::new(&bytes)), Err(EmulatorError::SaveStateInvalid(_))));
+
+        assert!(Z80State::load_state(&mut StateReader::new(&bytes[..10])).is_err());
+    }
+}
@@ -5,6 +5,40 @@ pub enum EmulatorError {
     FilePathNotGiven,
     FilePathNotFound(String),
     FileCantOpen(String),
+    ArgumentUnknown(String),
+    ArgumentValueMissing(String),
+    ArgumentValueInvalid(String, String),
+    ArgumentsConflict(String, String),
+    SaveStateInvalid(String),
+    SaveStateVersion(u16),
+    RewindUnavailable(u64),
+    ProvenanceDisabled,
+    ProfilerDisabled,
+    CoverageDisabled,
+    ImageFormatUnknown(String),
+    TerminalUnavailable,
+    SoundUnavailable,
+    VideoUnavailable,
+    WavInvalid(String),
+    MovieInvalid(String),
+    MovieRomMismatch(u32, u32),
+    MovieDesync(u32, u32),
+    GymGameNotStarted,
+    PatchInvalid(String),
+    PatchChecksum(String, u32, u32),
+    NvramInvalid(String),
+    CpmProgramTooLarge(usize, usize),
+    DiskImageInvalid(String, usize),
+    DiskSectorInvalid(u8, u8),
+    DiskCountInvalid(usize, usize),
+    DiskNotBootable(String),
+    RamSizeInvalid(usize, usize),
+    LoadAddressInvalid(usize, u16),
+    SerialLinkFailed(String, String),
+    RomSizeInvalid(String, usize, usize),
+    ColourPromInvalid(String, usize, usize),
+    ConfigInvalid(String),
+    UndocumentedOpcode(u8, u16),
 }
 
 fn get_err_msg(err: &EmulatorError) -> String {
@@ -12,6 +46,42 @@ fn get_err_msg(err: &EmulatorError) -> String {
         EmulatorError::FilePathNotGiven => format!("File path was not given!"),
         EmulatorError::FilePathNotFound(s) => format!("File path '{s}' was not valid!"),
         EmulatorError::FileCantOpen(s) => format!("Couldn't open file '{s}'!"),
+        EmulatorError::ArgumentUnknown(s) => format!("Unknown argument '{s}'!"),
+        EmulatorError::ArgumentValueMissing(s) => format!("Argument '{s}' requires a value!"),
+        EmulatorError::ArgumentValueInvalid(s, v) => format!("Invalid value '{v}' for argument '{s}'!"),
+        EmulatorError::ArgumentsConflict(s, o) => format!("Argument '{s}' can't be used together with '{o}'!"),
+        EmulatorError::SaveStateInvalid(s) => format!("Invalid save state: {s}!"),
+        EmulatorError::SaveStateVersion(v) => format!("Unsupported save state version {v}!"),
+        EmulatorError::RewindUnavailable(i) => format!("Instruction {i} is not in the rewind history!"),
+        EmulatorError::ProvenanceDisabled => String::from("Memory write provenance tracking is not enabled!"),
+        EmulatorError::ProfilerDisabled => String::from("Profiler is not enabled!"),
+        EmulatorError::CoverageDisabled => String::from("Coverage recording is not enabled!"),
+        EmulatorError::ImageFormatUnknown(s) => format!("Unknown image format for '{s}', use .png or .ppm!"),
+        EmulatorError::TerminalUnavailable => String::from("Couldn't set the terminal into raw mode!"),
+        EmulatorError::SoundUnavailable => String::from("The machine has no sound board, use --invaders!"),
+        EmulatorError::VideoUnavailable => String::from("The machine has no video, use --invaders or --machine!"),
+        EmulatorError::WavInvalid(s) => format!("'{s}' is not an 8 or 16-bit PCM WAV file!"),
+        EmulatorError::MovieInvalid(s) => format!("'{s}' is not a supported movie file!"),
+        EmulatorError::MovieRomMismatch(e, a) => {
+            format!("Movie was recorded with ROM {e:08x}, but the ROM is {a:08x}!")
+        },
+        EmulatorError::MovieDesync(e, a) => format!("Replay desynced, memory hash is {a:08x} instead of {e:08x}!"),
+        EmulatorError::PatchInvalid(s) => format!("Invalid patch: {s}!"),
+        EmulatorError::PatchChecksum(s, e, a) => format!("Patch {s} checksum is {a:08x} instead of {e:08x}!"),
+        EmulatorError::NvramInvalid(s) => format!("'{s}' is not an NVRAM file of this machine!"),
+        EmulatorError::CpmProgramTooLarge(s, m) => format!("Program of {s} bytes doesn't fit the TPA of {m} bytes!"),
+        EmulatorError::DiskImageInvalid(s, l) => format!("'{s}' is {l} bytes, not an 8\" disk image of 256256 bytes!"),
+        EmulatorError::DiskSectorInvalid(t, s) => format!("Track {t} sector {s} is not on the disk!"),
+        EmulatorError::DiskCountInvalid(c, m) => format!("{c} disk images given, 1 to {m} drives are supported!"),
+        EmulatorError::DiskNotBootable(s) => format!("'{s}' has no CP/M 2.2 CCP on its system tracks!"),
+        EmulatorError::RamSizeInvalid(s, m) => format!("RAM size of {s}KB is not between 1KB and {m}KB!"),
+        EmulatorError::LoadAddressInvalid(l, a) => format!("Image of {l} bytes doesn't fit at address {a:#06x}!"),
+        EmulatorError::SerialLinkFailed(k, e) => format!("Couldn't open the {k} serial link: {e}!"),
+        EmulatorError::RomSizeInvalid(b, s, m) => format!("ROM of {s} bytes is larger than the {m} bytes of {b}!"),
+        EmulatorError::ColourPromInvalid(s, l, e) => format!("Colour PROM of {s} is {l} bytes instead of {e} bytes!"),
+        EmulatorError::ConfigInvalid(s) => format!("Invalid machine configuration, {s}!"),
+        EmulatorError::UndocumentedOpcode(o, a) => format!("Stopped at undocumented opcode {o:#04x} at {a:#06x}!"),
+        EmulatorError::GymGameNotStarted => String::from("Couldn't start a game, is the ROM Space Invaders?"),
     }
 }
 
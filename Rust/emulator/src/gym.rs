@@ -0,0 +1,316 @@
+/*
+Gym-like reinforcement learning environment for Space Invaders
+
+    let mut env = InvadersEnv::new(cpu, ObservationKind::Ram, 4)?;
+    let mut observation = env.reset(seed);
+
+    loop {
+        let (observation, reward, done) = env.step(Action::Fire);
+        ...
+    }
+
+The environment inserts a coin and starts a one player game once when it is created, and every reset() restores
+that state instead of booting the machine again. The seed picks a random number of idle frames before the agent
+takes over, since the game itself has no source of randomness besides the timing of the inputs.
+
+Work RAM used by the environment:
+    0x20EF      Game mode, 1 while a game is running
+    0x20F8      Player 1 score, lower two BCD digits
+    0x20F9      Player 1 score, upper two BCD digits
+    0x21FF      Player 1 reserve ships
+*/
+
+use crate::emulator::Intel8080;
+use crate::errors::EmulatorError;
+use crate::invaders::{self, Invaders};
+use crate::machine::Machine;
+use crate::snapshot;
+use crate::video;
+
+const GAME_MODE: usize = 0x20EF;
+const SCORE_LOW: usize = 0x20F8;
+const SCORE_HIGH: usize = 0x20F9;
+const RESERVE_SHIPS: usize = 0x21FF;
+
+const WORK_RAM_START: usize = 0x2000;
+
+// Frames to wait for the game to boot before inserting a coin, and to accept the coin before pressing start
+const BOOT_FRAMES: u64 = 100;
+const COIN_FRAMES: u64 = 100;
+const BUTTON_FRAMES: u64 = 5;
+
+// Upper limit of idle frames after a reset
+const MAX_IDLE_FRAMES: u64 = 30;
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Action {
+    Noop,
+    Left,
+    Right,
+    Fire,
+    LeftFire,
+    RightFire,
+}
+
+impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::Noop, Action::Left, Action::Right, Action::Fire, Action::LeftFire, Action::RightFire,
+    ];
+
+    fn buttons(self) -> (bool, bool, bool) {
+        match self {
+            Action::Noop => (false, false, false),
+            Action::Left => (true, false, false),
+            Action::Right => (false, true, false),
+            Action::Fire => (false, false, true),
+            Action::LeftFire => (true, false, true),
+            Action::RightFire => (false, true, true),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ObservationKind {
+    // Work and video RAM (0x2000-0x3FFF), 8192 bytes
+    Ram,
+    // Rotated screen, one byte per pixel (0 or 1) in rows of 224 pixels, 224x256 bytes
+    Screen,
+}
+
+
+pub struct InvadersEnv {
+    machine: Invaders,
+    observation: ObservationKind,
+    frame_skip: u32,
+
+    // Save state right after the game has started
+    start_state: Vec<u8>,
+    score: u32,
+}
+
+impl InvadersEnv {
+    // Create the environment for a CPU that has the Space Invaders ROM in memory. Each step repeats the action for
+    // frame_skip frames.
+    pub fn new(cpu: Intel8080, observation: ObservationKind, frame_skip: u32) -> Result<Self, EmulatorError> {
+        let mut machine = Invaders::new(cpu);
+
+        run_frames(&mut machine, BOOT_FRAMES);
+        press(&mut machine, invaders::COIN);
+        run_frames(&mut machine, COIN_FRAMES);
+        press(&mut machine, invaders::P1_START);
+
+        let start_state = snapshot::save_state(machine.cpu());
+
+        let env = InvadersEnv {
+            machine,
+            observation,
+            frame_skip: frame_skip.max(1),
+
+            start_state,
+            score: 0,
+        };
+
+        // Without the game running the ROM is not Space Invaders
+        if !env.game_running() {
+            return Err(EmulatorError::GymGameNotStarted);
+        }
+
+        Ok(env)
+    }
+
+    // Restart the game and return the first observation
+    pub fn reset(&mut self, seed: u64) -> Vec<u8> {
+        snapshot::load_state(self.machine.cpu_mut(), &self.start_state).expect("Start state of the game is invalid");
+        self.machine.sound().clear_events();
+
+        // Xorshift needs a non-zero state
+        let mut rng = seed ^ 0x9E37_79B9_7F4A_7C15;
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+
+        run_frames(&mut self.machine, rng % (MAX_IDLE_FRAMES + 1));
+        self.score = self.score();
+
+        self.observe()
+    }
+
+    // Hold the buttons of the action for frame_skip frames, return the observation after the last frame, the points
+    // scored during the frames and whether the game is over
+    pub fn step(&mut self, action: Action) -> (Vec<u8>, u32, bool) {
+        let (left, right, fire) = action.buttons();
+
+        let latch = self.machine.input(1);
+        latch.set_bit(invaders::P1_LEFT, left);
+        latch.set_bit(invaders::P1_RIGHT, right);
+        latch.set_bit(invaders::P1_FIRE, fire);
+
+        for _ in 0..self.frame_skip {
+            self.machine.run_frame();
+
+            if self.is_done() {
+                break;
+            }
+        }
+
+        // The score only goes down when a new game starts
+        let score = self.score();
+        let reward = score.saturating_sub(self.score);
+        self.score = score;
+
+        (self.observe(), reward, self.is_done())
+    }
+
+    pub fn observe(&self) -> Vec<u8> {
+        let mem = self.machine.cpu().memory();
+
+        match self.observation {
+            ObservationKind::Ram => mem[WORK_RAM_START..video::VRAM_END + 1].to_vec(),
+            ObservationKind::Screen => {
+                let screen = video::render_invaders(mem, false);
+                screen.pixels.iter().map(|pixel| (*pixel != video::BLACK) as u8).collect()
+            },
+        }
+    }
+
+    // Length of the observations in bytes
+    pub fn observation_len(&self) -> usize {
+        match self.observation {
+            ObservationKind::Ram => video::VRAM_END + 1 - WORK_RAM_START,
+            ObservationKind::Screen => video::WIDTH * video::HEIGHT,
+        }
+    }
+
+    // Player 1 score in points, decoded from the four BCD digits
+    pub fn score(&self) -> u32 {
+        let mem = self.machine.cpu().memory();
+        bcd(mem[SCORE_HIGH]) * 100 + bcd(mem[SCORE_LOW])
+    }
+
+    pub fn reserve_ships(&self) -> u8 {
+        self.machine.cpu().memory()[RESERVE_SHIPS]
+    }
+
+    fn game_running(&self) -> bool {
+        self.machine.cpu().memory()[GAME_MODE] != 0x00
+    }
+
+    // The game is over when the ship is lost without reserve ships left, which ends the game mode
+    pub fn is_done(&self) -> bool {
+        self.reserve_ships() == 0 && !self.game_running()
+    }
+
+    pub fn machine(&mut self) -> &mut Invaders {
+        &mut self.machine
+    }
+}
+
+fn run_frames(machine: &mut Invaders, frames: u64) {
+    for _ in 0..frames {
+        machine.run_frame();
+    }
+}
+
+// Hold a button of IN 1 down for a few frames
+fn press(machine: &mut Invaders, bit: u8) {
+    machine.input(1).set_bit(bit, true);
+    run_frames(machine, BUTTON_FRAMES);
+    machine.input(1).set_bit(bit, false);
+}
+
+fn bcd(val: u8) -> u32 {
+    (val >> 4) as u32 * 10 + (val & 0x0F) as u32
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::read;
+    use std::path::Path;
+
+    fn env(observation: ObservationKind, frame_skip: u32) -> InvadersEnv {
+        let rom = read(Path::new(env!("CARGO_MANIFEST_DIR")).join("../../Invaders_8080/invaders")).unwrap();
+
+        let mut cpu = Intel8080::new();
+        cpu.load_rom(&rom);
+        InvadersEnv::new(cpu, observation, frame_skip).unwrap()
+    }
+
+    #[test]
+    fn other_roms_fail() {
+        // HLT
+        let mut cpu = Intel8080::new();
+        cpu.load_rom(&[0x76]);
+
+        let result = InvadersEnv::new(cpu, ObservationKind::Ram, 1);
+        assert!(matches!(result, Err(EmulatorError::GymGameNotStarted)));
+    }
+
+    #[test]
+    fn observation_sizes() {
+        for observation in [ObservationKind::Ram, ObservationKind::Screen] {
+            let mut env = env(observation, 1);
+            assert_eq!(env.reset(0).len(), env.observation_len());
+            assert_eq!(env.step(Action::Noop).0.len(), env.observation_len());
+        }
+    }
+
+    #[test]
+    fn reset_is_deterministic_for_a_seed() {
+        let mut env = env(ObservationKind::Ram, 2);
+        let actions = [Action::LeftFire, Action::Fire, Action::Right, Action::RightFire, Action::Noop];
+
+        let mut run = |seed: u64| {
+            let mut observations = vec![env.reset(seed)];
+            observations.extend(actions.iter().cycle().take(200).map(|action| env.step(*action).0));
+            observations
+        };
+
+        let first = run(7);
+        assert_eq!(run(7), first);
+        assert_eq!(run(8)[0].len(), first[0].len());
+    }
+
+    #[test]
+    fn frame_skip_repeats_the_action() {
+        let mut env = env(ObservationKind::Ram, 4);
+        env.reset(0);
+
+        let frame = env.machine().frame();
+        env.step(Action::Left);
+        assert_eq!(env.machine().frame(), frame + 4);
+    }
+
+    #[test]
+    fn reward_is_the_increase_of_the_bcd_score() {
+        let mut env = env(ObservationKind::Ram, 1);
+        env.reset(0);
+
+        env.machine().cpu_mut().write_byte(SCORE_LOW as u16, 0x50);
+        assert_eq!(env.step(Action::Noop).1, 50);
+        assert_eq!(env.score(), 50);
+
+        env.machine().cpu_mut().write_byte(SCORE_HIGH as u16, 0x12);
+        assert_eq!(env.step(Action::Noop).1, 1200);
+        assert_eq!(env.step(Action::Noop).1, 0);
+    }
+
+    #[test]
+    fn done_after_the_last_ship() {
+        let mut env = env(ObservationKind::Ram, 1);
+        env.reset(0);
+        assert!(env.reserve_ships() > 0);
+        assert!(!env.step(Action::Noop).2);
+
+        env.machine().cpu_mut().write_byte(RESERVE_SHIPS as u16, 0);
+        assert!(!env.step(Action::Noop).2);
+
+        env.machine().cpu_mut().write_byte(GAME_MODE as u16, 0);
+        assert!(env.is_done());
+
+        env.reset(0);
+        assert!(!env.is_done());
+    }
+}
@@ -0,0 +1,195 @@
+/*
+Space Invaders (Midway/Taito 1978) arcade board
+
+    CPU         Intel 8080 at 2 MHz
+    ROM         0x0000-0x1FFF, invaders.h, invaders.g, invaders.f and invaders.e in that order
+    RAM         0x2000-0x23FF work RAM, 0x2400-0x3FFF video RAM
+
+    IN 0        Unused on the upright cabinet, bits 1-3 always set
+    IN 1        Player 1 controls and coin
+    IN 2        Player 2 controls and DIP switches
+    IN 3        Shift register result
+    OUT 2       Shift register offset
+    OUT 3       Sound bits
+    OUT 4       Shift register data
+    OUT 5       Sound bits
+    OUT 6       Watchdog
+
+The video hardware interrupts the CPU twice per frame: RST 1 when the beam is in the middle of the screen and RST 2
+at the start of the vertical blank. The game uses the two interrupts to draw the half of the screen that the beam is
+not drawing.
+
+The other games of the board family run on the same machine with their own board description, see midway.rs.
+*/
+
+use crate::devices::{InputLatch, ShiftRegister};
+use crate::emulator::Intel8080;
+use crate::errors::EmulatorError;
+use crate::machine::Machine;
+use crate::midway::{self, Board, Colours};
+use crate::nvram::{NvramProfile, NvramRegion};
+use crate::sound::SoundBoard;
+use crate::video::{self, Framebuffer};
+
+pub const CLOCK_HZ: u64 = 2_000_000;
+pub const FRAMES_PER_SECOND: u64 = 60;
+
+// About 16.6 ms worth of cycles between the two vblank interrupts
+pub const FRAME_CYCLES: u64 = CLOCK_HZ / FRAMES_PER_SECOND;
+pub const HALF_FRAME_CYCLES: u64 = FRAME_CYCLES / 2;
+
+pub const MID_SCREEN_RST: u8 = 1;
+pub const VBLANK_RST: u8 = 2;
+
+// IN 1 bits
+pub const COIN: u8 = 0;
+pub const P2_START: u8 = 1;
+pub const P1_START: u8 = 2;
+pub const P1_FIRE: u8 = 4;
+pub const P1_LEFT: u8 = 5;
+pub const P1_RIGHT: u8 = 6;
+
+// IN 2 bits
+pub const P2_FIRE: u8 = 4;
+pub const P2_LEFT: u8 = 5;
+pub const P2_RIGHT: u8 = 6;
+
+// IN 2 DIP switches: bits 0-1 extra ships (3-6 ships), bit 3 bonus ship at 1000 instead of 1500 points and bit 7
+// hides the coin info in the attract mode
+pub const DIP_MASK: u8 = 0x8B;
+
+// The high score at 0x20F4-0x20F5, restored after the boot code has initialized the RAM but before the attract mode
+// draws it
+pub const NVRAM: NvramProfile = NvramProfile {
+    regions: &[NvramRegion { start: 0x20F4, len: 2 }],
+    restore_frame: 2,
+};
+
+// Names of the input latch devices, in the order of their ports
+pub const INPUT_LATCHES: [&str; 3] = ["in0", "in1", "in2"];
+
+
+pub struct Invaders {
+    cpu: Intel8080,
+    frame: u64,
+    board: &'static Board,
+
+    // Colour maps of the boards with a colour PROM, empty draws the screen in white
+    prom: Vec<u8>,
+}
+
+impl Invaders {
+    // Wire the Space Invaders hardware to a CPU that already has the ROM in memory
+    pub fn new(cpu: Intel8080) -> Self {
+        Self::with_board(cpu, &midway::INVADERS)
+    }
+
+    // Wire the hardware of a game of the board family to a CPU that already has the ROM in memory
+    pub fn with_board(mut cpu: Intel8080, board: &'static Board) -> Self {
+        for input in board.inputs.iter() {
+            cpu.attach_device(Box::new(InputLatch::new(input.name, input.port, input.idle)));
+        }
+
+        let shifter = &board.shifter;
+        let mut shift_register = ShiftRegister::new(shifter.offset_port, shifter.data_port, shifter.result_port);
+        if let Some(port) = shifter.reversed_port {
+            shift_register = shift_register.with_reversed_result(port);
+        }
+
+        cpu.attach_device(Box::new(shift_register));
+
+        if board.sound {
+            cpu.attach_device(Box::new(SoundBoard::new()));
+        }
+
+        Invaders { cpu, frame: 0, board, prom: Vec::new() }
+    }
+
+    pub fn board(&self) -> &'static Board {
+        self.board
+    }
+
+    pub fn set_colour_prom(&mut self, prom: &[u8]) -> Result<(), EmulatorError> {
+        if prom.len() != midway::PROM_SIZE {
+            return Err(EmulatorError::ColourPromInvalid(self.board.name.to_string(), prom.len(), midway::PROM_SIZE));
+        }
+
+        self.prom = prom.to_vec();
+        Ok(())
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Return the input latch n, i.e. IN port 0, 1 or 2 on Space Invaders
+    pub fn input(&mut self, n: usize) -> &mut InputLatch {
+        let name = self.board.inputs[n].name;
+        self.cpu.named_device_mut::<InputLatch>(name).expect("Midway board input latch missing")
+    }
+
+    // Return the DIP switch bits, IN 2 on Space Invaders
+    pub fn dips(&mut self) -> u8 {
+        match self.board.dips {
+            Some((n, mask)) => self.input(n).get() & mask,
+            None => 0x00,
+        }
+    }
+
+    // Set the DIP switch bits, the controls on the same port keep their state
+    pub fn set_dips(&mut self, dips: u8) {
+        if let Some((n, mask)) = self.board.dips {
+            let latch = self.input(n);
+            latch.set(latch.get() & !mask | dips & mask);
+        }
+    }
+
+    // Draw the screen in the colours of the board, the overlay is only used by the boards that have one
+    pub fn render(&mut self, overlay: bool) -> Framebuffer {
+        match self.board.colours {
+            Colours::Overlay => video::render_invaders(self.cpu.memory(), overlay),
+            Colours::Prom if !self.prom.is_empty() => {
+                // The colour map is selected by bit 5 of OUT 5 and bit 2 of OUT 3 turns the screen red
+                let (port3, port5) = self.sound().ports();
+                let colour_map = (port5 >> 5 & 0x01) as usize;
+
+                video::render_colour_prom(self.cpu.memory(), &self.prom, colour_map, port3 & 0x04 != 0)
+            },
+            _ => video::render_invaders(self.cpu.memory(), false),
+        }
+    }
+
+    // Return the sound board that records the OUT 3 and OUT 5 sound events
+    pub fn sound(&mut self) -> &mut SoundBoard {
+        self.cpu.device_mut::<SoundBoard>().expect("Space Invaders sound board missing")
+    }
+}
+
+impl Machine for Invaders {
+    fn cpu(&self) -> &Intel8080 {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut Intel8080 {
+        &mut self.cpu
+    }
+
+    // Run the CPU for one frame, delivering the mid-screen and the vblank interrupts at their correct cycles
+    fn run_frame(&mut self) {
+        self.cpu.apply_freezes();
+
+        let start = self.cpu.get_cycles();
+
+        self.cpu.run_until(start + HALF_FRAME_CYCLES);
+        self.cpu.interrupt(MID_SCREEN_RST);
+
+        self.cpu.run_until(start + FRAME_CYCLES);
+        self.cpu.interrupt(VBLANK_RST);
+
+        self.frame += 1;
+    }
+
+    fn nvram_profile(&self) -> Option<&'static NvramProfile> {
+        self.board.nvram
+    }
+}
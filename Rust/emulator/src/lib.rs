@@ -0,0 +1,24 @@
+/*
+Intel 8080 emulator written in rust
+*/
+
+pub mod altair;
+pub mod checksum;
+pub mod config;
+pub mod cpm;
+pub mod devices;
+pub mod emulator;
+pub mod errors;
+pub mod gym;
+pub mod invaders;
+pub mod machine;
+pub mod midway;
+pub mod movie;
+pub mod nvram;
+pub mod patch;
+pub mod serial;
+pub mod snapshot;
+pub mod sound;
+pub mod throttle;
+pub mod toml;
+pub mod video;
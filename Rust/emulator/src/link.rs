@@ -0,0 +1,229 @@
+/*
+Links that connect the console of a machine to a terminal
+
+    stdio   The terminal the emulator runs in, see console.rs
+    tcp     A listening socket on 127.0.0.1, e.g. "nc localhost 8800" or "telnet localhost 8800" in character mode.
+            One client is served at a time, the next one can connect after it disconnected.
+    pty     A Linux pseudo-terminal, e.g. "screen /dev/pts/3" or "minicom -p /dev/pts/3". The emulator keeps the
+            terminal side open as well, so that clients can come and go.
+
+The bytes pass through unchanged on the TCP and PTY links. While no client is connected the output stays with the
+machine, so that the program sees a busy transmitter once its buffer is full instead of losing the output.
+*/
+
+use std::fs::File;
+use std::io::{self, stdout, Read, Write};
+use std::net::TcpListener;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use emulator::errors::EmulatorError;
+
+use crate::terminal::spawn_input_reader;
+
+const TCP_ADDRESS: &str = "127.0.0.1";
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LinkKind {
+    Stdio,
+    Tcp(u16),
+    Pty,
+}
+
+// Writer of the connected client, None while nobody is connected
+type Client = Arc<Mutex<Option<Box<dyn Write + Send>>>>;
+
+pub struct Link {
+    kind: LinkKind,
+    input: Receiver<u8>,
+    client: Client,
+
+    // Terminal side of the pseudo-terminal, kept open so that reading the other side doesn't fail without a client
+    _pty_slave: Option<File>,
+}
+
+impl Link {
+    pub fn open(kind: LinkKind) -> Result<Self, EmulatorError> {
+        match kind {
+            LinkKind::Stdio => Ok(Link {
+                kind,
+                input: spawn_input_reader(),
+                client: Arc::new(Mutex::new(Some(Box::new(stdout())))),
+                _pty_slave: None,
+            }),
+            LinkKind::Tcp(port) => Self::open_tcp(port),
+            LinkKind::Pty => Self::open_pty(),
+        }
+    }
+
+    pub fn kind(&self) -> LinkKind {
+        self.kind
+    }
+
+    pub fn input(&self) -> &Receiver<u8> {
+        &self.input
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.client.lock().is_ok_and(|client| client.is_some())
+    }
+
+    // Send the bytes to the client, a client that can't take them is disconnected
+    pub fn write(&self, bytes: &[u8]) -> Result<(), EmulatorError> {
+        let Ok(mut client) = self.client.lock() else {
+            return Ok(());
+        };
+
+        let Some(writer) = client.as_mut() else {
+            return Ok(());
+        };
+
+        if let Err(err) = writer.write_all(bytes).and_then(|_| writer.flush()) {
+            if self.kind == LinkKind::Stdio {
+                return Err(err.into());
+            }
+
+            *client = None;
+        }
+
+        Ok(())
+    }
+
+    // Accept the clients one after another in the background, each one gets a thread reading its input
+    fn open_tcp(port: u16) -> Result<Self, EmulatorError> {
+        let listener = TcpListener::bind((TCP_ADDRESS, port)).map_err(|e| link_failed("tcp", e))?;
+        println!("Serial port listening on {TCP_ADDRESS}:{port}");
+
+        let (sender, receiver) = channel();
+        let client: Client = Arc::new(Mutex::new(None));
+        let accepting = client.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+
+                if accepting.lock().is_ok_and(|client| client.is_some()) {
+                    continue;
+                }
+
+                let Ok(writer) = stream.try_clone() else {
+                    continue;
+                };
+
+                stream.set_nodelay(true).ok();
+                *accepting.lock().expect("serial link lock poisoned") = Some(Box::new(writer));
+
+                let disconnected = accepting.clone();
+                let sender = sender.clone();
+
+                thread::spawn(move || {
+                    forward_input(stream, &sender);
+                    disconnected.lock().expect("serial link lock poisoned").take();
+                });
+            }
+        });
+
+        Ok(Link { kind: LinkKind::Tcp(port), input: receiver, client, _pty_slave: None })
+    }
+
+    fn open_pty() -> Result<Self, EmulatorError> {
+        let (master, path) = pty::open().map_err(|e| link_failed("pty", e))?;
+        let slave = File::options().read(true).write(true).open(&path).map_err(|e| link_failed("pty", e))?;
+
+        // The clients expect a serial line, i.e. no echo or line editing by the kernel
+        Command::new("stty").args(["-F", &path, "raw", "-echo"]).stderr(Stdio::null()).status()
+            .map_err(|e| link_failed("pty", e))?;
+
+        println!("Serial port at {path}");
+
+        let reader = master.try_clone().map_err(|e| link_failed("pty", e))?;
+        let (sender, receiver) = channel();
+
+        thread::spawn(move || forward_input(reader, &sender));
+
+        Ok(Link {
+            kind: LinkKind::Pty,
+            input: receiver,
+            client: Arc::new(Mutex::new(Some(Box::new(master)))),
+            _pty_slave: Some(slave),
+        })
+    }
+}
+
+fn link_failed(kind: &str, err: io::Error) -> EmulatorError {
+    EmulatorError::SerialLinkFailed(kind.to_string(), err.to_string())
+}
+
+// Send the bytes read from the client to the machine until the client or the machine goes away
+fn forward_input(mut reader: impl Read, sender: &Sender<u8>) {
+    let mut buffer = [0u8; 256];
+
+    while let Ok(count @ 1..) = reader.read(&mut buffer) {
+        for byte in buffer[..count].iter() {
+            if sender.send(*byte).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+
+// std has no pseudo-terminals, the few libc calls needed are declared here
+#[cfg(target_os = "linux")]
+mod pty {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::FromRawFd;
+    use std::os::raw::{c_char, c_int};
+
+    const O_RDWR: c_int = 0o2;
+    const O_NOCTTY: c_int = 0o400;
+
+    extern "C" {
+        fn posix_openpt(flags: c_int) -> c_int;
+        fn grantpt(fd: c_int) -> c_int;
+        fn unlockpt(fd: c_int) -> c_int;
+        fn ptsname(fd: c_int) -> *const c_char;
+    }
+
+    // Open a new pseudo-terminal, return its controlling side and the path of its terminal side
+    pub fn open() -> io::Result<(File, String)> {
+        // SAFETY: the descriptor is checked before use and owned by the returned File, ptsname returns a string in a
+        // static buffer that is copied before any other call
+        unsafe {
+            let fd = posix_openpt(O_RDWR | O_NOCTTY);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let master = File::from_raw_fd(fd);
+
+            if grantpt(fd) != 0 || unlockpt(fd) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let name = ptsname(fd);
+            if name.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok((master, CStr::from_ptr(name).to_string_lossy().into_owned()))
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod pty {
+    use std::fs::File;
+    use std::io;
+
+    pub fn open() -> io::Result<(File, String)> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "pseudo-terminals are only supported on Linux"))
+    }
+}
@@ -0,0 +1,72 @@
+/*
+Machines built around the Intel 8080, i.e. the CPU together with the hardware of a specific board
+*/
+
+use crate::emulator::Intel8080;
+use crate::nvram::NvramProfile;
+use crate::throttle::DEFAULT_CLOCK_HZ;
+
+// Cycles run per frame by the bare CPU, matches a 60 Hz frame at 2 MHz
+const BARE_FRAME_CYCLES: u64 = 2_000_000 / 60;
+
+
+pub trait Machine {
+    fn cpu(&self) -> &Intel8080;
+
+    fn cpu_mut(&mut self) -> &mut Intel8080;
+
+    // Run the machine for the duration of one video frame, or a comparable time slice if it has no video
+    fn run_frame(&mut self);
+
+    // CPU clock that the front ends throttle the machine to
+    fn clock_hz(&self) -> u64 {
+        DEFAULT_CLOCK_HZ
+    }
+
+    // RAM regions that are kept across runs, e.g. high scores or battery backed RAM
+    fn nvram_profile(&self) -> Option<&'static NvramProfile> {
+        None
+    }
+}
+
+
+// Machines with a character terminal, e.g. a CP/M console or a serial board. The front end feeds the typed bytes in and
+// prints the output after every frame.
+pub trait Console {
+    fn push_input(&mut self, bytes: &[u8]);
+
+    // Return and clear the output since the last call
+    fn take_output(&mut self) -> Vec<u8>;
+
+    // True if the program waited for input during the last frame, so that the front end can sleep
+    fn is_waiting_for_input(&self) -> bool;
+}
+
+
+// Just the CPU and memory without any devices, e.g. for running test programs until they halt
+pub struct BareMachine {
+    cpu: Intel8080,
+}
+
+impl BareMachine {
+    pub fn new(cpu: Intel8080) -> Self {
+        BareMachine { cpu }
+    }
+}
+
+impl Machine for BareMachine {
+    fn cpu(&self) -> &Intel8080 {
+        &self.cpu
+    }
+
+    fn cpu_mut(&mut self) -> &mut Intel8080 {
+        &mut self.cpu
+    }
+
+    fn run_frame(&mut self) {
+        self.cpu.apply_freezes();
+
+        let target = self.cpu.get_cycles() + BARE_FRAME_CYCLES;
+        self.cpu.run_until(target);
+    }
+}
@@ -2,49 +2,356 @@
 Intel 8080 disassembler written in rust
 */
 
-mod errors;
-mod emulator;
+mod cli;
+mod console;
+mod debugger;
+mod link;
+mod terminal;
 
-use std::env;
-use std::path::PathBuf;
+use std::fs::read;
+use std::path::Path;
 
-use errors::EmulatorError;
-use emulator::Intel8080;
+use cli::Options;
 
+use disassembler::romdb;
 
-fn get_input_file() -> Result<PathBuf, EmulatorError> {
+use emulator::errors::EmulatorError;
+use emulator::emulator::Intel8080;
+use emulator::invaders::{self, Invaders};
+use emulator::altair::{Altair, AltairConfig};
+use emulator::checksum;
+use emulator::config::MachineConfig;
+use emulator::cpm::CpmMachine;
+use emulator::cpm::disk::DiskImage;
+use emulator::machine::{BareMachine, Console, Machine};
+use emulator::movie::Movie;
+use emulator::nvram::Nvram;
+use emulator::patch;
+use emulator::snapshot;
+use emulator::sound::SoundBoard;
+use emulator::throttle::Throttle;
+use emulator::video::{self, Framebuffer};
 
-    // Skip first arg that has the executable path
-    let iter = match env::args().nth(1) {
-        Some(i) => {
-            i
-        },
-        
-        None => {
-            return Err(EmulatorError::FilePathNotGiven);
-        },
+// Amount of entries in each section of the profile report
+const PROFILE_REPORT_LINES: usize = 20;
+
+
+// Select the CPU model, restore the state and enable the optional features before the machine starts running
+fn start(cpu: &mut Intel8080, options: &Options) -> Result<(), EmulatorError> {
+    if let Some(model) = options.cpu {
+        cpu.set_model(model);
+    }
+
+    cpu.set_undocumented_policy(options.undocumented);
+
+    if let Some(path) = &options.load_state {
+        snapshot::load_state_from_file(cpu, path)?;
+        println!("Machine state restored from '{}'", path.display());
+    }
+
+    for (addr, value_type, val) in options.freezes.iter() {
+        cpu.freeze(*addr, *value_type, *val);
+    }
+
+    if options.provenance_report.is_some() {
+        cpu.enable_provenance();
+    }
+
+    if options.profile || options.profile_folded.is_some() {
+        cpu.enable_profiler();
+    }
+
+    if options.coverage.is_some() {
+        cpu.enable_coverage();
+    }
+
+    Ok(())
+}
+
+// Load the movie to replay or start recording a new one, movies always start from power on
+fn open_movie(options: &Options, rom_crc: u32, dips: u8, latches: &[&str]) -> Result<Option<Movie>, EmulatorError> {
+    if let Some(path) = &options.play_movie {
+        let movie = Movie::load(path)?;
+        movie.check_rom(rom_crc)?;
+        println!("Replaying {} frames from '{}'", movie.len(), path.display());
+        return Ok(Some(movie));
+    }
+
+    if options.record_movie.is_some() {
+        return Ok(Some(Movie::new(rom_crc, dips, latches)));
+    }
+
+    Ok(None)
+}
+
+// Open the NVRAM file given on the command line, if the machine has regions to keep. Movies and save states don't
+// use it, so that they always replay the same way.
+fn open_nvram(machine: &dyn Machine, options: &Options) -> Result<Option<Nvram>, EmulatorError> {
+    let (Some(profile), Some(path)) = (machine.nvram_profile(), &options.nvram) else {
+        return Ok(None);
     };
-    
-    let file_path = PathBuf::from(&iter);
 
-    if !file_path.exists() {
-        return Err(EmulatorError::FilePathNotFound(iter));
+    if options.play_movie.is_some() || options.record_movie.is_some() {
+        return Ok(None);
+    }
+
+    let mut nvram = Nvram::open(profile, path)?;
+
+    if options.load_state.is_some() {
+        nvram.skip_restore();
+    }
+
+    Ok(Some(nvram))
+}
+
+// Throttle the machine to the clock given on the command line, or to its own clock
+fn open_throttle(machine: &dyn Machine, options: &Options) -> Throttle {
+    let mut throttle = Throttle::new(options.clock.unwrap_or(machine.clock_hz()), machine.cpu().get_cycles());
+    throttle.set_turbo(options.turbo);
+    throttle
+}
+
+// Run frames until the CPU halts, the frame limit or the end of the replayed movie is reached, or hand the machine
+// over to the debugger. Without a front end the machine only runs in real time when asked to.
+fn run(machine: &mut dyn Machine, options: &Options, mut movie: Option<&mut Movie>, mut nvram: Option<&mut Nvram>) {
+    if options.debug {
+        debugger::run(machine);
+        return;
     }
 
-    Ok(file_path)
+    let mut throttle = options.throttle.then(|| open_throttle(machine, options));
+    let mut frame: u64 = 0;
+
+    while !machine.cpu().is_halted() && options.frames.is_none_or(|max| frame < max) {
+        if let Some(movie) = movie.as_mut() {
+            if !movie.update(machine.cpu_mut()) {
+                break;
+            }
+        }
+
+        machine.run_frame();
+        frame += 1;
+
+        if let Some(nvram) = nvram.as_mut() {
+            nvram.update(machine.cpu_mut());
+        }
+
+        if let Some(throttle) = throttle.as_mut() {
+            if throttle.sync(machine.cpu().get_cycles()) && options.show_speed {
+                println!("Speed: {}", throttle.readout());
+            }
+        }
+    }
 }
 
+// Run a machine with a character terminal on the console, the debugger runs it without the console and prints the
+// output when it exits
+fn run_console(machine: &mut (impl Machine + Console), options: &Options) -> Result<(), EmulatorError> {
+    if options.debug {
+        run(machine, options, None, None);
+        print!("{}", String::from_utf8_lossy(&machine.take_output()));
+        return Ok(());
+    }
+
+    let mut throttle = open_throttle(machine, options);
+    console::run(machine, options.frames, options.link, &mut throttle, options.throttle, options.show_speed)
+}
+
+// Write the reports and files requested on the command line after the machine has stopped, the screen is None for
+// the machines without video
+fn finish(
+    cpu: &mut Intel8080, options: &Options, movie: Option<&mut Movie>, nvram: Option<&Nvram>,
+    screen: Option<Framebuffer>,
+) -> Result<(), EmulatorError> {
+    if let Some(nvram) = nvram {
+        if nvram.save(cpu)? {
+            println!("NVRAM saved to '{}'", nvram.path().display());
+        }
+    }
+
+    if let Some(movie) = movie {
+        if !movie.is_playing() {
+            movie.finish(cpu);
+
+            if let Some(path) = &options.record_movie {
+                movie.save(path)?;
+                println!("Movie of {} frames recorded to '{}'", movie.len(), path.display());
+            }
+        } else if options.verify {
+            movie.verify(cpu)?;
+            println!("Replay verified, the memory matches the recording");
+        }
+    }
+
+    if options.profile {
+        println!("{}", cpu.profile_report(PROFILE_REPORT_LINES)?);
+    }
+
+    if let Some(path) = &options.profile_folded {
+        cpu.write_folded_stacks(path)?;
+        println!("Folded call stacks written to '{}'", path.display());
+    }
+
+    if let Some(path) = &options.screenshot {
+        screen.ok_or(EmulatorError::VideoUnavailable)?.save(path)?;
+        println!("Screenshot saved to '{}'", path.display());
+    }
+
+    if options.sound_log.is_some() || options.sound_wav.is_some() {
+        let sound = cpu.device_mut::<SoundBoard>().ok_or(EmulatorError::SoundUnavailable)?;
+
+        if let Some(path) = &options.sound_log {
+            sound.write_log(path, invaders::CLOCK_HZ)?;
+            println!("Sound events written to '{}'", path.display());
+        }
+
+        if let Some(path) = &options.sound_wav {
+            sound.write_wav(path, invaders::CLOCK_HZ, options.samples.as_deref())?;
+            println!("Sound rendered to '{}'", path.display());
+        }
+    }
+
+    if let Some(path) = &options.coverage {
+        cpu.write_coverage(path)?;
+        println!("Coverage written to '{}'", path.display());
+    }
+
+    if let Some(path) = &options.provenance_report {
+        cpu.write_provenance_report(path, 0x0000, 0xFFFF)?;
+        println!("Memory write provenance report written to '{}'", path.display());
+    }
+
+    if let Some(path) = &options.save_state {
+        snapshot::save_state_to_file(cpu, path)?;
+        println!("Machine state saved to '{}'", path.display());
+    }
+
+    // Report the stop at an undocumented opcode after the files above, so that e.g. the save state can be examined
+    cpu.check_undocumented()
+}
 
 fn main() -> Result<(), EmulatorError>{
     println!("\n### Initializing emulator! ###\n");
 
-    let path = get_input_file()?;
+    let mut options = cli::get_options()?;
     let mut cpu = Intel8080::new();
 
-    cpu.read_rom_to_mem(path)?;
-    //cpu.emulate();
+    // CP/M programs, disks, Altair paper tapes and configurations are loaded by their machines, ROMs are identified and
+    // the whole set is assembled when given one of its parts
+    let image = if options.cpm || options.cpm_disk || options.altair || options.config {
+        read(&options.rom)?
+    } else {
+        let rom = romdb::load_rom(&options.rom).map_err(|e| EmulatorError::FileCantOpen(e.to_string()))?;
+        println!("{}", rom.describe());
+
+        for warning in rom.warnings.iter() {
+            println!("Warning: {warning}");
+        }
+
+        if rom.set.is_some_and(|set| set.machine == "invaders") {
+            options.invaders = true;
+        }
 
-    cpu.test();
+        rom.image
+    };
+
+    let rom = patch::apply_patches(&image, &options.patches, options.patch_crc)?;
+    let rom_crc = checksum::crc32(&rom);
+
+    if !options.patches.is_empty() {
+        println!("Applied {} patches, ROM checksum is {rom_crc:08x}", options.patches.len());
+    }
+
+    if options.config {
+        let config = MachineConfig::load(&options.rom)?;
+        let mut machine = config.build(cpu)?;
+        println!("Running {} at {} Hz", machine.name(), config.clock_hz);
+
+        start(machine.cpu_mut(), &options)?;
+
+        if machine.has_console() {
+            run_console(&mut machine, &options)?;
+        } else {
+            run(&mut machine, &options, None, None);
+        }
+
+        finish(machine.cpu_mut(), &options, None, None, None)?;
+    } else if options.cpm || options.cpm_disk {
+        let mut machine = if options.cpm_disk {
+            let mut disks = vec![DiskImage::open(&options.rom)?];
+            for path in options.disks.iter() {
+                disks.push(DiskImage::open(path)?);
+            }
+
+            CpmMachine::boot(cpu, disks)?
+        } else {
+            let program_dir = options.rom.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            let dir = options.cpm_dir.clone().unwrap_or_else(|| program_dir.to_path_buf());
+
+            CpmMachine::new(cpu, &rom, &dir, &options.cpm_args)?
+        };
+
+        start(machine.cpu_mut(), &options)?;
+        run_console(&mut machine, &options)?;
+        finish(machine.cpu_mut(), &options, None, None, None)?;
+    } else if options.altair {
+        let config = AltairConfig {
+            ram_kb: options.ram_kb,
+            serial: options.serial,
+            sense_switches: options.sense,
+            baud: options.baud,
+        };
+        let mut machine = Altair::new(cpu, &config)?;
+
+        machine.load(&rom, options.load_addr)?;
+        machine.start(options.start_addr.unwrap_or(options.load_addr));
+
+        start(machine.cpu_mut(), &options)?;
+        run_console(&mut machine, &options)?;
+        finish(machine.cpu_mut(), &options, None, None, None)?;
+    } else if options.invaders {
+        cpu.load_rom(&options.board.place_rom(&rom)?);
+        let mut machine = Invaders::with_board(cpu, options.board);
+
+        if let Some(path) = &options.prom {
+            machine.set_colour_prom(&read(path)?)?;
+        }
+
+        if let Some(dips) = options.dips {
+            machine.set_dips(dips);
+        }
+
+        let mut movie = open_movie(&options, rom_crc, machine.dips(), &options.board.latch_names())?;
+        if let Some(movie) = movie.as_ref().filter(|movie| movie.is_playing()) {
+            machine.set_dips(movie.dips());
+        }
+
+        let mut nvram = open_nvram(&machine, &options)?;
+        start(machine.cpu_mut(), &options)?;
+
+        match options.terminal {
+            Some(mode) => {
+                let mut throttle = open_throttle(&machine, &options);
+                let speed = options.show_speed;
+                terminal::run(&mut machine, mode, options.frames, movie.as_mut(), nvram.as_mut(), &mut throttle, speed)?
+            },
+            None => run(&mut machine, &options, movie.as_mut(), nvram.as_mut()),
+        }
+
+        let screen = options.screenshot.is_some().then(|| machine.render(options.overlay));
+        finish(machine.cpu_mut(), &options, movie.as_mut(), nvram.as_ref(), screen)?;
+    } else {
+        cpu.load_rom(&rom);
+        let mut machine = BareMachine::new(cpu);
+        let mut movie = open_movie(&options, rom_crc, 0x00, &[])?;
+
+        let mut nvram = open_nvram(&machine, &options)?;
+
+        start(machine.cpu_mut(), &options)?;
+        run(&mut machine, &options, movie.as_mut(), nvram.as_mut());
+        let screen = video::render_invaders(machine.cpu().memory(), options.overlay);
+        finish(machine.cpu_mut(), &options, movie.as_mut(), nvram.as_ref(), Some(screen))?;
+    }
 
     println!("\n### Emulator exiting! ###");
     Ok(())
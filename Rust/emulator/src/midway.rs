@@ -0,0 +1,241 @@
+/*
+Midway 8080 board family
+
+Space Invaders and the games built on its hardware share the CPU, the RAM layout, the 256x224 bitmap video and the
+two interrupts per frame, see invaders.rs. They differ in:
+    - the ROM, the Taito games add a second ROM area at 0x4000
+    - the IN ports that the controls and the DIP switches are wired to
+    - the ports of the shift register, Sea Wolf can also read its result with the bits reversed
+    - the sound boards, only the Space Invaders style board on OUT 3 and OUT 5 is recorded
+    - the colours: cellophane overlays, plain black and white, or a colour PROM that gives every 8x8 pixel cell one
+      of 8 colours
+
+    name        game                                 ROM                           colours
+    invaders    Space Invaders (Midway/Taito 1978)   0x0000-0x1FFF                 overlay
+    invadpt2    Space Invaders Part II (Taito 1979)  0x0000-0x1FFF, 0x4000-0x5FFF  colour PROM
+    lrescue     Lunar Rescue (Taito 1979)            0x0000-0x1FFF, 0x4000-0x47FF  colour PROM
+    ballbomb    Balloon Bomber (Taito 1980)          0x0000-0x1FFF, 0x4000-0x47FF  colour PROM
+    gunfight    Gun Fight (Midway 1975)              0x0000-0x1FFF                 black and white
+    seawolf     Sea Wolf (Midway 1976)               0x0000-0x1FFF                 black and white
+
+The ROM image holds the ROM areas one after another. The colour PROMs are separate files of two 1KB colour maps.
+*/
+
+use crate::errors::EmulatorError;
+use crate::invaders;
+use crate::nvram::NvramProfile;
+
+pub const PROM_SIZE: usize = 0x800;
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Colours {
+    Overlay,
+    Mono,
+    Prom,
+}
+
+// Ports of the shift register, see devices.rs
+pub struct Shifter {
+    pub offset_port: u8,
+    pub data_port: u8,
+    pub result_port: u8,
+
+    // Port that returns the result with the bits reversed, when bit 3 of the offset is set
+    pub reversed_port: Option<u8>,
+}
+
+// Input latch device name, IN port and the value with no buttons pressed
+pub struct Input {
+    pub name: &'static str,
+    pub port: u8,
+    pub idle: u8,
+}
+
+// Buttons and joystick directions of player 1 that the front ends can press
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Control {
+    Coin,
+    P1Start,
+    P2Start,
+    Left,
+    Right,
+    Fire,
+}
+
+// Index of the input latch and the bit that a control is wired to, active low bits are cleared when pressed
+pub struct ControlBit {
+    pub control: Control,
+    pub input: usize,
+    pub bit: u8,
+    pub active_low: bool,
+}
+
+pub struct Board {
+    pub name: &'static str,
+    pub title: &'static str,
+
+    // Start and length of the ROM areas in the order of the image
+    pub rom: &'static [(u16, usize)],
+
+    pub inputs: &'static [Input],
+    pub shifter: Shifter,
+
+    // Wiring of the controls, empty when it is not known
+    pub controls: &'static [ControlBit],
+
+    // Space Invaders style sound board on OUT 3 and OUT 5
+    pub sound: bool,
+    pub colours: Colours,
+
+    // Index of the input latch with the DIP switches and their bits
+    pub dips: Option<(usize, u8)>,
+
+    // RAM regions such as the high scores to keep across runs, None when their location is not known
+    pub nvram: Option<&'static NvramProfile>,
+}
+
+// Bit 3 of IN 1 is always set
+const INVADERS_INPUTS: [Input; 3] = [
+    Input { name: "in0", port: 0, idle: 0x0E },
+    Input { name: "in1", port: 1, idle: 0x08 },
+    Input { name: "in2", port: 2, idle: 0x00 },
+];
+
+// Player 1 and the coin on IN 1, active high
+const INVADERS_CONTROLS: [ControlBit; 6] = [
+    ControlBit { control: Control::Coin, input: 1, bit: invaders::COIN, active_low: false },
+    ControlBit { control: Control::P1Start, input: 1, bit: invaders::P1_START, active_low: false },
+    ControlBit { control: Control::P2Start, input: 1, bit: invaders::P2_START, active_low: false },
+    ControlBit { control: Control::Left, input: 1, bit: invaders::P1_LEFT, active_low: false },
+    ControlBit { control: Control::Right, input: 1, bit: invaders::P1_RIGHT, active_low: false },
+    ControlBit { control: Control::Fire, input: 1, bit: invaders::P1_FIRE, active_low: false },
+];
+
+const INVADERS_SHIFTER: Shifter = Shifter { offset_port: 2, data_port: 4, result_port: 3, reversed_port: None };
+
+pub const INVADERS: Board = Board {
+    name: "invaders",
+    title: "Space Invaders",
+    rom: &[(0x0000, 0x2000)],
+    inputs: &INVADERS_INPUTS,
+    shifter: INVADERS_SHIFTER,
+    controls: &INVADERS_CONTROLS,
+    sound: true,
+    colours: Colours::Overlay,
+    dips: Some((2, invaders::DIP_MASK)),
+    nvram: Some(&invaders::NVRAM),
+};
+
+pub const INVADERS_PART_2: Board = Board {
+    name: "invadpt2",
+    title: "Space Invaders Part II",
+    rom: &[(0x0000, 0x2000), (0x4000, 0x2000)],
+    inputs: &INVADERS_INPUTS,
+    shifter: INVADERS_SHIFTER,
+    controls: &INVADERS_CONTROLS,
+    sound: true,
+    colours: Colours::Prom,
+    dips: Some((2, invaders::DIP_MASK)),
+    nvram: None,
+};
+
+pub const LUNAR_RESCUE: Board = Board {
+    name: "lrescue",
+    title: "Lunar Rescue",
+    rom: &[(0x0000, 0x2000), (0x4000, 0x0800)],
+    inputs: &INVADERS_INPUTS,
+    shifter: INVADERS_SHIFTER,
+    controls: &INVADERS_CONTROLS,
+    sound: true,
+    colours: Colours::Prom,
+    dips: Some((2, invaders::DIP_MASK)),
+    nvram: None,
+};
+
+pub const BALLOON_BOMBER: Board = Board {
+    name: "ballbomb",
+    title: "Balloon Bomber",
+    rom: &[(0x0000, 0x2000), (0x4000, 0x0800)],
+    inputs: &INVADERS_INPUTS,
+    shifter: INVADERS_SHIFTER,
+    controls: &INVADERS_CONTROLS,
+    sound: true,
+    colours: Colours::Prom,
+    dips: Some((2, invaders::DIP_MASK)),
+    nvram: None,
+};
+
+// Player 1 and 2 on IN 0 and IN 1 with the moves in bits 0-3, the gun aim in bits 4-6 and the trigger in bit 7, the
+// DIP switches and the coin on IN 2. The sound board is on OUT 1.
+pub const GUN_FIGHT: Board = Board {
+    name: "gunfight",
+    title: "Gun Fight",
+    rom: &[(0x0000, 0x2000)],
+    inputs: &[
+        Input { name: "in0", port: 0, idle: 0xFF },
+        Input { name: "in1", port: 1, idle: 0xFF },
+        Input { name: "in2", port: 2, idle: 0x00 },
+    ],
+    shifter: Shifter { offset_port: 2, data_port: 4, result_port: 3, reversed_port: None },
+    controls: &[],
+    sound: false,
+    colours: Colours::Mono,
+    dips: Some((2, 0x3F)),
+    nvram: None,
+};
+
+// The periscope position and the fire button on IN 1, the coin and the DIP switches on IN 2. The lamps are on
+// OUT 1-2 and the sound board is on OUT 5.
+pub const SEA_WOLF: Board = Board {
+    name: "seawolf",
+    title: "Sea Wolf",
+    rom: &[(0x0000, 0x2000)],
+    inputs: &[
+        Input { name: "in0", port: 1, idle: 0x00 },
+        Input { name: "in1", port: 2, idle: 0x00 },
+    ],
+    shifter: Shifter { offset_port: 4, data_port: 3, result_port: 3, reversed_port: Some(0) },
+    controls: &[],
+    sound: false,
+    colours: Colours::Mono,
+    dips: Some((1, 0xFE)),
+    nvram: None,
+};
+
+pub const BOARDS: [&Board; 6] = [&INVADERS, &INVADERS_PART_2, &LUNAR_RESCUE, &BALLOON_BOMBER, &GUN_FIGHT, &SEA_WOLF];
+
+
+pub fn find(name: &str) -> Option<&'static Board> {
+    BOARDS.iter().find(|board| board.name == name).copied()
+}
+
+impl Board {
+    pub fn rom_size(&self) -> usize {
+        self.rom.iter().map(|(_, len)| len).sum()
+    }
+
+    pub fn latch_names(&self) -> Vec<&'static str> {
+        self.inputs.iter().map(|input| input.name).collect()
+    }
+
+    // Spread the ROM image over the ROM areas, returning the memory image from address 0 up to the end of the last
+    // area. Shorter images leave the rest of the areas empty, e.g. for homebrew that only uses the first one.
+    pub fn place_rom(&self, image: &[u8]) -> Result<Vec<u8>, EmulatorError> {
+        if image.len() > self.rom_size() {
+            return Err(EmulatorError::RomSizeInvalid(self.name.to_string(), image.len(), self.rom_size()));
+        }
+
+        let end = self.rom.iter().map(|(start, len)| *start as usize + len).max().unwrap_or(0);
+        let mut mem = vec![0x00; end];
+        let mut pos = 0;
+
+        for (start, len) in self.rom.iter() {
+            let part = &image[pos.min(image.len())..(pos + len).min(image.len())];
+            mem[*start as usize..*start as usize + part.len()].copy_from_slice(part);
+            pos += len;
+        }
+
+        Ok(mem)
+    }
+}
@@ -0,0 +1,209 @@
+/*
+Movies - Record the input latches once per frame, so that a run can be replayed deterministically e.g. for bug
+reports and regression tests
+
+A movie starts from power on. Since the emulation only depends on the ROM and the inputs, replaying the recorded
+input values frame by frame reproduces the exact same execution, which is checked by comparing the hash of the
+memory at the end of the movie.
+
+File layout (all values little endian, strings and byte blobs are prefixed with their u32 length):
+    8 bytes     Magic "I8080MOV"
+    2 bytes     Format version
+    4 bytes     CRC32 of the ROM file
+    1 byte      DIP switch settings
+    4 bytes     CRC32 of the whole memory after the last frame
+    2 bytes     Number of input latches, followed by their device names
+    4 bytes     Number of frames, followed by the values of the input latches for each frame
+*/
+
+use std::fs::{read, write};
+use std::path::Path;
+
+use crate::checksum;
+use crate::devices::InputLatch;
+use crate::emulator::Intel8080;
+use crate::errors::EmulatorError;
+use crate::snapshot::{StateReader, StateWriter};
+
+const MAGIC: &[u8; 8] = b"I8080MOV";
+pub const VERSION: u16 = 1;
+
+
+pub struct Movie {
+    rom_crc: u32,
+    dips: u8,
+    mem_crc: u32,
+    latches: Vec<String>,
+    frames: Vec<Vec<u8>>,
+
+    // Replaying the loaded movie instead of recording a new one
+    playing: bool,
+    position: usize,
+}
+
+// Hash of the whole 64K memory, used for checking that a replay ended in the same state as the recording
+pub fn memory_hash(cpu: &Intel8080) -> u32 {
+    checksum::crc32(cpu.memory())
+}
+
+impl Movie {
+    // Start recording a new movie of the given input latch devices
+    pub fn new(rom_crc: u32, dips: u8, latches: &[&str]) -> Self {
+        Movie {
+            rom_crc,
+            dips,
+            mem_crc: 0,
+            latches: latches.iter().map(|name| name.to_string()).collect(),
+            frames: Vec::new(),
+
+            playing: false,
+            position: 0,
+        }
+    }
+
+    pub fn rom_crc(&self) -> u32 {
+        self.rom_crc
+    }
+
+    pub fn dips(&self) -> u8 {
+        self.dips
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    // Record the input values of the next frame, or set them from the movie when replaying.
+    // Return false if the replay has reached the end of the movie.
+    pub fn update(&mut self, cpu: &mut Intel8080) -> bool {
+        if self.playing {
+            let Some(values) = self.frames.get(self.position) else {
+                return false;
+            };
+
+            for (name, val) in self.latches.iter().zip(values) {
+                if let Some(latch) = cpu.named_device_mut::<InputLatch>(name) {
+                    latch.set(*val);
+                }
+            }
+        } else {
+            let values = self.latches
+                .iter()
+                .map(|name| cpu.named_device_mut::<InputLatch>(name).map_or(0x00, |latch| latch.get()))
+                .collect();
+
+            self.frames.push(values);
+        }
+
+        self.position += 1;
+        true
+    }
+
+    // Store the memory hash at the end of the recording
+    pub fn finish(&mut self, cpu: &Intel8080) {
+        self.mem_crc = memory_hash(cpu);
+    }
+
+    // Check that the movie was recorded with the same ROM
+    pub fn check_rom(&self, rom_crc: u32) -> Result<(), EmulatorError> {
+        if rom_crc != self.rom_crc {
+            return Err(EmulatorError::MovieRomMismatch(self.rom_crc, rom_crc));
+        }
+
+        Ok(())
+    }
+
+    // Check that the replay ended with the same memory as the recording
+    pub fn verify(&self, cpu: &Intel8080) -> Result<(), EmulatorError> {
+        let mem_crc = memory_hash(cpu);
+
+        if self.position != self.frames.len() || mem_crc != self.mem_crc {
+            return Err(EmulatorError::MovieDesync(self.mem_crc, mem_crc));
+        }
+
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), EmulatorError> {
+        let mut writer = StateWriter::new();
+
+        for byte in MAGIC {
+            writer.write_u8(*byte);
+        }
+
+        writer.write_u16(VERSION);
+        writer.write_u32(self.rom_crc);
+        writer.write_u8(self.dips);
+        writer.write_u32(self.mem_crc);
+
+        writer.write_u16(self.latches.len() as u16);
+        for name in self.latches.iter() {
+            writer.write_str(name);
+        }
+
+        writer.write_u32(self.frames.len() as u32);
+        for values in self.frames.iter() {
+            for val in values {
+                writer.write_u8(*val);
+            }
+        }
+
+        write(path, writer.into_bytes())?;
+        Ok(())
+    }
+
+    // Load a movie for replaying
+    pub fn load(path: &Path) -> Result<Self, EmulatorError> {
+        let data = read(path)?;
+        let mut reader = StateReader::new(&data);
+
+        for byte in MAGIC {
+            if reader.read_u8()? != *byte {
+                return Err(EmulatorError::MovieInvalid(path.display().to_string()));
+            }
+        }
+
+        let version = reader.read_u16()?;
+        if version != VERSION {
+            return Err(EmulatorError::MovieInvalid(path.display().to_string()));
+        }
+
+        let rom_crc = reader.read_u32()?;
+        let dips = reader.read_u8()?;
+        let mem_crc = reader.read_u32()?;
+
+        let mut latches = Vec::new();
+        for _ in 0..reader.read_u16()? {
+            latches.push(reader.read_str()?);
+        }
+
+        let mut frames = Vec::new();
+        for _ in 0..reader.read_u32()? {
+            let mut values = Vec::with_capacity(latches.len());
+            for _ in 0..latches.len() {
+                values.push(reader.read_u8()?);
+            }
+
+            frames.push(values);
+        }
+
+        Ok(Movie {
+            rom_crc,
+            dips,
+            mem_crc,
+            latches,
+            frames,
+
+            playing: true,
+            position: 0,
+        })
+    }
+}
@@ -0,0 +1,154 @@
+/*
+NVRAM - Keep RAM regions such as high scores or battery backed RAM across runs
+
+The regions are saved into a file when the emulator exits, and written back into memory once the machine has
+booted, since the boot code of most games clears or initializes the RAM.
+
+File layout (all values little endian):
+    8 bytes     Magic "I8080NVR"
+    2 bytes     Format version
+    2 bytes     Number of regions, followed by the start address, the length and the bytes of each region
+*/
+
+use std::fs::{read, write};
+use std::path::{Path, PathBuf};
+
+use crate::emulator::Intel8080;
+use crate::errors::EmulatorError;
+use crate::snapshot::{StateReader, StateWriter};
+
+const MAGIC: &[u8; 8] = b"I8080NVR";
+pub const VERSION: u16 = 1;
+
+
+pub struct NvramRegion {
+    pub start: u16,
+    pub len: u16,
+}
+
+// The persisted regions of a machine
+pub struct NvramProfile {
+    pub regions: &'static [NvramRegion],
+
+    // Frames to run after power on before the regions are restored
+    pub restore_frame: u64,
+}
+
+
+pub struct Nvram {
+    profile: &'static NvramProfile,
+    path: PathBuf,
+
+    // Contents from the file waiting to be restored, one entry per region
+    saved: Option<Vec<Vec<u8>>>,
+    frame: u64,
+}
+
+impl Nvram {
+    // Read the regions from the file if it exists. The file is only written when saving.
+    pub fn open(profile: &'static NvramProfile, path: &Path) -> Result<Self, EmulatorError> {
+        let saved = if path.exists() { Some(read_regions(profile, path)?) } else { None };
+
+        Ok(Nvram {
+            profile,
+            path: path.to_path_buf(),
+
+            saved,
+            frame: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Don't restore the regions, e.g. when starting from a save state that already has them
+    pub fn skip_restore(&mut self) {
+        self.saved = None;
+    }
+
+    // Count the frames since power on and restore the regions at the restore frame, call after every frame.
+    // Return true when the regions were restored.
+    pub fn update(&mut self, cpu: &mut Intel8080) -> bool {
+        self.frame += 1;
+
+        if self.frame < self.profile.restore_frame {
+            return false;
+        }
+
+        let Some(saved) = self.saved.take() else {
+            return false;
+        };
+
+        for (region, bytes) in self.profile.regions.iter().zip(saved) {
+            for (i, byte) in bytes.into_iter().enumerate() {
+                cpu.write_byte(region.start.wrapping_add(i as u16), byte);
+            }
+        }
+
+        true
+    }
+
+    // Write the regions into the file. Return false without writing if the regions were never restored, so that
+    // e.g. a short run doesn't overwrite the saved high score with the initial one.
+    pub fn save(&self, cpu: &Intel8080) -> Result<bool, EmulatorError> {
+        if self.saved.is_some() {
+            return Ok(false);
+        }
+
+        let mut writer = StateWriter::new();
+
+        for byte in MAGIC {
+            writer.write_u8(*byte);
+        }
+
+        writer.write_u16(VERSION);
+        writer.write_u16(self.profile.regions.len() as u16);
+
+        for region in self.profile.regions {
+            writer.write_u16(region.start);
+            writer.write_u16(region.len);
+
+            for i in 0..region.len {
+                writer.write_u8(cpu.read_byte(region.start.wrapping_add(i)));
+            }
+        }
+
+        write(&self.path, writer.into_bytes())?;
+        Ok(true)
+    }
+}
+
+// Read the file, the regions in it must match the profile
+fn read_regions(profile: &NvramProfile, path: &Path) -> Result<Vec<Vec<u8>>, EmulatorError> {
+    let data = read(path)?;
+    let mut reader = StateReader::new(&data);
+    let invalid = || EmulatorError::NvramInvalid(path.display().to_string());
+
+    for byte in MAGIC {
+        if reader.read_u8()? != *byte {
+            return Err(invalid());
+        }
+    }
+
+    if reader.read_u16()? != VERSION || reader.read_u16()? as usize != profile.regions.len() {
+        return Err(invalid());
+    }
+
+    let mut saved = Vec::new();
+
+    for region in profile.regions {
+        if reader.read_u16()? != region.start || reader.read_u16()? != region.len {
+            return Err(invalid());
+        }
+
+        let mut bytes = Vec::with_capacity(region.len as usize);
+        for _ in 0..region.len {
+            bytes.push(reader.read_u8()?);
+        }
+
+        saved.push(bytes);
+    }
+
+    Ok(saved)
+}
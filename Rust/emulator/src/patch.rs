@@ -0,0 +1,407 @@
+/*
+ROM patches - Apply IPS, BPS and inline byte patches to the ROM image before it is loaded into memory
+
+IPS:
+    5 bytes     Magic "PATCH"
+    Records of a 3 byte big endian offset and a 2 byte big endian size followed by size bytes of data. A size of 0
+    is a run of a 2 byte big endian length of a single byte value.
+    3 bytes     "EOF", optionally followed by a 3 byte big endian size to truncate the image to
+
+BPS:
+    4 bytes     Magic "BPS1"
+    Variable length numbers of the source size, target size and metadata size followed by the metadata
+    Actions (SourceRead, TargetRead, SourceCopy, TargetCopy) until the footer
+    12 bytes    CRC32 of the source, the target and the patch itself
+
+Only BPS patches contain the checksum of the image they were made for, the others can be checked against an
+expected CRC32 of the original image given by the user.
+*/
+
+use std::fs::read;
+use std::path::Path;
+
+use crate::checksum;
+use crate::errors::EmulatorError;
+
+const IPS_MAGIC: &[u8; 5] = b"PATCH";
+const IPS_EOF: &[u8; 3] = b"EOF";
+const BPS_MAGIC: &[u8; 4] = b"BPS1";
+
+// Inline patches must fit into the 64KB address space
+const INLINE_END: usize = 0x10000;
+
+
+pub enum Patch {
+    Ips(Vec<u8>),
+    Bps(Vec<u8>),
+    Inline { addr: usize, bytes: Vec<u8> },
+}
+
+impl Patch {
+    // Load an IPS or BPS patch file, the format is detected from the magic bytes
+    pub fn load(path: &Path) -> Result<Self, EmulatorError> {
+        let data = read(path)?;
+
+        if data.starts_with(IPS_MAGIC) {
+            Ok(Patch::Ips(data))
+        } else if data.starts_with(BPS_MAGIC) {
+            Ok(Patch::Bps(data))
+        } else {
+            Err(EmulatorError::PatchInvalid(format!("'{}' is not an IPS or BPS patch", path.display())))
+        }
+    }
+
+    // Parse an inline patch of the form <addr>:<hex bytes>, e.g. "0x1A2B:C90000" or "1A2B:C9,00,00". The bytes must end
+    // within the 64KB address space.
+    pub fn parse_inline(s: &str) -> Option<Self> {
+        let (addr, hex) = s.split_once(':')?;
+        let addr = usize::from_str_radix(addr.trim_start_matches("0x").trim_start_matches("0X"), 16).ok()?;
+
+        let digits: Vec<char> = hex.chars().filter(|c| !matches!(c, ',' | ' ' | '_')).collect();
+        if digits.is_empty() || !digits.len().is_multiple_of(2) {
+            return None;
+        }
+
+        let bytes = digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(&pair.iter().collect::<String>(), 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+
+        if addr.checked_add(bytes.len())? > INLINE_END {
+            return None;
+        }
+
+        Some(Patch::Inline { addr, bytes })
+    }
+
+    // Return the patched copy of the image
+    pub fn apply(&self, image: &[u8]) -> Result<Vec<u8>, EmulatorError> {
+        match self {
+            Patch::Ips(data) => apply_ips(data, image),
+            Patch::Bps(data) => apply_bps(data, image),
+            Patch::Inline { addr, bytes } => {
+                let mut target = image.to_vec();
+
+                if target.len() < addr + bytes.len() {
+                    target.resize(addr + bytes.len(), 0x00);
+                }
+
+                target[*addr..addr + bytes.len()].copy_from_slice(bytes);
+                Ok(target)
+            },
+        }
+    }
+}
+
+// Check the original image against the expected CRC32 and apply the patches in order
+pub fn apply_patches(image: &[u8], patches: &[Patch], expected_crc: Option<u32>) -> Result<Vec<u8>, EmulatorError> {
+    if let Some(expected) = expected_crc {
+        let crc = checksum::crc32(image);
+
+        if crc != expected {
+            return Err(EmulatorError::PatchChecksum(String::from("source"), expected, crc));
+        }
+    }
+
+    let mut target = image.to_vec();
+    for patch in patches {
+        target = patch.apply(&target)?;
+    }
+
+    Ok(target)
+}
+
+fn invalid(msg: &str) -> EmulatorError {
+    EmulatorError::PatchInvalid(msg.to_string())
+}
+
+fn apply_ips(data: &[u8], image: &[u8]) -> Result<Vec<u8>, EmulatorError> {
+    let mut target = image.to_vec();
+    let mut pos = IPS_MAGIC.len();
+
+    let take = |pos: &mut usize, len: usize| -> Result<&[u8], EmulatorError> {
+        let bytes = data.get(*pos..*pos + len).ok_or_else(|| invalid("IPS patch ends without EOF"))?;
+        *pos += len;
+        Ok(bytes)
+    };
+
+    loop {
+        let record = take(&mut pos, 3)?;
+        if record == IPS_EOF {
+            break;
+        }
+
+        let offset = (record[0] as usize) << 16 | (record[1] as usize) << 8 | record[2] as usize;
+        let size = take(&mut pos, 2)?;
+        let size = (size[0] as usize) << 8 | size[1] as usize;
+
+        let bytes = if size == 0 {
+            let run = take(&mut pos, 3)?;
+            vec![run[2]; (run[0] as usize) << 8 | run[1] as usize]
+        } else {
+            take(&mut pos, size)?.to_vec()
+        };
+
+        if target.len() < offset + bytes.len() {
+            target.resize(offset + bytes.len(), 0x00);
+        }
+
+        target[offset..offset + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    // Truncation extension
+    if let Ok(size) = take(&mut pos, 3) {
+        target.truncate((size[0] as usize) << 16 | (size[1] as usize) << 8 | size[2] as usize);
+    }
+
+    Ok(target)
+}
+
+// Variable length number of BPS, 7 bits per byte with the highest bit ending the number
+fn read_bps_number(data: &[u8], pos: &mut usize) -> Result<usize, EmulatorError> {
+    let mut number: usize = 0;
+    let mut shift: usize = 1;
+
+    loop {
+        let byte = *data.get(*pos).ok_or_else(|| invalid("BPS patch ends in the middle of a number"))?;
+        *pos += 1;
+
+        number = ((byte & 0x7F) as usize)
+            .checked_mul(shift)
+            .and_then(|val| number.checked_add(val))
+            .ok_or_else(|| invalid("BPS patch has a too large number"))?;
+
+        if byte & 0x80 != 0 {
+            return Ok(number);
+        }
+
+        shift = shift.checked_mul(0x80).ok_or_else(|| invalid("BPS patch has a too large number"))?;
+        number = number.checked_add(shift).ok_or_else(|| invalid("BPS patch has a too large number"))?;
+    }
+}
+
+fn read_crc(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+}
+
+fn apply_bps(data: &[u8], image: &[u8]) -> Result<Vec<u8>, EmulatorError> {
+    if data.len() < BPS_MAGIC.len() + 12 {
+        return Err(invalid("BPS patch is too short"));
+    }
+
+    let footer = data.len() - 12;
+    let source_crc = read_crc(data, footer);
+    let target_crc = read_crc(data, footer + 4);
+    let patch_crc = read_crc(data, footer + 8);
+
+    let crc = checksum::crc32(&data[..footer + 8]);
+    if crc != patch_crc {
+        return Err(EmulatorError::PatchChecksum(String::from("patch"), patch_crc, crc));
+    }
+
+    let crc = checksum::crc32(image);
+    if crc != source_crc {
+        return Err(EmulatorError::PatchChecksum(String::from("source"), source_crc, crc));
+    }
+
+    let mut pos = BPS_MAGIC.len();
+    let source_size = read_bps_number(data, &mut pos)?;
+    let target_size = read_bps_number(data, &mut pos)?;
+    let metadata_size = read_bps_number(data, &mut pos)?;
+    pos = pos.saturating_add(metadata_size);
+
+    if source_size != image.len() {
+        return Err(invalid("BPS patch was made for an image of a different size"));
+    }
+
+    let mut target: Vec<u8> = Vec::new();
+    let (mut source_offset, mut target_offset): (isize, isize) = (0, 0);
+
+    while pos < footer {
+        let action = read_bps_number(data, &mut pos)?;
+        let len = (action >> 2) + 1;
+
+        match action & 0x03 {
+            // SourceRead - Copy from the same position of the source
+            0 => {
+                let start = target.len();
+                let bytes = image.get(start..start + len).ok_or_else(|| invalid("BPS SourceRead out of range"))?;
+                target.extend_from_slice(bytes);
+            },
+
+            // TargetRead - Copy from the patch
+            1 => {
+                let bytes = data.get(pos..pos + len).filter(|_| pos + len <= footer)
+                    .ok_or_else(|| invalid("BPS TargetRead out of range"))?;
+                target.extend_from_slice(bytes);
+                pos += len;
+            },
+
+            // SourceCopy and TargetCopy - Copy from a signed relative offset of the source or the target
+            command => {
+                let offset = read_bps_number(data, &mut pos)?;
+                let delta = if offset & 1 == 1 { -((offset >> 1) as isize) } else { (offset >> 1) as isize };
+
+                if command == 2 {
+                    source_offset += delta;
+
+                    let start = usize::try_from(source_offset).map_err(|_| invalid("BPS SourceCopy out of range"))?;
+                    let bytes = image.get(start..start + len).ok_or_else(|| invalid("BPS SourceCopy out of range"))?;
+                    target.extend_from_slice(bytes);
+                    source_offset += len as isize;
+                } else {
+                    target_offset += delta;
+
+                    // Byte by byte, since the copied range may overlap the bytes being written
+                    for _ in 0..len {
+                        let byte = usize::try_from(target_offset)
+                            .ok()
+                            .and_then(|i| target.get(i).copied())
+                            .ok_or_else(|| invalid("BPS TargetCopy out of range"))?;
+                        target.push(byte);
+                        target_offset += 1;
+                    }
+                }
+            },
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(invalid("BPS patch produced an image of the wrong size"));
+    }
+
+    let crc = checksum::crc32(&target);
+    if crc != target_crc {
+        return Err(EmulatorError::PatchChecksum(String::from("target"), target_crc, crc));
+    }
+
+    Ok(target)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_bps_number(patch: &mut Vec<u8>, mut number: usize) {
+        loop {
+            let byte = (number & 0x7F) as u8;
+            number >>= 7;
+
+            if number == 0 {
+                patch.push(byte | 0x80);
+                return;
+            }
+
+            patch.push(byte);
+            number -= 1;
+        }
+    }
+
+    // BPS patch of the actions, each an action number followed by its data, with valid checksums
+    fn bps_patch(source: &[u8], target: &[u8], actions: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        write_bps_number(&mut patch, source.len());
+        write_bps_number(&mut patch, target.len());
+        write_bps_number(&mut patch, 0);
+
+        for (action, data) in actions {
+            write_bps_number(&mut patch, *action);
+            patch.extend_from_slice(data);
+        }
+
+        patch.extend_from_slice(&checksum::crc32(source).to_le_bytes());
+        patch.extend_from_slice(&checksum::crc32(target).to_le_bytes());
+        patch.extend_from_slice(&checksum::crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn bps_numbers_round_trip() {
+        for number in [0, 1, 0x7F, 0x80, 0x407F, 0x4080, 0x123456] {
+            let mut data = Vec::new();
+            write_bps_number(&mut data, number);
+            assert_eq!(read_bps_number(&data, &mut 0).unwrap(), number);
+        }
+
+        let result = read_bps_number(&[0x7F; 20], &mut 0);
+        assert!(matches!(result, Err(EmulatorError::PatchInvalid(_))));
+    }
+
+    #[test]
+    fn ips_records_runs_and_truncation() {
+        let mut data = IPS_MAGIC.to_vec();
+        data.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        data.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+        data.extend_from_slice(IPS_EOF);
+
+        let target = Patch::Ips(data.clone()).apply(&[0; 4]).unwrap();
+        assert_eq!(target, vec![0x00, 0xAA, 0xBB, 0x00, 0x00, 0x00, 0xCC, 0xCC, 0xCC]);
+
+        data.extend_from_slice(&[0x00, 0x00, 0x02]);
+        assert_eq!(Patch::Ips(data).apply(&[0; 4]).unwrap(), vec![0x00, 0xAA]);
+    }
+
+    #[test]
+    fn ips_without_eof_fails() {
+        let mut data = IPS_MAGIC.to_vec();
+        data.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA]);
+
+        assert!(matches!(Patch::Ips(data).apply(&[0; 4]), Err(EmulatorError::PatchInvalid(_))));
+    }
+
+    #[test]
+    fn bps_actions() {
+        let source = b"ABCDEFGH";
+        let target = b"ABxyGHABx";
+
+        // SourceRead 2, TargetRead "xy", SourceCopy 2 from +6, TargetCopy 3 from 0
+        let patch = bps_patch(source, target, &[
+            ((2 - 1) << 2, &[]),
+            ((2 - 1) << 2 | 1, b"xy"),
+            ((2 - 1) << 2 | 2, &[6 << 1 | 0x80]),
+            ((3 - 1) << 2 | 3, &[0x80]),
+        ]);
+
+        assert_eq!(Patch::Bps(patch).apply(source).unwrap(), target.to_vec());
+    }
+
+    #[test]
+    fn bps_checks_the_checksums() {
+        let source = b"ABCD";
+        let patch = bps_patch(source, b"AB", &[((2 - 1) << 2, &[])]);
+
+        let result = Patch::Bps(patch.clone()).apply(b"ABCE");
+        assert!(matches!(result, Err(EmulatorError::PatchChecksum(s, _, _)) if s == "source"));
+
+        let mut corrupted = patch;
+        corrupted[4] ^= 0x01;
+        let result = Patch::Bps(corrupted).apply(source);
+        assert!(matches!(result, Err(EmulatorError::PatchChecksum(s, _, _)) if s == "patch"));
+    }
+
+    #[test]
+    fn inline_patches() {
+        let patch = Patch::parse_inline("0x0002:C9,00_01").unwrap();
+        assert_eq!(patch.apply(&[0xFF; 4]).unwrap(), vec![0xFF, 0xFF, 0xC9, 0x00, 0x01]);
+
+        assert!(Patch::parse_inline("2:C").is_none());
+        assert!(Patch::parse_inline("2:").is_none());
+        assert!(Patch::parse_inline("2:XY").is_none());
+        assert!(Patch::parse_inline("C9").is_none());
+        assert!(Patch::parse_inline("FFFF:C9").is_some());
+        assert!(Patch::parse_inline("FFFF:C900").is_none());
+        assert!(Patch::parse_inline("FFFFFFFFFFFFFFFF:00").is_none());
+        assert!(Patch::parse_inline("40000000000:00").is_none());
+    }
+
+    #[test]
+    fn patches_check_the_expected_checksum() {
+        let image = [0x01, 0x02];
+        let patches = [Patch::parse_inline("0:FF").unwrap()];
+
+        let crc = checksum::crc32(&image);
+        assert_eq!(apply_patches(&image, &patches, Some(crc)).unwrap(), vec![0xFF, 0x02]);
+        assert!(matches!(apply_patches(&image, &patches, Some(crc ^ 1)), Err(EmulatorError::PatchChecksum(..))));
+    }
+}
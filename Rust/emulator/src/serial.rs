@@ -0,0 +1,251 @@
+/*
+Serial boards of the Altair 8800 that connect the terminal to the CPU
+
+88-SIO: one channel with the status at the base port and the data at the base port + 1. The status bits are active
+low:
+    bit 0   Input device ready, 0 when a received byte is waiting
+    bit 7   Output device ready, 0 when a byte can be sent
+
+88-2SIO: two Motorola 6850 ACIAs with the control/status register at the even and the data at the odd ports:
+    bit 0   Receive data register full
+    bit 1   Transmit data register empty
+
+Only the first channel of the 2SIO is connected to the terminal, the second one discards its output. The received
+bytes are queued until the program reads them and the sent ones until the front end takes them. The transmitter is
+busy while TX_BUFFER_SIZE sent bytes wait for the front end, e.g. when no client is connected to the serial link.
+
+Without a baud rate the bytes move as fast as the program polls. With one, each byte takes 10 bit times (start bit, 8
+data bits and stop bit) to arrive or to be sent, and the status bits stay clear in between like on the real boards.
+*/
+
+use std::any::Any;
+use std::collections::VecDeque;
+
+use crate::devices::Device;
+use crate::errors::EmulatorError;
+use crate::snapshot::{StateReader, StateWriter};
+
+pub const SIO_PORT: u8 = 0x00;
+pub const TWO_SIO_PORT: u8 = 0x10;
+
+// Sent bytes that may wait for the front end before the transmitter is busy
+pub const TX_BUFFER_SIZE: usize = 4096;
+
+// Bits per byte on the line with one start and one stop bit
+const BITS_PER_BYTE: u64 = 10;
+
+const SIO_INPUT_NOT_READY: u8 = 0x01;
+const SIO_OUTPUT_NOT_READY: u8 = 0x80;
+const ACIA_RECEIVE_FULL: u8 = 0x01;
+const ACIA_TRANSMIT_EMPTY: u8 = 0x02;
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SerialKind {
+    Sio,
+    TwoSio,
+}
+
+impl SerialKind {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "sio" => Some(SerialKind::Sio),
+            "2sio" => Some(SerialKind::TwoSio),
+            _ => None,
+        }
+    }
+
+    // Port of the status register of the first channel
+    pub fn base_port(self) -> u8 {
+        match self {
+            SerialKind::Sio => SIO_PORT,
+            SerialKind::TwoSio => TWO_SIO_PORT,
+        }
+    }
+}
+
+
+pub struct SerialCard {
+    kind: SerialKind,
+    base_port: u8,
+
+    rx: VecDeque<u8>,
+    tx: Vec<u8>,
+
+    // Last received byte, the 6850 keeps returning it from the data register
+    data: u8,
+
+    // Set when the program found neither a received byte nor a busy transmitter, and when it sent a byte. A program
+    // that polls without sending anything waits for input.
+    idle: bool,
+    sent: bool,
+
+    // CPU cycles that a byte takes on the line, 0 without baud rate pacing
+    byte_cycles: u64,
+
+    // Cycle when the next received byte arrives and when the transmitter has sent the last byte
+    rx_ready_at: u64,
+    tx_ready_at: u64,
+}
+
+impl SerialCard {
+    pub fn new(kind: SerialKind) -> Self {
+        SerialCard {
+            kind,
+            base_port: kind.base_port(),
+
+            rx: VecDeque::new(),
+            tx: Vec::new(),
+
+            data: 0x00,
+            idle: false,
+            sent: false,
+
+            byte_cycles: 0,
+            rx_ready_at: 0,
+            tx_ready_at: 0,
+        }
+    }
+
+    // Pace the bytes at the baud rate for a CPU running at clock_hz, None moves them as fast as possible
+    pub fn set_baud(&mut self, baud: Option<u32>, clock_hz: u64) {
+        self.byte_cycles = baud.map_or(0, |baud| clock_hz * BITS_PER_BYTE / baud.max(1) as u64);
+    }
+
+    // Move the board to other ports than the default ones of its kind
+    pub fn with_base_port(mut self, port: u8) -> Self {
+        self.base_port = port;
+        self
+    }
+
+    pub fn kind(&self) -> SerialKind {
+        self.kind
+    }
+
+    // Queue bytes received from the terminal
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.rx.extend(bytes);
+    }
+
+    // Return and clear the bytes sent to the terminal
+    pub fn take_sent(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.tx)
+    }
+
+    // Bytes received but not read by the program yet
+    pub fn pending(&self) -> usize {
+        self.rx.len()
+    }
+
+    // Return whether the program waited for input since the last call, and clear the flags
+    pub fn take_idle(&mut self) -> bool {
+        let sent = std::mem::replace(&mut self.sent, false);
+        std::mem::replace(&mut self.idle, false) && !sent
+    }
+
+    fn is_received(&self, cycles: u64) -> bool {
+        !self.rx.is_empty() && cycles >= self.rx_ready_at
+    }
+
+    fn is_transmit_ready(&self, cycles: u64) -> bool {
+        self.tx.len() < TX_BUFFER_SIZE && cycles >= self.tx_ready_at
+    }
+
+    fn status(&mut self, cycles: u64) -> u8 {
+        let received = self.is_received(cycles);
+        let transmit_ready = self.is_transmit_ready(cycles);
+        self.idle |= self.rx.is_empty() && transmit_ready;
+
+        match self.kind {
+            SerialKind::Sio => {
+                (if received { 0x00 } else { SIO_INPUT_NOT_READY })
+                    | if transmit_ready { 0x00 } else { SIO_OUTPUT_NOT_READY }
+            },
+            SerialKind::TwoSio => {
+                (if received { ACIA_RECEIVE_FULL } else { 0x00 })
+                    | if transmit_ready { ACIA_TRANSMIT_EMPTY } else { 0x00 }
+            },
+        }
+    }
+
+    // The next byte arrives a byte time after the current one was read
+    fn read_data(&mut self, cycles: u64) -> u8 {
+        if self.is_received(cycles) {
+            if let Some(byte) = self.rx.pop_front() {
+                self.data = byte;
+                self.rx_ready_at = cycles + self.byte_cycles;
+            }
+        }
+
+        self.data
+    }
+
+    // Programs that don't check the status lose no bytes, they only go out later
+    fn send(&mut self, val: u8, cycles: u64) {
+        self.tx.push(val);
+        self.sent = true;
+        self.tx_ready_at = cycles.max(self.tx_ready_at) + self.byte_cycles;
+    }
+}
+
+impl Device for SerialCard {
+    fn name(&self) -> &str {
+        match self.kind {
+            SerialKind::Sio => "sio",
+            SerialKind::TwoSio => "2sio",
+        }
+    }
+
+    fn input(&mut self, port: u8, cycles: u64) -> Option<u8> {
+        match (self.kind, port.wrapping_sub(self.base_port)) {
+            (_, 0) => Some(self.status(cycles)),
+            (_, 1) => Some(self.read_data(cycles)),
+
+            // Second 2SIO channel without a terminal
+            (SerialKind::TwoSio, 2) => Some(ACIA_TRANSMIT_EMPTY),
+            (SerialKind::TwoSio, 3) => Some(0x00),
+            _ => None,
+        }
+    }
+
+    fn output(&mut self, port: u8, val: u8, cycles: u64) -> bool {
+        match (self.kind, port.wrapping_sub(self.base_port)) {
+            // Terminals use 7 bits, some programs send with the parity bit set
+            (_, 1) => self.send(val & 0x7F, cycles),
+
+            // 6850 control registers, the line settings don't matter here
+            (SerialKind::TwoSio, 0 | 2 | 3) => {},
+            (SerialKind::Sio, 0) => {},
+            _ => return false,
+        }
+
+        true
+    }
+
+    // The sent bytes are already with the front end, only the received ones are saved
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.data);
+        writer.write_u32(self.rx.len() as u32);
+
+        for byte in self.rx.iter() {
+            writer.write_u8(*byte);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.data = reader.read_u8()?;
+        self.rx.clear();
+        self.rx_ready_at = 0;
+        self.tx_ready_at = 0;
+
+        for _ in 0..reader.read_u32()? {
+            self.rx.push_back(reader.read_u8()?);
+        }
+
+        Ok(())
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -0,0 +1,287 @@
+/*
+Save states - Serialize the whole machine state (CPU, memory and attached devices) into a versioned binary file
+
+File layout (all values little endian):
+    8 bytes     Magic "I8080SAV"
+    2 bytes     Format version
+    N bytes     CPU state, see Intel8080::save_state()
+*/
+
+use std::fs::{read, write};
+use std::path::Path;
+
+use crate::emulator::Intel8080;
+use crate::errors::EmulatorError;
+
+const MAGIC: &[u8; 8] = b"I8080SAV";
+pub const VERSION: u16 = 2;
+
+// Version 1 lacks the state of the CPU variant, which is read only when present
+const OLDEST_VERSION: u16 = 1;
+
+
+// Helper for building the binary save state
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { buf: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.buf.push(val as u8);
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    // Length prefixed byte blob
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        self.write_bytes(s.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+// Helper for reading the binary save state, every read fails if the data ends too early
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], EmulatorError> {
+        if self.pos + len > self.data.len() {
+            return Err(EmulatorError::SaveStateInvalid(format!("unexpected end of data at offset {}", self.pos)));
+        }
+
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, EmulatorError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, EmulatorError> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, EmulatorError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, EmulatorError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, EmulatorError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], EmulatorError> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn read_str(&mut self) -> Result<String, EmulatorError> {
+        let bytes = self.read_bytes()?;
+        String::from_utf8(bytes.to_vec())
+            .map_err(|_| EmulatorError::SaveStateInvalid(String::from("string is not valid UTF-8")))
+    }
+}
+
+
+// Serialize the CPU state with the file header into bytes
+pub fn save_state(cpu: &Intel8080) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+
+    for byte in MAGIC {
+        writer.write_u8(*byte);
+    }
+
+    writer.write_u16(VERSION);
+    cpu.save_state(&mut writer);
+
+    writer.into_bytes()
+}
+
+// Restore the CPU state from bytes created by save_state()
+pub fn load_state(cpu: &mut Intel8080, data: &[u8]) -> Result<(), EmulatorError> {
+    let mut reader = StateReader::new(data);
+
+    for byte in MAGIC {
+        if reader.read_u8()? != *byte {
+            return Err(EmulatorError::SaveStateInvalid(String::from("file is not a save state")));
+        }
+    }
+
+    let version = reader.read_u16()?;
+    if !(OLDEST_VERSION..=VERSION).contains(&version) {
+        return Err(EmulatorError::SaveStateVersion(version));
+    }
+
+    cpu.load_state(&mut reader)
+}
+
+pub fn save_state_to_file(cpu: &Intel8080, path: &Path) -> Result<(), EmulatorError> {
+    write(path, save_state(cpu))?;
+    Ok(())
+}
+
+pub fn load_state_from_file(cpu: &mut Intel8080, path: &Path) -> Result<(), EmulatorError> {
+    let data = read(path)?;
+    load_state(cpu, &data)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::{Device, ShiftRegister};
+    use crate::emulator::CpuModel;
+
+    // LXI H,2000h; INR M; MOV A,M; OUT 4; JMP 0003h
+    const PROGRAM: [u8; 10] = [0x21, 0x00, 0x20, 0x34, 0x7e, 0xd3, 0x04, 0xc3, 0x03, 0x00];
+
+    fn machine(model: CpuModel) -> Intel8080 {
+        let mut cpu = Intel8080::new();
+        cpu.set_model(model);
+        cpu.load_rom(&PROGRAM);
+        cpu.attach_device(Box::new(ShiftRegister::new(2, 4, 3)));
+        cpu
+    }
+
+    fn run_steps(cpu: &mut Intel8080, steps: usize) {
+        for _ in 0..steps {
+            cpu.step();
+        }
+    }
+
+    // Save state of an 8080 with the shift register, with the given memory and device state
+    fn raw_state(mem: &[u8], device_state: &[u8]) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        for byte in MAGIC {
+            writer.write_u8(*byte);
+        }
+
+        writer.write_u16(VERSION);
+        for _ in 0..8 {
+            writer.write_u8(0);
+        }
+
+        writer.write_u16(0);
+        writer.write_u16(0);
+        writer.write_bool(false);
+        writer.write_bool(false);
+        writer.write_u64(0);
+        writer.write_bytes(mem);
+
+        writer.write_u32(1);
+        writer.write_str("shift_register");
+        writer.write_bytes(device_state);
+
+        writer.write_u8(CpuModel::I8080 as u8);
+        writer.write_bytes(&[]);
+        writer.into_bytes()
+    }
+
+    #[test]
+    fn load_restores_the_saved_state() {
+        for model in [CpuModel::I8080, CpuModel::I8085, CpuModel::Z80] {
+            let mut cpu = machine(model);
+            run_steps(&mut cpu, 20);
+
+            let state = save_state(&cpu);
+            let (registers, mem) = (cpu.dump_registers(), cpu.memory().to_vec());
+            let mut expected = machine(model);
+            run_steps(&mut expected, 40);
+
+            run_steps(&mut cpu, 7);
+            load_state(&mut cpu, &state).unwrap();
+            assert_eq!(cpu.dump_registers(), registers);
+            assert_eq!(cpu.memory(), &mem[..]);
+
+            // The device state is restored too, so the machine continues like one that was never interrupted
+            run_steps(&mut cpu, 20);
+            assert_eq!(cpu.dump_registers(), expected.dump_registers());
+            let result = expected.device_mut::<ShiftRegister>().unwrap().input(3, 0);
+            assert_eq!(cpu.device_mut::<ShiftRegister>().unwrap().input(3, 0), result);
+        }
+    }
+
+    #[test]
+    fn raw_state_is_valid() {
+        let mut cpu = machine(CpuModel::I8080);
+        load_state(&mut cpu, &raw_state(&[0; 0x10000], &[0; 3])).unwrap();
+    }
+
+    #[test]
+    fn load_rejects_a_wrong_memory_size() {
+        let mut cpu = machine(CpuModel::I8080);
+        let result = load_state(&mut cpu, &raw_state(&[0; 0x100], &[0; 3]));
+        assert!(matches!(result, Err(EmulatorError::SaveStateInvalid(_))));
+    }
+
+    #[test]
+    fn load_rejects_extra_device_data() {
+        let mut cpu = machine(CpuModel::I8080);
+        let result = load_state(&mut cpu, &raw_state(&[0; 0x10000], &[0; 4]));
+        assert!(matches!(result, Err(EmulatorError::SaveStateInvalid(_))));
+    }
+
+    #[test]
+    fn load_rejects_another_model_and_version() {
+        let state = save_state(&machine(CpuModel::Z80));
+        let result = load_state(&mut machine(CpuModel::I8080), &state);
+        assert!(matches!(result, Err(EmulatorError::SaveStateInvalid(_))));
+
+        let mut state = save_state(&machine(CpuModel::I8080));
+        state[8] = VERSION as u8 + 1;
+        let result = load_state(&mut machine(CpuModel::I8080), &state);
+        assert!(matches!(result, Err(EmulatorError::SaveStateVersion(_))));
+    }
+}
@@ -0,0 +1,333 @@
+/*
+Space Invaders sound events
+
+The board has discrete analog sound circuits that the game triggers through the bits of OUT 3 and OUT 5:
+    OUT 3 bit 0     UFO (repeats while the bit is set)
+    OUT 3 bit 1     Shot
+    OUT 3 bit 2     Player death
+    OUT 3 bit 3     Invader hit
+    OUT 3 bit 4     Extended play
+    OUT 5 bit 0-3   Fleet movement tones 1-4
+    OUT 5 bit 4     UFO hit
+
+The writes are decoded into named sound events with cycle timestamps, which can be written as a log or rendered
+into a WAV file offline. The WAV uses the samples from a directory (0.wav - 9.wav in the order of the Sound enum,
+the same naming that other emulators use) or simple synthesized tones when no sample is found.
+*/
+
+use std::any::Any;
+use std::f32::consts::PI;
+use std::fs::{read, write, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::devices::Device;
+use crate::errors::EmulatorError;
+use crate::snapshot::{StateReader, StateWriter};
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Sound {
+    Ufo,
+    Shot,
+    PlayerDeath,
+    InvaderHit,
+    Fleet1,
+    Fleet2,
+    Fleet3,
+    Fleet4,
+    UfoHit,
+    ExtendedPlay,
+}
+
+const SOUNDS: [Sound; 10] = [
+    Sound::Ufo, Sound::Shot, Sound::PlayerDeath, Sound::InvaderHit, Sound::Fleet1, Sound::Fleet2, Sound::Fleet3,
+    Sound::Fleet4, Sound::UfoHit, Sound::ExtendedPlay,
+];
+
+// Port and bit of each sound
+const SOUND_BITS: [(u8, u8, Sound); 10] = [
+    (3, 0, Sound::Ufo),
+    (3, 1, Sound::Shot),
+    (3, 2, Sound::PlayerDeath),
+    (3, 3, Sound::InvaderHit),
+    (3, 4, Sound::ExtendedPlay),
+    (5, 0, Sound::Fleet1),
+    (5, 1, Sound::Fleet2),
+    (5, 2, Sound::Fleet3),
+    (5, 3, Sound::Fleet4),
+    (5, 4, Sound::UfoHit),
+];
+
+impl Sound {
+    fn index(self) -> usize {
+        SOUNDS.iter().position(|sound| *sound == self).unwrap_or(0)
+    }
+
+    // Sound that keeps playing for as long as its bit is set, instead of playing once
+    fn is_looping(self) -> bool {
+        self == Sound::Ufo
+    }
+}
+
+
+// Sound starting (bit set) or stopping (bit cleared)
+#[derive(Clone, Copy)]
+pub struct SoundEvent {
+    pub cycles: u64,
+    pub sound: Sound,
+    pub on: bool,
+}
+
+// Records the sound port writes of the Space Invaders board
+pub struct SoundBoard {
+    port3: u8,
+    port5: u8,
+    events: Vec<SoundEvent>,
+}
+
+impl SoundBoard {
+    pub fn new() -> Self {
+        SoundBoard {
+            port3: 0x00,
+            port5: 0x00,
+            events: Vec::new(),
+        }
+    }
+
+    // Last values written to OUT 3 and OUT 5, some boards also wire video bits to them
+    pub fn ports(&self) -> (u8, u8) {
+        (self.port3, self.port5)
+    }
+
+    pub fn events(&self) -> &[SoundEvent] {
+        &self.events
+    }
+
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+
+    // Write the events as text, one per line with the cycle count and the time from the start at the clock speed
+    pub fn write_log(&self, path: &Path, clock_hz: u64) -> Result<(), EmulatorError> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        writeln!(out, "# Cycle       Time (s)    Sound           State")?;
+        for event in self.events.iter() {
+            writeln!(
+                out,
+                "{:<12}  {:<10.4}  {:<14}  {}",
+                event.cycles, event.cycles as f64 / clock_hz as f64, format!("{:?}", event.sound),
+                if event.on { "on" } else { "off" },
+            )?;
+        }
+
+        out.flush()?;
+        Ok(())
+    }
+
+    // Render the events into a 16-bit mono WAV file, using the samples from the directory when available
+    pub fn write_wav(&self, path: &Path, clock_hz: u64, samples_dir: Option<&Path>) -> Result<(), EmulatorError> {
+        let mut samples: Vec<Vec<f32>> = Vec::new();
+
+        for sound in SOUNDS {
+            let sample = match samples_dir.map(|dir| dir.join(format!("{}.wav", sound.index()))) {
+                Some(file) if file.exists() => read_wav(&file)?,
+                _ => synthesize(sound),
+            };
+
+            samples.push(sample);
+        }
+
+        let to_sample = |cycles: u64| (cycles as f64 * SAMPLE_RATE as f64 / clock_hz as f64) as usize;
+        let end_cycles = self.events.last().map_or(0, |event| event.cycles);
+        let mut mix: Vec<f32> = vec![0.0; to_sample(end_cycles) + SAMPLE_RATE as usize];
+
+        for (i, event) in self.events.iter().enumerate().filter(|(_, event)| event.on) {
+            let sample = &samples[event.sound.index()];
+            let start = to_sample(event.cycles);
+
+            // Looping sounds play until their bit is cleared, the others play their sample once
+            let len = if event.sound.is_looping() {
+                let stop = self.events[i + 1..].iter().find(|other| other.sound == event.sound && !other.on);
+                stop.map_or(mix.len(), |stop| to_sample(stop.cycles)) - start
+            } else {
+                sample.len()
+            };
+
+            for j in 0..len {
+                if start + j >= mix.len() || sample.is_empty() {
+                    break;
+                }
+
+                mix[start + j] += sample[j % sample.len()];
+            }
+        }
+
+        let pcm: Vec<i16> = mix.iter().map(|val| (val.clamp(-1.0, 1.0) * 0.8 * i16::MAX as f32) as i16).collect();
+        write(path, wav_bytes(&pcm))?;
+        Ok(())
+    }
+}
+
+impl Default for SoundBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Device for SoundBoard {
+    fn name(&self) -> &str {
+        "sound"
+    }
+
+    fn input(&mut self, _port: u8, _cycles: u64) -> Option<u8> {
+        None
+    }
+
+    fn output(&mut self, port: u8, val: u8, cycles: u64) -> bool {
+        let old = match port {
+            3 => std::mem::replace(&mut self.port3, val),
+            5 => std::mem::replace(&mut self.port5, val),
+            _ => return false,
+        };
+
+        // Only the changed bits start or stop a sound
+        for (sound_port, bit, sound) in SOUND_BITS {
+            if sound_port == port && (old ^ val) >> bit & 0x01 == 1 {
+                self.events.push(SoundEvent { cycles, sound, on: val >> bit & 0x01 == 1 });
+            }
+        }
+
+        true
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.port3);
+        writer.write_u8(self.port5);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), EmulatorError> {
+        self.port3 = reader.read_u8()?;
+        self.port5 = reader.read_u8()?;
+        Ok(())
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+
+// Simple stand-ins for the analog sound circuits
+fn synthesize(sound: Sound) -> Vec<f32> {
+    let rate = SAMPLE_RATE as f32;
+    let mut noise: u32 = 0x1234_5678;
+
+    // Cheap white noise from a xorshift generator
+    let mut next_noise = move || {
+        noise ^= noise << 13;
+        noise ^= noise >> 17;
+        noise ^= noise << 5;
+        (noise as f32 / u32::MAX as f32) * 2.0 - 1.0
+    };
+
+    let square = |freq: f32, t: f32| if (freq * t).fract() < 0.5 { 0.5 } else { -0.5 };
+
+    let (duration, mut generate): (f32, Box<dyn FnMut(f32) -> f32>) = match sound {
+        // Warbling tone, looped for as long as the UFO is on the screen
+        Sound::Ufo => (0.1, Box::new(|t| (2.0 * PI * (700.0 + 100.0 * (2.0 * PI * 10.0 * t).sin()) * t).sin() * 0.3)),
+        Sound::Shot => (0.3, Box::new(move |t| {
+            next_noise() * (1.0 - t / 0.3) * 0.4 + square(1200.0 - 3000.0 * t, t) * 0.1
+        })),
+        Sound::PlayerDeath => (1.0, Box::new(move |t| next_noise() * (1.0 - t) * 0.6)),
+        Sound::InvaderHit => (0.2, Box::new(move |t| next_noise() * (1.0 - t / 0.2) * 0.5)),
+        Sound::Fleet1 => (0.08, Box::new(move |t| square(110.0, t) * 0.6)),
+        Sound::Fleet2 => (0.08, Box::new(move |t| square(98.0, t) * 0.6)),
+        Sound::Fleet3 => (0.08, Box::new(move |t| square(87.0, t) * 0.6)),
+        Sound::Fleet4 => (0.08, Box::new(move |t| square(82.0, t) * 0.6)),
+        Sound::UfoHit => (0.8, Box::new(move |t| square(1000.0 - 800.0 * t, t) * (1.0 - t / 0.8) * 0.4)),
+        Sound::ExtendedPlay => (0.5, Box::new(move |t| square(1500.0, t) * 0.3)),
+    };
+
+    (0..(duration * rate) as usize).map(|i| generate(i as f32 / rate)).collect()
+}
+
+// 16-bit mono PCM in a RIFF WAVE container
+fn wav_bytes(pcm: &[i16]) -> Vec<u8> {
+    let data_len = (pcm.len() * 2) as u32;
+    let mut bytes: Vec<u8> = Vec::with_capacity(44 + data_len as usize);
+
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());                  // PCM
+    bytes.extend_from_slice(&1u16.to_le_bytes());                  // Mono
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());     // Bytes per second
+    bytes.extend_from_slice(&2u16.to_le_bytes());                  // Bytes per frame
+    bytes.extend_from_slice(&16u16.to_le_bytes());                 // Bits per sample
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in pcm {
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    bytes
+}
+
+// Read an 8 or 16-bit PCM WAV file as mono samples in -1.0..1.0, resampled to SAMPLE_RATE
+pub fn read_wav(path: &Path) -> Result<Vec<f32>, EmulatorError> {
+    let bytes = read(path)?;
+    let invalid = || EmulatorError::WavInvalid(path.display().to_string());
+
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid());
+    }
+
+    let (mut channels, mut rate, mut bits) = (0u16, 0u32, 0u16);
+    let mut pos = 12;
+
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32::from_le_bytes([bytes[pos + 4], bytes[pos + 5], bytes[pos + 6], bytes[pos + 7]]) as usize;
+        let body = bytes.get(pos + 8..pos + 8 + len).ok_or_else(invalid)?;
+
+        if id == b"fmt " && len >= 16 {
+            if u16::from_le_bytes([body[0], body[1]]) != 1 {
+                return Err(invalid());
+            }
+
+            channels = u16::from_le_bytes([body[2], body[3]]);
+            rate = u32::from_le_bytes([body[4], body[5], body[6], body[7]]);
+            bits = u16::from_le_bytes([body[14], body[15]]);
+        } else if id == b"data" {
+            if channels == 0 || rate == 0 {
+                return Err(invalid());
+            }
+
+            let frames: Vec<f32> = match bits {
+                8 => body.chunks_exact(channels as usize).map(|frame| (frame[0] as f32 - 128.0) / 128.0).collect(),
+                16 => body.chunks_exact(2 * channels as usize)
+                    .map(|frame| i16::from_le_bytes([frame[0], frame[1]]) as f32 / 32768.0)
+                    .collect(),
+                _ => return Err(invalid()),
+            };
+
+            // Nearest neighbour resampling is good enough for these sounds
+            let out_len = (frames.len() as u64 * SAMPLE_RATE as u64 / rate as u64) as usize;
+            return Ok((0..out_len).map(|i| frames[(i as u64 * rate as u64 / SAMPLE_RATE as u64) as usize]).collect());
+        }
+
+        // Chunks are padded to an even length
+        pos += 8 + len + (len & 1);
+    }
+
+    Err(invalid())
+}
@@ -0,0 +1,269 @@
+/*
+Terminal front end for Space Invaders and the boards of its family with known controls, for playing over SSH without
+a graphical display
+
+The screen is drawn with ANSI colours and either Unicode braille characters (2x4 pixels per character, 112x64
+characters) or half blocks (1x2 pixels per character, 224x128 characters). The keyboard is read in raw mode:
+    c / 5           Insert coin
+    1 / 2           1 or 2 player start
+    a / Left        Move left
+    d / Right       Move right
+    Space / w       Fire
+    Tab             Toggle the turbo mode
+    q / Esc / ^C    Quit
+
+The game runs in real time at the clock of the machine, the measured speed can be shown in the title of the terminal.
+
+Terminals only report key presses (and their auto repeat), never releases, so a key is held down for a few frames
+after each press.
+*/
+
+use std::io::{stdin, stdout, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+use emulator::errors::EmulatorError;
+use emulator::invaders::Invaders;
+use emulator::machine::Machine;
+use emulator::midway::Control;
+use emulator::movie::Movie;
+use emulator::nvram::Nvram;
+use emulator::throttle::Throttle;
+use emulator::video::{self, Framebuffer};
+
+// How many frames a key stays pressed after the terminal reported it
+const HOLD_FRAMES: u32 = 6;
+
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    Braille,
+    HalfBlock,
+}
+
+// The controls are wired to the input latches as the board describes
+#[derive(Clone, Copy, PartialEq)]
+enum Key {
+    Control(Control),
+    Turbo,
+    Quit,
+}
+
+// Puts the terminal into raw mode and restores the original settings when dropped, also on panics
+pub struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    pub fn enable() -> Result<Self, EmulatorError> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+
+        Ok(RawMode { saved: saved.trim().to_string() })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        stty(&[&self.saved]).ok();
+        print!("\x1b[0m\x1b[?25h\r\n");
+        stdout().flush().ok();
+    }
+}
+
+// Run stty on the terminal connected to stdin
+fn stty(args: &[&str]) -> Result<String, EmulatorError> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+
+    if !output.status.success() {
+        return Err(EmulatorError::TerminalUnavailable);
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// Read stdin on its own thread, so that the emulation never blocks waiting for a key
+pub fn spawn_input_reader() -> Receiver<u8> {
+    let (sender, receiver) = channel();
+
+    thread::spawn(move || {
+        let mut byte = [0u8; 1];
+
+        while let Ok(1) = stdin().read(&mut byte) {
+            if sender.send(byte[0]).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+// Turn the bytes from the terminal into keys, arrow keys arrive as the escape sequences "ESC [ C" and "ESC [ D"
+fn read_keys(input: &Receiver<u8>) -> Vec<Key> {
+    let bytes: Vec<u8> = input.try_iter().collect();
+    let mut keys = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let key = match bytes[i] {
+            0x1b if bytes.get(i + 1) == Some(&b'[') => {
+                i += 2;
+                match bytes.get(i) {
+                    Some(b'D') => Some(Key::Control(Control::Left)),
+                    Some(b'C') => Some(Key::Control(Control::Right)),
+                    _ => None,
+                }
+            },
+            0x1b | 0x03 | b'q' => Some(Key::Quit),
+            b'c' | b'5' => Some(Key::Control(Control::Coin)),
+            b'1' => Some(Key::Control(Control::P1Start)),
+            b'2' => Some(Key::Control(Control::P2Start)),
+            b'a' => Some(Key::Control(Control::Left)),
+            b'd' => Some(Key::Control(Control::Right)),
+            b' ' | b'w' => Some(Key::Control(Control::Fire)),
+            b'\t' => Some(Key::Turbo),
+            _ => None,
+        };
+
+        if let Some(key) = key {
+            keys.push(key);
+        }
+
+        i += 1;
+    }
+
+    keys
+}
+
+fn colour_code(colour: u32) -> String {
+    format!("\x1b[38;2;{};{};{}m", (colour >> 16) & 0xFF, (colour >> 8) & 0xFF, colour & 0xFF)
+}
+
+/*
+Braille characters have 8 dots in a 2x4 grid, each dot is one bit of the code point offset from U+2800:
+    0x01  0x08
+    0x02  0x10
+    0x04  0x20
+    0x40  0x80
+*/
+fn render_braille(frame: &Framebuffer, out: &mut String) {
+    const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+    let mut current_colour = video::BLACK;
+
+    for row in (0..frame.height).step_by(4) {
+        for col in (0..frame.width).step_by(2) {
+            let mut bits: u32 = 0;
+            let mut colour = video::BLACK;
+
+            for (dy, dots) in DOTS.iter().enumerate() {
+                for (dx, dot) in dots.iter().enumerate() {
+                    let pixel = frame.get(col + dx, row + dy);
+                    if pixel != video::BLACK {
+                        bits |= dot;
+                        colour = pixel;
+                    }
+                }
+            }
+
+            if bits != 0 && colour != current_colour {
+                out.push_str(&colour_code(colour));
+                current_colour = colour;
+            }
+
+            out.push(char::from_u32(0x2800 + bits).unwrap_or(' '));
+        }
+
+        out.push_str("\r\n");
+    }
+}
+
+// Upper half block with the top pixel as the foreground and the bottom pixel as the background colour
+fn render_half_blocks(frame: &Framebuffer, out: &mut String) {
+    for row in (0..frame.height).step_by(2) {
+        for col in 0..frame.width {
+            let (top, bottom) = (frame.get(col, row), frame.get(col, row + 1));
+
+            out.push_str(&colour_code(top));
+            out.push_str(&format!("\x1b[48;2;{};{};{}m", (bottom >> 16) & 0xFF, (bottom >> 8) & 0xFF, bottom & 0xFF));
+            out.push('\u{2580}');
+        }
+
+        out.push_str("\x1b[0m\r\n");
+    }
+}
+
+// Play the game in the terminal until quit or the frame limit is reached, throttled to the clock of the CPU.
+// The inputs are recorded into the movie, or taken from it instead of the keyboard when replaying.
+pub fn run(
+    machine: &mut Invaders, mode: RenderMode, frames: Option<u64>, mut movie: Option<&mut Movie>,
+    mut nvram: Option<&mut Nvram>, throttle: &mut Throttle, show_speed: bool,
+) -> Result<(), EmulatorError> {
+    let _raw_mode = RawMode::enable()?;
+    let input = spawn_input_reader();
+
+    // Hide the cursor and clear the screen
+    print!("\x1b[?25l\x1b[2J");
+    stdout().flush()?;
+
+    // Frames left for each key to stay pressed
+    let mut held: Vec<(Key, u32)> = Vec::new();
+    let mut frame: u64 = 0;
+
+    while machine.cpu().check_undocumented().is_ok() && frames.is_none_or(|max| frame < max) {
+        for key in read_keys(&input) {
+            match key {
+                Key::Quit => return Ok(()),
+                Key::Turbo => {
+                    throttle.toggle_turbo();
+                    continue;
+                },
+                _ => (),
+            }
+
+            held.retain(|(held_key, _)| *held_key != key);
+            held.push((key, HOLD_FRAMES));
+        }
+
+        for wiring in machine.board().controls.iter() {
+            let pressed = held.iter().any(|(held_key, _)| *held_key == Key::Control(wiring.control));
+            machine.input(wiring.input).set_bit(wiring.bit, pressed != wiring.active_low);
+        }
+
+        held.iter_mut().for_each(|(_, frames_left)| *frames_left -= 1);
+        held.retain(|(_, frames_left)| *frames_left > 0);
+
+        if let Some(movie) = movie.as_mut() {
+            if !movie.update(machine.cpu_mut()) {
+                break;
+            }
+        }
+
+        machine.run_frame();
+        frame += 1;
+
+        if let Some(nvram) = nvram.as_mut() {
+            nvram.update(machine.cpu_mut());
+        }
+
+        let screen = machine.render(true);
+        let mut out = String::from("\x1b[H");
+
+        match mode {
+            RenderMode::Braille => render_braille(&screen, &mut out),
+            RenderMode::HalfBlock => render_half_blocks(&screen, &mut out),
+        }
+
+        out.push_str("\x1b[0m");
+        stdout().write_all(out.as_bytes())?;
+        stdout().flush()?;
+
+        // Sleep until the next frame is due, the speed goes into the title of the terminal window
+        if throttle.sync(machine.cpu().get_cycles()) && show_speed {
+            print!("\x1b]2;{} {}\x07", machine.board().title, throttle.readout());
+        }
+    }
+
+    Ok(())
+}
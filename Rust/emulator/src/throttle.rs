@@ -0,0 +1,161 @@
+/*
+Real-time throttling - keeps the emulated CPU at its clock speed instead of running as fast as the host allows
+
+The front ends run the CPU a frame at a time and then sleep until the real time has caught up with the emulated time
+of the cycles run so far. If the host can't keep up the throttle doesn't try to catch up later, so that a stall
+doesn't speed the program up afterwards. In turbo mode nothing is slept, e.g. for fast forwarding.
+
+The speed is measured over windows of one second, as the cycles run per second of real time.
+
+Clock presets of the 8080 parts:
+    8080        2 MHz
+    8080a-2     2.5 MHz
+    8080a-1     3.125 MHz
+*/
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_CLOCK_HZ: u64 = 2_000_000;
+
+pub const CLOCK_PRESETS: [(&str, u64); 3] = [
+    ("8080", 2_000_000),
+    ("8080a-2", 2_500_000),
+    ("8080a-1", 3_125_000),
+];
+
+// How far the emulation may fall behind before the throttle gives up on the lost time
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+// How far the emulation may run ahead before the throttle assumes the cycle count jumped, e.g. by loading a save state
+const MAX_LEAD: Duration = Duration::from_secs(1);
+
+const SPEED_WINDOW: Duration = Duration::from_secs(1);
+
+
+// Parse a clock frequency in Hz, e.g. 3125000 or 3_125_000, or the name of a preset
+pub fn parse_clock(s: &str) -> Option<u64> {
+    if let Some((_, hz)) = CLOCK_PRESETS.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+        return Some(*hz);
+    }
+
+    s.replace('_', "").parse::<u64>().ok().filter(|hz| *hz > 0)
+}
+
+
+pub struct Throttle {
+    clock_hz: u64,
+    turbo: bool,
+
+    // Real time and cycle count that the emulated time is measured from
+    base_time: Instant,
+    base_cycles: u64,
+
+    // Start of the current speed measurement and the last measured speed
+    window_time: Instant,
+    window_cycles: u64,
+    speed_hz: Option<f64>,
+}
+
+impl Throttle {
+    // Start throttling at the clock frequency from the current cycle count of the CPU
+    pub fn new(clock_hz: u64, cycles: u64) -> Self {
+        let now = Instant::now();
+
+        Throttle {
+            clock_hz: clock_hz.max(1),
+            turbo: false,
+
+            base_time: now,
+            base_cycles: cycles,
+
+            window_time: now,
+            window_cycles: cycles,
+            speed_hz: None,
+        }
+    }
+
+    pub fn clock_hz(&self) -> u64 {
+        self.clock_hz
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    // Switch between real speed and turbo, return whether turbo is on now
+    pub fn toggle_turbo(&mut self) -> bool {
+        self.turbo = !self.turbo;
+        self.turbo
+    }
+
+    // Last measured speed in cycles per second, None during the first second
+    pub fn speed_hz(&self) -> Option<f64> {
+        self.speed_hz
+    }
+
+    // Measured speed as text, e.g. "2.000 MHz (100%)"
+    pub fn readout(&self) -> String {
+        let mode = if self.turbo { "turbo " } else { "" };
+
+        match self.speed_hz {
+            Some(hz) => format!("{mode}{:.3} MHz ({:.0}%)", hz / 1e6, hz * 100.0 / self.clock_hz as f64),
+            None => format!("{mode}measuring..."),
+        }
+    }
+
+    // Sleep until the real time has caught up with the emulated time of the cycle count. Returns true when a new
+    // speed measurement is available.
+    pub fn sync(&mut self, cycles: u64) -> bool {
+        let measured = self.measure(cycles);
+
+        // The cycle count goes back e.g. when an older save state is loaded
+        if self.turbo || cycles < self.base_cycles {
+            self.rebase(cycles);
+            return measured;
+        }
+
+        let nanos = (cycles - self.base_cycles) as u128 * 1_000_000_000 / self.clock_hz as u128;
+        let emulated = Duration::from_nanos(nanos.min(u64::MAX as u128) as u64);
+        let elapsed = self.base_time.elapsed();
+
+        if emulated > elapsed + MAX_LEAD {
+            self.rebase(cycles);
+        } else if emulated > elapsed {
+            thread::sleep(emulated - elapsed);
+        } else if elapsed - emulated > MAX_LAG {
+            self.rebase(cycles);
+        }
+
+        measured
+    }
+
+    fn rebase(&mut self, cycles: u64) {
+        self.base_time = Instant::now();
+        self.base_cycles = cycles;
+    }
+
+    fn measure(&mut self, cycles: u64) -> bool {
+        let elapsed = self.window_time.elapsed();
+
+        if cycles < self.window_cycles {
+            self.window_time = Instant::now();
+            self.window_cycles = cycles;
+            return false;
+        }
+
+        if elapsed < SPEED_WINDOW {
+            return false;
+        }
+
+        self.speed_hz = Some((cycles - self.window_cycles) as f64 / elapsed.as_secs_f64());
+        self.window_time = Instant::now();
+        self.window_cycles = cycles;
+
+        true
+    }
+}
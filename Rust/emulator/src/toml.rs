@@ -0,0 +1,518 @@
+/*
+Parser for the subset of TOML used by the machine configuration files
+
+Supported:
+    key = value             Bare, quoted and dotted keys
+    [table], [a.b]          Tables
+    [[array]]               Arrays of tables
+    "basic\n", 'literal'    Single line strings
+    42, -1, 0x1F, 0o17, 0b101, 1_000
+                            Integers
+    true, false             Booleans
+    [1, 2, 3]               Arrays, also over multiple lines
+    { a = 1, b = "x" }      Inline tables
+    # comment
+
+Floats, dates and times and multi-line strings are not supported.
+*/
+
+use std::collections::BTreeMap;
+
+use crate::errors::EmulatorError;
+
+pub type Table = BTreeMap<String, Value>;
+
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    // Name of the type for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(table) => Some(table),
+            _ => None,
+        }
+    }
+}
+
+
+// Parse a TOML document into its root table
+pub fn parse(text: &str) -> Result<Table, EmulatorError> {
+    let mut parser = Parser { chars: text.chars().collect(), pos: 0 };
+    let mut root = Table::new();
+
+    // Path of the table that the keys go into, set by the last table header
+    let mut current: Vec<String> = Vec::new();
+
+    loop {
+        parser.skip_blank_lines();
+
+        match parser.peek() {
+            None => break,
+            Some('[') => {
+                let array = parser.peek_at(1) == Some('[');
+                parser.pos += if array { 2 } else { 1 };
+
+                parser.skip_spaces();
+                current = parser.key()?;
+                parser.skip_spaces();
+
+                if !parser.eat(']') || (array && !parser.eat(']')) {
+                    return Err(parser.error("expected ']' after the table name"));
+                }
+
+                open_table(&mut root, &current, array).map_err(|msg| parser.error(&msg))?;
+            },
+            Some(_) => {
+                let key = parser.key()?;
+                parser.skip_spaces();
+
+                if !parser.eat('=') {
+                    return Err(parser.error("expected '=' after the key"));
+                }
+
+                parser.skip_spaces();
+                let value = parser.value()?;
+
+                let mut path = current.clone();
+                path.extend(key);
+                insert(&mut root, &path, value).map_err(|msg| parser.error(&msg))?;
+            },
+        }
+
+        parser.end_of_line()?;
+    }
+
+    Ok(root)
+}
+
+// Create the table of a header, or append a new table to the array of an array header
+fn open_table(root: &mut Table, path: &[String], array: bool) -> Result<(), String> {
+    let (last, parents) = path.split_last().ok_or("empty table name")?;
+    let parent = table_at(root, parents)?;
+
+    match (parent.get_mut(last), array) {
+        (None, false) => {
+            parent.insert(last.clone(), Value::Table(Table::new()));
+        },
+        (None, true) => {
+            parent.insert(last.clone(), Value::Array(vec![Value::Table(Table::new())]));
+        },
+        (Some(Value::Array(tables)), true) => tables.push(Value::Table(Table::new())),
+        (Some(Value::Table(_)), false) => return Err(format!("table '{last}' is defined twice")),
+        (Some(_), _) => return Err(format!("'{last}' is already defined as a value")),
+    }
+
+    Ok(())
+}
+
+// Return the table at the path, creating the missing ones. A path through an array of tables goes into its last
+// table.
+fn table_at<'t>(root: &'t mut Table, path: &[String]) -> Result<&'t mut Table, String> {
+    let mut table = root;
+
+    for name in path {
+        let value = table.entry(name.clone()).or_insert_with(|| Value::Table(Table::new()));
+
+        table = match value {
+            Value::Table(table) => table,
+            Value::Array(values) => match values.last_mut() {
+                Some(Value::Table(table)) => table,
+                _ => return Err(format!("'{name}' is not a table")),
+            },
+            _ => return Err(format!("'{name}' is not a table")),
+        };
+    }
+
+    Ok(table)
+}
+
+fn insert(root: &mut Table, path: &[String], value: Value) -> Result<(), String> {
+    let (last, parents) = path.split_last().ok_or("empty key")?;
+    let table = table_at(root, parents)?;
+
+    if table.contains_key(last) {
+        return Err(format!("key '{last}' is defined twice"));
+    }
+
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            return true;
+        }
+
+        false
+    }
+
+    fn error(&self, msg: &str) -> EmulatorError {
+        let line = self.chars[..self.pos.min(self.chars.len())].iter().filter(|c| **c == '\n').count() + 1;
+        EmulatorError::ConfigInvalid(format!("line {line}: {msg}"))
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.pos += 1;
+            }
+        }
+    }
+
+    // Skip whitespace, comments and line breaks, e.g. between the values of an array
+    fn skip_blank_lines(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+
+            if !(self.eat('\n') || self.eat('\r')) {
+                break;
+            }
+        }
+    }
+
+    // Only a comment may follow a key/value pair or a table header on its line
+    fn end_of_line(&mut self) -> Result<(), EmulatorError> {
+        self.skip_spaces();
+        self.skip_comment();
+        self.eat('\r');
+
+        if self.peek().is_some() && !self.eat('\n') {
+            return Err(self.error("expected the end of the line"));
+        }
+
+        Ok(())
+    }
+
+    // Bare, quoted or dotted key
+    fn key(&mut self) -> Result<Vec<String>, EmulatorError> {
+        let mut parts = Vec::new();
+
+        loop {
+            let part = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let start = self.pos;
+                    while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                        self.pos += 1;
+                    }
+
+                    if start == self.pos {
+                        return Err(self.error("expected a key"));
+                    }
+
+                    self.chars[start..self.pos].iter().collect()
+                },
+            };
+
+            parts.push(part);
+            self.skip_spaces();
+
+            if !self.eat('.') {
+                return Ok(parts);
+            }
+
+            self.skip_spaces();
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, EmulatorError> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some('t' | 'f') => self.boolean(),
+            Some(c) if c.is_ascii_digit() || c == '+' || c == '-' => self.integer(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, EmulatorError> {
+        self.pos += 1;
+        let mut s = String::new();
+
+        loop {
+            let c = match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                },
+                Some('\\') => {
+                    self.pos += 1;
+
+                    match self.peek() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        _ => return Err(self.error("unknown escape sequence")),
+                    }
+                },
+                Some(c) => c,
+            };
+
+            s.push(c);
+            self.pos += 1;
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, EmulatorError> {
+        self.pos += 1;
+        let start = self.pos;
+
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some('\'') => {
+                    let s = self.chars[start..self.pos].iter().collect();
+                    self.pos += 1;
+                    return Ok(s);
+                },
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+
+    fn boolean(&mut self) -> Result<Value, EmulatorError> {
+        for (word, val) in [("true", true), ("false", false)] {
+            let end = self.pos + word.len();
+
+            if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(word.chars()) {
+                self.pos = end;
+                return Ok(Value::Boolean(val));
+            }
+        }
+
+        Err(self.error("expected a value"))
+    }
+
+    fn integer(&mut self) -> Result<Value, EmulatorError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-' | '.')) {
+            self.pos += 1;
+        }
+
+        let text: String = self.chars[start..self.pos].iter().filter(|c| **c != '_').collect();
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(&text)),
+        };
+
+        let parsed = match digits.get(..2) {
+            Some("0x") => i64::from_str_radix(&digits[2..], 16),
+            Some("0o") => i64::from_str_radix(&digits[2..], 8),
+            Some("0b") => i64::from_str_radix(&digits[2..], 2),
+            _ => digits.parse::<i64>(),
+        };
+
+        match parsed {
+            Ok(val) => Ok(Value::Integer(if negative { -val } else { val })),
+            Err(_) => Err(self.error(&format!("'{text}' is not an integer"))),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, EmulatorError> {
+        self.pos += 1;
+        let mut values = Vec::new();
+
+        loop {
+            self.skip_blank_lines();
+
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+
+            values.push(self.value()?);
+            self.skip_blank_lines();
+
+            if !self.eat(',') {
+                self.skip_blank_lines();
+
+                if !self.eat(']') {
+                    return Err(self.error("expected ',' or ']' in the array"));
+                }
+
+                return Ok(Value::Array(values));
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, EmulatorError> {
+        self.pos += 1;
+        let mut table = Table::new();
+
+        self.skip_spaces();
+        if self.eat('}') {
+            return Ok(Value::Table(table));
+        }
+
+        loop {
+            self.skip_spaces();
+            let key = self.key()?;
+            self.skip_spaces();
+
+            if !self.eat('=') {
+                return Err(self.error("expected '=' after the key"));
+            }
+
+            self.skip_spaces();
+            let value = self.value()?;
+            insert(&mut table, &key, value).map_err(|msg| self.error(&msg))?;
+            self.skip_spaces();
+
+            if self.eat('}') {
+                return Ok(Value::Table(table));
+            }
+
+            if !self.eat(',') {
+                return Err(self.error("expected ',' or '}' in the inline table"));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, Value)]) -> Value {
+        Value::Table(entries.iter().map(|(key, value)| (key.to_string(), value.clone())).collect())
+    }
+
+    #[test]
+    fn keys_tables_and_arrays_of_tables() {
+        let root = parse(r#"
+            # Machine
+            name = "test"
+            cpu.model = 'z80'
+
+            [memory]
+            "rom size" = 0x2000
+
+            [[device]]
+            ports = [1, 2,
+                     3]  # over two lines
+
+            [[device]]
+            port = { in = 0o17, out = 0b101 }
+            enabled = false
+        "#).unwrap();
+
+        let expected = table(&[
+            ("name", Value::String(String::from("test"))),
+            ("cpu", table(&[("model", Value::String(String::from("z80")))])),
+            ("memory", table(&[("rom size", Value::Integer(0x2000))])),
+            ("device", Value::Array(vec![
+                table(&[("ports", Value::Array(vec![Value::Integer(1), Value::Integer(2), Value::Integer(3)]))]),
+                table(&[
+                    ("port", table(&[("in", Value::Integer(0o17)), ("out", Value::Integer(0b101))])),
+                    ("enabled", Value::Boolean(false)),
+                ]),
+            ])),
+        ]);
+
+        assert_eq!(Value::Table(root), expected);
+    }
+
+    #[test]
+    fn integers_and_strings() {
+        let root = parse("a = -1_000\nb = +7\nc = \"tab\\t\\\"q\\\"\"\nd = 'C:\\rom'\n").unwrap();
+
+        assert_eq!(root["a"].as_integer(), Some(-1000));
+        assert_eq!(root["b"].as_integer(), Some(7));
+        assert_eq!(root["c"].as_str(), Some("tab\t\"q\""));
+        assert_eq!(root["d"].as_str(), Some("C:\\rom"));
+    }
+
+    #[test]
+    fn invalid_documents_fail_with_the_line() {
+        for (text, line) in [
+            ("a = 1\na = 2", 2),
+            ("[t]\n[t]", 2),
+            ("a = 1\n[a]", 2),
+            ("a = 1.5", 1),
+            ("a = \"open", 1),
+            ("a = 1 b = 2", 1),
+            ("\n\na = [1, 2", 3),
+            ("a = \"\\q\"", 1),
+        ] {
+            match parse(text) {
+                Err(EmulatorError::ConfigInvalid(msg)) => assert!(msg.starts_with(&format!("line {line}:")), "{msg}"),
+                _ => panic!("'{text}' was parsed"),
+            }
+        }
+    }
+}
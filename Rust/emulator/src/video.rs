@@ -0,0 +1,218 @@
+/*
+Space Invaders video rendering
+
+The video RAM at 0x2400-0x3FFF is a 1 bit per pixel bitmap of 256x224 pixels, where every 32 bytes form one column
+of the screen. The monitor in the cabinet is rotated 90 degrees counter-clockwise, so the first byte of the video RAM
+is the bottom left corner of the screen and the LSB of a byte is its bottom pixel:
+    addr = 0x2400 + x * 32 + (255 - y) / 8
+    bit  = (255 - y) % 8
+
+The screen itself is black and white, the colours come from strips of cellophane glued on the screen.
+
+The Taito boards of the family (see midway.rs) have a colour PROM instead, that gives every 8 pixels wide and 8 rows
+high cell of the screen its colour:
+    prom addr = colour map * 0x400 + (addr - 0x2000) / 256 * 32 + addr % 32
+    colour    = bits 0-2 of the PROM, bit 0 red, bit 1 blue and bit 2 green
+*/
+
+use std::fs::write;
+use std::path::Path;
+
+use crate::checksum::{adler32, crc32};
+use crate::errors::EmulatorError;
+
+pub const RAM_START: usize = 0x2000;
+pub const VRAM_START: usize = 0x2400;
+pub const VRAM_END: usize = 0x3FFF;
+
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 256;
+
+pub const BLACK: u32 = 0x000000;
+pub const WHITE: u32 = 0xFFFFFF;
+pub const RED: u32 = 0xFF2020;
+pub const GREEN: u32 = 0x20FF20;
+
+// Size of one of the colour maps of a colour PROM
+pub const COLOUR_MAP_SIZE: usize = 0x400;
+
+// Horizontal band of coloured cellophane: rows y_start..=y_end between the columns x_start..=x_end
+struct OverlayBand {
+    y_start: usize,
+    y_end: usize,
+    x_start: usize,
+    x_end: usize,
+    colour: u32,
+}
+
+// Cellophane overlay of the upright cabinet: red over the UFO, green over the shields, the player and the lives
+const OVERLAY: [OverlayBand; 3] = [
+    OverlayBand { y_start: 32, y_end: 63, x_start: 0, x_end: WIDTH - 1, colour: RED },
+    OverlayBand { y_start: 184, y_end: 239, x_start: 0, x_end: WIDTH - 1, colour: GREEN },
+    OverlayBand { y_start: 240, y_end: 255, x_start: 16, x_end: 133, colour: GREEN },
+];
+
+
+// RGB framebuffer with pixels stored row by row from the top left corner as 0xRRGGBB
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![BLACK; width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, colour: u32) {
+        self.pixels[y * self.width + x] = colour;
+    }
+
+    fn rgb_rows(&self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        self.pixels.chunks(self.width).map(|row| {
+            row.iter().flat_map(|pixel| [(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]).collect()
+        })
+    }
+
+    // Binary PPM (P6), the simplest image format there is
+    pub fn to_ppm(&self) -> Vec<u8> {
+        let mut bytes = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+
+        for row in self.rgb_rows() {
+            bytes.extend_from_slice(&row);
+        }
+
+        bytes
+    }
+
+    // PNG with the image data in uncompressed deflate blocks, bigger than needed but readable by everything
+    pub fn to_png(&self) -> Vec<u8> {
+        // Every row starts with filter type 0 (None)
+        let mut raw: Vec<u8> = Vec::with_capacity((self.width * 3 + 1) * self.height);
+        for row in self.rgb_rows() {
+            raw.push(0);
+            raw.extend_from_slice(&row);
+        }
+
+        // zlib stream: header, stored deflate blocks of max 65535 bytes each and the Adler-32 of the data
+        let mut zlib: Vec<u8> = vec![0x78, 0x01];
+        let block_count = raw.len().div_ceil(0xFFFF).max(1);
+
+        for (i, block) in raw.chunks(0xFFFF).enumerate() {
+            let len = block.len() as u16;
+            zlib.push((i + 1 == block_count) as u8);
+            zlib.extend_from_slice(&len.to_le_bytes());
+            zlib.extend_from_slice(&(!len).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+
+        zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+        let mut header: Vec<u8> = Vec::new();
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);    // 8 bit depth, RGB, default compression, filter, no interlace
+
+        let mut png: Vec<u8> = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        write_png_chunk(&mut png, b"IHDR", &header);
+        write_png_chunk(&mut png, b"IDAT", &zlib);
+        write_png_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+
+    // Save as PNG or PPM depending on the file extension
+    pub fn save(&self, path: &Path) -> Result<(), EmulatorError> {
+        let bytes = match path.extension().and_then(|ext| ext.to_str()).map(|ext| ext.to_ascii_lowercase()) {
+            Some(ext) if ext == "png" => self.to_png(),
+            Some(ext) if ext == "ppm" => self.to_ppm(),
+            _ => return Err(EmulatorError::ImageFormatUnknown(path.display().to_string())),
+        };
+
+        write(path, bytes)?;
+        Ok(())
+    }
+}
+
+fn write_png_chunk(png: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(chunk_type);
+    png.extend_from_slice(data);
+
+    // CRC covers the chunk type and the data, but not the length
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Colour of the cellophane at the pixel, white where there is none
+fn overlay_colour(x: usize, y: usize) -> u32 {
+    OVERLAY.iter()
+        .find(|band| (band.y_start..=band.y_end).contains(&y) && (band.x_start..=band.x_end).contains(&x))
+        .map_or(WHITE, |band| band.colour)
+}
+
+// Convert the video RAM into a correctly oriented 224x256 framebuffer, optionally with the colour overlay
+pub fn render_invaders(mem: &[u8], overlay: bool) -> Framebuffer {
+    let mut frame = Framebuffer::new(WIDTH, HEIGHT);
+    let vram = &mem[VRAM_START..=VRAM_END];
+
+    for (i, byte) in vram.iter().enumerate() {
+        let x = i / 32;
+        let y_base = (i % 32) * 8;
+
+        for bit in 0..8 {
+            if byte >> bit & 0x01 == 1 {
+                let y = HEIGHT - 1 - (y_base + bit);
+                let colour = if overlay { overlay_colour(x, y) } else { WHITE };
+                frame.set(x, y, colour);
+            }
+        }
+    }
+
+    frame
+}
+
+// Colour of a 3 bit PROM entry
+fn prom_colour(entry: u8) -> u32 {
+    let red = if entry & 0x01 != 0 { 0xFF0000 } else { 0 };
+    let blue = if entry & 0x02 != 0 { 0x0000FF } else { 0 };
+    let green = if entry & 0x04 != 0 { 0x00FF00 } else { 0 };
+
+    red | green | blue
+}
+
+// Convert the video RAM into a 224x256 framebuffer coloured by the colour map of the PROM, some games turn the whole
+// screen red e.g. when the player is hit
+pub fn render_colour_prom(mem: &[u8], prom: &[u8], colour_map: usize, screen_red: bool) -> Framebuffer {
+    let mut frame = Framebuffer::new(WIDTH, HEIGHT);
+    let vram = &mem[VRAM_START..=VRAM_END];
+    let map = prom.get(colour_map * COLOUR_MAP_SIZE..(colour_map + 1) * COLOUR_MAP_SIZE).unwrap_or(&[]);
+
+    for (i, byte) in vram.iter().enumerate() {
+        let x = i / 32;
+        let y_base = (i % 32) * 8;
+
+        let prom_addr = (VRAM_START - RAM_START + i) / 256 * 32 + i % 32;
+        let entry = if screen_red { 0x01 } else { map.get(prom_addr).map_or(0x07, |entry| entry & 0x07) };
+        let colour = prom_colour(entry);
+
+        for bit in 0..8 {
+            if byte >> bit & 0x01 == 1 {
+                frame.set(x, HEIGHT - 1 - (y_base + bit), colour);
+            }
+        }
+    }
+
+    frame
+}